@@ -20,6 +20,7 @@ import ConfigEditor from "./components/ConfigEditor";
 import DaemonStatus from "./components/DaemonStatus";
 import DiscordPreview from "./components/DiscordPreview";
 import GettingStarted from "./components/GettingStarted";
+import ProfileSwitcher from "./components/ProfileSwitcher";
 import TemplateGallery from "./components/TemplateGallery";
 import { cn } from "./lib/utils";
 import { Config, Template, UpdateInfo, UserTemplate } from "./types";
@@ -260,6 +261,8 @@ export default function App() {
       setConfig(newConfig);
     } catch (error) {
       console.error("Failed to save config:", error);
+      alert(`Failed to save configuration: ${error}`);
+      throw error;
     }
   };
 
@@ -532,6 +535,7 @@ max_party_size = ${config.max_party_size}`;
 
         {/* Footer Status */}
         <div className="p-4 border-t border-zinc-800/50 space-y-3">
+          <ProfileSwitcher onSwitch={setConfig} />
           <DaemonStatus />
 
           {/* Update Status */}
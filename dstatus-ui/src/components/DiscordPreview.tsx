@@ -1,32 +1,62 @@
 import { useEffect, useState } from "react";
+import { parseDuration } from "../lib/utils";
 import { Config } from "../types";
 
 interface DiscordPreviewProps {
   config: Config;
 }
 
+const ActivityTimestamp = () => {
+  const [time, setTime] = useState("00:00 elapsed");
+
+  useEffect(() => {
+    let seconds = 0;
+    const interval = setInterval(() => {
+      seconds++;
+      const minutes = Math.floor(seconds / 60)
+        .toString()
+        .padStart(2, "0");
+      const secs = (seconds % 60).toString().padStart(2, "0");
+      setTime(`${minutes}:${secs} elapsed`);
+    }, 1000);
+    return () => clearInterval(interval);
+  }, []);
+
+  return <span className="text-xs text-[#b5bac1]">{time}</span>;
+};
+
+const DEFAULT_INTERVAL = 60_000;
+const MIN_INTERVAL = 15_000;
+
 export default function DiscordPreview({ config }: DiscordPreviewProps) {
+  const slides = config.rotation?.slides ?? [];
+  const [slide, setSlide] = useState(0);
+  const current = slide < slides.length ? slide : 0;
+
+  // Step through the slides as the daemon would, in order.
+  useEffect(() => {
+    if (slides.length < 2) return;
+    const interval =
+      parseDuration(config.rotation?.interval ?? "") ?? DEFAULT_INTERVAL;
+    const timer = setInterval(
+      () => setSlide((i) => (i + 1) % slides.length),
+      Math.max(interval, MIN_INTERVAL),
+    );
+    return () => clearInterval(timer);
+  }, [slides.length, config.rotation?.interval]);
+
+  // Fields a slide leaves out keep the configuration's value. The timer
+  // lives outside the slides so that it keeps counting across them.
+  const shown: Config = { ...config };
+  if (slides.length > 0) {
+    for (const [key, value] of Object.entries(slides[current])) {
+      if (value !== undefined && value !== null) {
+        Object.assign(shown, { [key]: value });
+      }
+    }
+  }
   const { details, state, large_image, large_text, small_image, small_text } =
-    config;
-
-  const ActivityTimestamp = () => {
-    const [time, setTime] = useState("00:00 elapsed");
-
-    useEffect(() => {
-      let seconds = 0;
-      const interval = setInterval(() => {
-        seconds++;
-        const minutes = Math.floor(seconds / 60)
-          .toString()
-          .padStart(2, "0");
-        const secs = (seconds % 60).toString().padStart(2, "0");
-        setTime(`${minutes}:${secs} elapsed`);
-      }, 1000);
-      return () => clearInterval(interval);
-    }, []);
-
-    return <span className="text-xs text-[#b5bac1]">{time}</span>;
-  };
+    shown;
 
   return (
     <div className="max-w-3xl">
@@ -152,9 +182,9 @@ export default function DiscordPreview({ config }: DiscordPreviewProps) {
                   </div>
 
                   {/* Buttons */}
-                  {config.buttons && config.buttons.length > 0 && (
+                  {shown.buttons && shown.buttons.length > 0 && (
                     <div className="mt-3 space-y-2">
-                      {config.buttons.map((button, index) => (
+                      {shown.buttons.map((button, index) => (
                         <button
                           key={index}
                           className="w-full bg-[#4e5058] hover:bg-[#5d6269] text-white text-sm py-2 px-3 rounded transition-all duration-200 font-medium"
@@ -165,6 +195,22 @@ export default function DiscordPreview({ config }: DiscordPreviewProps) {
                     </div>
                   )}
                 </div>
+
+                {/* Rotation */}
+                {slides.length > 1 && (
+                  <div className="mt-2 flex items-center justify-center space-x-2">
+                    {slides.map((_, index) => (
+                      <button
+                        key={index}
+                        onClick={() => setSlide(index)}
+                        title={`Slide ${index + 1} of ${slides.length}`}
+                        className={`w-2 h-2 rounded-full transition-colors ${
+                          index === current ? "bg-white" : "bg-[#4e5058]"
+                        }`}
+                      />
+                    ))}
+                  </div>
+                )}
               </div>
             )}
 
@@ -0,0 +1,85 @@
+import { invoke } from "@tauri-apps/api/tauri";
+import { Layers, Plus } from "lucide-react";
+import { useEffect, useState } from "react";
+import { cn } from "../lib/utils";
+import { Config, ProfileList } from "../types";
+
+interface ProfileSwitcherProps {
+  onSwitch: (config: Config) => void;
+}
+
+export default function ProfileSwitcher({ onSwitch }: ProfileSwitcherProps) {
+  const [profiles, setProfiles] = useState<ProfileList | null>(null);
+  const [isSwitching, setIsSwitching] = useState(false);
+
+  const loadProfiles = async () => {
+    try {
+      setProfiles(await invoke<ProfileList>("list_profiles"));
+    } catch (error) {
+      console.error("Failed to list profiles:", error);
+    }
+  };
+
+  const switchTo = async (name: string) => {
+    if (isSwitching || name === profiles?.active) return;
+
+    setIsSwitching(true);
+    try {
+      const config = await invoke<Config>("use_profile", { name });
+      onSwitch(config);
+      await loadProfiles();
+    } catch (error) {
+      console.error("Failed to switch profile:", error);
+      alert(`Failed to switch profile: ${error}`);
+    } finally {
+      setIsSwitching(false);
+    }
+  };
+
+  const createProfile = () => {
+    const name = prompt("New profile name (letters, digits, - and _):");
+    if (name) {
+      switchTo(name.trim());
+    }
+  };
+
+  useEffect(() => {
+    loadProfiles();
+  }, []);
+
+  if (!profiles) {
+    return null;
+  }
+
+  return (
+    <div className="rounded-xl bg-zinc-800/50 border border-zinc-700/50 p-3">
+      <div className="flex items-center space-x-2">
+        <Layers className="h-4 w-4 text-zinc-400" />
+        <select
+          value={profiles.active}
+          disabled={isSwitching}
+          onChange={(e) => switchTo(e.target.value)}
+          className={cn(
+            "flex-1 rounded-lg bg-zinc-900/50 border border-zinc-700/50 px-2 py-1",
+            "text-sm text-zinc-200 disabled:opacity-50"
+          )}
+          title="Active profile"
+        >
+          {profiles.profiles.map((name) => (
+            <option key={name} value={name}>
+              {name}
+            </option>
+          ))}
+        </select>
+        <button
+          onClick={createProfile}
+          disabled={isSwitching}
+          className="rounded-lg p-1.5 text-zinc-400 hover:text-white hover:bg-zinc-700/50 disabled:opacity-50"
+          title="New profile from the current configuration"
+        >
+          <Plus className="h-4 w-4" />
+        </button>
+      </div>
+    </div>
+  );
+}
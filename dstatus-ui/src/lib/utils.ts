@@ -4,3 +4,12 @@ import { twMerge } from "tailwind-merge";
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs));
 }
+
+/** Milliseconds in a duration such as `500ms`, `30s`, `5m` or `1h`; a bare
+ * number is in seconds. Mirrors `source::parse_duration`. */
+export function parseDuration(text: string): number | null {
+  const match = /^\s*(\d+)\s*(ms|s|m|h)?\s*$/.exec(text);
+  if (!match) return null;
+  const units: Record<string, number> = { ms: 1, s: 1000, m: 60_000, h: 3_600_000 };
+  return Number(match[1]) * units[match[2] ?? "s"];
+}
@@ -1,4 +1,6 @@
 export interface Config {
+  version?: number;
+  extends?: string;
   name: string;
   description: string;
   client_id: string;
@@ -13,11 +15,35 @@ export interface Config {
   buttons: Array<{ label: string; url: string }> | null;
   start_timestamp?: number;
   end_timestamp?: number;
-  party_max?: number;
   match_secret?: string;
   join_secret?: string;
   spectate_secret?: string;
   instance?: boolean;
+  rotation?: Rotation;
+  media?: Media;
+}
+
+export interface Slide {
+  details?: string;
+  state?: string;
+  large_image?: string;
+  large_text?: string;
+  small_image?: string;
+  small_text?: string;
+  buttons?: Array<{ label: string; url: string }>;
+}
+
+export interface Rotation {
+  interval?: string;
+  order?: "sequential" | "random";
+  slides: Slide[];
+}
+
+export interface Media {
+  players?: string[];
+  mpris?: boolean;
+  mpd?: string;
+  set?: Slide;
 }
 
 export type Button = {
@@ -46,3 +72,18 @@ export interface UserTemplate {
   created_at: string;
   last_used: string;
 }
+
+export interface FieldIssue {
+  field: string;
+  message: string;
+}
+
+export interface ValidationReport {
+  errors: FieldIssue[];
+  warnings: FieldIssue[];
+}
+
+export interface ProfileList {
+  profiles: string[];
+  active: string;
+}
@@ -1,61 +1,588 @@
+use crate::document;
+use crate::error::{Error, Result};
+use crate::extends;
+use crate::format::{self, Format};
+use crate::history;
+use crate::media::Media;
+use crate::migrate;
+use crate::rotation::Rotation;
+use crate::source::{self, FieldSource};
+use crate::validation;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::{self};
+use std::io::ErrorKind;
+use std::path::Path;
+use toml::{Table, Value};
+use tracing::{info, warn};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+/// A link shown under the activity.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Hash)]
 pub struct Button {
+    /// Text on the button.
+    #[schemars(length(min = 1, max = validation::BUTTON_LABEL_MAX))]
     pub label: String,
+    /// Link the button opens.
+    #[schemars(length(min = 1, max = validation::BUTTON_URL_MAX), regex(pattern = r"^https?://"))]
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default, Hash)]
+/// A dstatus configuration: the Discord application to use and the Rich
+/// Presence activity to show.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, Hash)]
 pub struct Config {
+    /// Schema version of the file. Written automatically; files without it
+    /// are treated as version 0 and migrated on load.
+    #[serde(default)]
+    #[schemars(range(max = migrate::CURRENT_VERSION))]
+    pub version: u32,
+    /// Path or URL of a base config this one overlays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Name of the configuration, used for templates.
     #[serde(default)]
     pub name: String,
+    /// Description of the configuration, used for templates.
     #[serde(default)]
     pub description: String,
+    /// Discord application ID.
+    #[serde(default)]
+    #[schemars(regex(pattern = r"^[0-9]{17,20}$"))]
     pub client_id: String,
+    /// First line of the activity.
+    #[serde(default)]
+    #[schemars(with = "Text")]
     pub details: String,
+    /// Second line of the activity.
+    #[serde(default)]
+    #[schemars(with = "Text")]
     pub state: String,
+    /// Asset key or URL of the large image.
+    #[serde(default)]
+    #[schemars(length(max = validation::IMAGE_KEY_MAX))]
     pub large_image: String,
+    /// Tooltip of the large image.
+    #[serde(default)]
+    #[schemars(with = "Text")]
     pub large_text: String,
+    /// Asset key or URL of the small image.
+    #[serde(default)]
+    #[schemars(length(max = validation::IMAGE_KEY_MAX))]
     pub small_image: String,
+    /// Tooltip of the small image.
+    #[serde(default)]
+    #[schemars(with = "Text")]
     pub small_text: String,
+    /// Current party size.
+    #[serde(default)]
+    #[schemars(range(min = 0))]
     pub party_size: i32,
+    /// Maximum party size; the party is only shown when this is set.
+    #[serde(default)]
+    #[schemars(range(min = 0))]
     pub max_party_size: i32,
+    /// Buttons shown under the activity. Discord shows at most two.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::MAX_BUTTONS))]
     pub buttons: Option<Vec<Button>>,
-    #[serde(default)]
+    /// Unix timestamp the elapsed time counts from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub start_timestamp: Option<i64>,
-    #[serde(default)]
+    /// Unix timestamp the remaining time counts down to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub end_timestamp: Option<i64>,
-    #[serde(default)]
-    pub party_max: Option<i32>,
-    #[serde(default)]
+    /// Secret for spectating or joining a match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub match_secret: Option<String>,
-    #[serde(default)]
+    /// Secret for joining the party.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub join_secret: Option<String>,
-    #[serde(default)]
+    /// Secret for spectating the game.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub spectate_secret: Option<String>,
-    #[serde(default)]
+    /// Whether the activity is an instanced game session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub instance: Option<bool>,
+    /// Activities shown in turn instead of this one's text, images and
+    /// buttons.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+    /// Shows the track playing in a media player instead, as a Listening
+    /// activity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<Media>,
+    /// Text fields filled from commands, keyed by field. Written in the
+    /// file in place of the field's text; see `source::lift`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(skip)]
+    pub sources: BTreeMap<String, FieldSource>,
+}
+
+/// Text, or a command that produces it. Only used for the schema.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(untagged)]
+enum Text {
+    Plain(#[schemars(length(max = validation::TEXT_MAX))] String),
+    Source(FieldSource),
 }
 
 impl Config {
-    pub fn save_to_file(&self, path: &str) -> io::Result<()> {
-        let toml_string = toml::to_string(self).unwrap();
-        fs::write(path, toml_string)
+    /// Writes the configuration. An existing file is edited in place so
+    /// that comments, key order and unknown keys survive; only the keys
+    /// whose values changed are rewritten. The write is atomic and the
+    /// previous version is kept in the history directory.
+    ///
+    /// A config that `extends` a base is written as an overlay: keys equal
+    /// to the base are left out so that they keep following it.
+    ///
+    /// JSON and YAML files, recognized by their extension, are rewritten
+    /// as a whole.
+    pub fn save_to_file(&self, path: &str) -> Result<()> {
+        // Whatever version was loaded, the struct now has the current shape.
+        let mut config = self.clone();
+        config.version = migrate::CURRENT_VERSION;
+        let mut new = config.to_table()?;
+        let defaults = match &config.extends {
+            Some(target) => {
+                let base = Self::from_table(extends::load(target, path)?, Path::new(target))?;
+                let base = base.to_table()?;
+                new.retain(|key, value| {
+                    key == "version" || key == "extends" || base.get(key) != Some(value)
+                });
+                // Every remaining key differs from the base and must be kept.
+                Table::new()
+            }
+            None => Config::default().to_table()?,
+        };
+        let format = Format::from_extension(path).unwrap_or(Format::Toml);
+        let full = || match &config.extends {
+            Some(_) => format::to_string(&new, format),
+            None => config.to_string_as(format),
+        };
+        if format != Format::Toml {
+            return history::save_with_history(Path::new(path), &full()?);
+        }
+
+        let toml_string = match fs::read_to_string(path) {
+            // A file that no longer parses has nothing worth keeping.
+            Ok(existing) => document::update(
+                &existing,
+                &new,
+                |key| Self::field_names().contains(&key),
+                &defaults,
+            )
+            .or_else(|_| full())?,
+            Err(e) if e.kind() == ErrorKind::NotFound => full()?,
+            Err(e) => return Err(Error::io(format!("Failed to read {:?}", path), e)),
+        };
+        history::save_with_history(Path::new(path), &toml_string)
     }
 
-    pub fn from_file(path: &str) -> io::Result<Self> {
-        let toml_string = fs::read_to_string(path)?;
-        let mut config: Config = toml::from_str(&toml_string).unwrap();
+    /// The configuration as it is written in a file.
+    pub fn to_table(&self) -> Result<Table> {
+        match Value::try_from(self).map_err(serialize_error)? {
+            Value::Table(mut table) => {
+                source::lower(&mut table);
+                Ok(table)
+            }
+            _ => Err(Error::Config(
+                "Configuration did not serialize to a table".to_string(),
+            )),
+        }
+    }
 
-        if let Some(buttons) = &mut config.buttons {
-            if buttons.len() > 2 {
-                buttons.truncate(2);
+    /// Serializes the whole configuration in `format`, keeping the field
+    /// order and writing sourced fields in place.
+    pub fn to_string_as(&self, format: Format) -> Result<String> {
+        let text = format::to_string(self, format)?;
+        if self.sources.is_empty() {
+            return Ok(text);
+        }
+        match format {
+            Format::Toml => document::update(&text, &self.to_table()?, |_| true, &Table::new()),
+            _ => {
+                let mut value = serde_json::to_value(self).map_err(serialize_error)?;
+                if let Some(fields) = value.as_object_mut() {
+                    fields.remove(source::SOURCES_KEY);
+                    for (field, source) in &self.sources {
+                        let source = serde_json::to_value(source).map_err(serialize_error)?;
+                        fields.insert(field.clone(), source);
+                    }
+                }
+                format::to_string(&value, format)
             }
         }
+    }
+
+    /// Every key `Config` understands, straight from its `Deserialize` impl.
+    pub fn field_names() -> &'static [&'static str] {
+        struct Probe<'a>(&'a mut &'static [&'static str]);
+
+        impl<'de> serde::Deserializer<'de> for Probe<'_> {
+            type Error = serde::de::value::Error;
+
+            fn deserialize_any<V: serde::de::Visitor<'de>>(
+                self,
+                _visitor: V,
+            ) -> std::result::Result<V::Value, Self::Error> {
+                Err(serde::de::Error::custom("field probe"))
+            }
+
+            fn deserialize_struct<V: serde::de::Visitor<'de>>(
+                self,
+                _name: &'static str,
+                fields: &'static [&'static str],
+                _visitor: V,
+            ) -> std::result::Result<V::Value, Self::Error> {
+                *self.0 = fields;
+                Err(serde::de::Error::custom("field probe"))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf option unit unit_struct newtype_struct seq tuple
+                tuple_struct map enum identifier ignored_any
+            }
+        }
+
+        let mut fields: &'static [&'static str] = &[];
+        let _ = Config::deserialize(Probe(&mut fields));
+        fields
+    }
+
+    /// Reads and validates a configuration. Warnings are logged; errors
+    /// are returned as `Error::Config`.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let mut config = Self::read_file(path)?;
+        for warning in config.check()?.warnings {
+            warn!("{}: {}: {}", path, warning.field, warning.message);
+        }
+        config.truncate_buttons();
+        Ok(config)
+    }
+
+    /// Reads a configuration without validating it, for editors that need
+    /// to open files which are not valid yet. Files using an older schema
+    /// are upgraded on disk, keeping the original as `<path>.v<N>.bak`.
+    pub fn read_file(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("Failed to read {:?}", path), e))?;
+        let (config, table, migration) = Self::parse_migrating(&content, Path::new(path))?;
+
+        // An overlay is migrated in memory only; writing it back would
+        // flatten the base into it.
+        if migration.is_needed() && config.extends.is_none() {
+            let backup = format!("{}.v{}.bak", path, migration.from);
+            if !Path::new(&backup).exists() {
+                fs::write(&backup, &content)
+                    .map_err(|e| Error::io(format!("Failed to back up {:?}", path), e))?;
+            }
+            let migrated = match Format::detect(path, &content) {
+                Format::Toml => document::update(&content, &table, |_| true, &Table::new())?,
+                format => format::to_string(&table, format)?,
+            };
+            history::atomic_write(Path::new(path), &migrated)?;
+            info!(
+                "Migrated {} from schema v{} to v{} (backup at {})",
+                path, migration.from, migration.to, backup
+            );
+        }
 
         Ok(config)
     }
+
+    /// Parses TOML, JSON or YAML text into a `Config`, upgrading older
+    /// schemas in memory. `origin` labels errors, anchors a relative
+    /// `extends` and decides the format by its extension; it may be a URL.
+    pub fn parse(content: &str, origin: &Path) -> Result<Self> {
+        Self::parse_migrating(content, origin).map(|(config, _, _)| config)
+    }
+
+    /// Reads a configuration from a file or an http(s) URL, without
+    /// validating it. Used by every command that imports a config.
+    pub fn from_source(source: &str) -> Result<Self> {
+        Self::parse(&read_source(source)?, Path::new(source))
+    }
+
+    /// Parses `content`, migrates it and merges it onto its `extends` base
+    /// if it has one, returning the config together with the merged raw
+    /// table and a description of what changed in `content`.
+    pub fn parse_migrating(
+        content: &str,
+        origin: &Path,
+    ) -> Result<(Self, Table, migrate::Migration)> {
+        let format = Format::detect(&origin.to_string_lossy(), content);
+        let mut table = format::parse(content, format, origin)?;
+        let extends = table.get("extends").cloned();
+        let migration = match extends {
+            Some(_) => {
+                let migration = migrate::migrate_overlay(&mut table)?;
+                table = extends::resolve(table, &origin.to_string_lossy())?;
+                migration
+            }
+            None => migrate::migrate(&mut table)?,
+        };
+        // Keep the link to the base so that saving writes an overlay again.
+        if let Some(extends) = extends {
+            table.insert("extends".to_string(), extends);
+        }
+
+        let config = Self::from_table(table.clone(), origin).map_err(|e| {
+            // Errors from a `Value` carry no position; re-parse the source
+            // text to point at the offending line when possible.
+            match format {
+                Format::Toml => match toml::from_str::<Config>(content) {
+                    Err(spanned) => Error::config_parse(origin, content, spanned),
+                    Ok(_) => e,
+                },
+                _ => e,
+            }
+        })?;
+
+        Ok((config, table, migration))
+    }
+
+    /// Builds a configuration from a table as written in a file. `origin`
+    /// labels errors.
+    pub fn from_table(mut table: Table, origin: &Path) -> Result<Self> {
+        source::lift(&mut table);
+        Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| {
+                Error::Config(format!("{}: {}", origin.display(), e.message()))
+            })
+    }
+
+    /// Validates against Discord's limits. Returns the report when there
+    /// are only warnings.
+    pub fn check(&self) -> Result<validation::Report> {
+        let report = validation::validate(self);
+        if !report.is_valid() {
+            return Err(Error::Config(validation::format_issues(&report.errors)));
+        }
+        Ok(report)
+    }
+
+    /// Drops the sources that run shell commands, leaving their fields at
+    /// the fallback text, and returns those fields.
+    pub fn strip_exec(&mut self) -> Vec<String> {
+        let fields: Vec<String> = self
+            .sources
+            .iter()
+            .filter(|(_, source)| source.exec.is_some())
+            .map(|(field, _)| field.clone())
+            .collect();
+        self.sources.retain(|field, _| !fields.contains(field));
+        fields
+    }
+
+    pub fn truncate_buttons(&mut self) {
+        let slides = self.rotation.iter_mut().flat_map(|r| &mut r.slides);
+        let media = self.media.iter_mut().filter_map(|media| media.set.as_mut());
+        let slide_buttons = slides
+            .chain(media)
+            .filter_map(|slide| slide.buttons.as_mut());
+        for buttons in self.buttons.iter_mut().chain(slide_buttons) {
+            buttons.truncate(validation::MAX_BUTTONS);
+        }
+    }
+}
+
+/// The text at `location`, a file path or an http(s) URL.
+pub fn read_source(location: &str) -> Result<String> {
+    if extends::is_url(location) {
+        let response = reqwest::blocking::get(location)
+            .map_err(|e| Error::Network(format!("Failed to download '{}': {}", location, e)))?;
+        if !response.status().is_success() {
+            return Err(Error::Network(format!(
+                "Failed to download '{}': HTTP {}",
+                location,
+                response.status()
+            )));
+        }
+        return response
+            .text()
+            .map_err(|e| Error::Network(format!("Failed to read '{}': {}", location, e)));
+    }
+    fs::read_to_string(location).map_err(|e| Error::io(format!("Failed to read {:?}", location), e))
+}
+
+fn serialize_error(e: impl std::fmt::Display) -> Error {
+    Error::Config(format!("Failed to serialize configuration: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const COMMENTED: &str = r#"# Shared by the whole team
+version = 2
+client_id = "1234567890123456789"
+details = "Coding" # keep it short
+# state = "On call"
+state = "Reviewing PRs"
+
+[team]
+channel = "presence"
+"#;
+
+    #[test]
+    fn test_save_unmodified_config_is_byte_for_byte() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        let path = path.to_str().unwrap();
+        fs::write(path, COMMENTED).unwrap();
+
+        let config = Config::read_file(path).unwrap();
+        config.save_to_file(path).unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), COMMENTED);
+    }
+
+    #[test]
+    fn test_save_changes_only_edited_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        let path = path.to_str().unwrap();
+        fs::write(path, COMMENTED).unwrap();
+
+        let mut config = Config::read_file(path).unwrap();
+        config.state = "Deploying".to_string();
+        config.large_image = "rust".to_string();
+        config.save_to_file(path).unwrap();
+
+        let saved = fs::read_to_string(path).unwrap();
+        assert!(saved.starts_with("# Shared by the whole team\n"));
+        assert!(saved.contains(
+            "details = \"Coding\" # keep it short\n# state = \"On call\"\nstate = \"Deploying\"\n"
+        ));
+        assert!(saved.contains("large_image = \"rust\""));
+        assert!(saved.contains("[team]\nchannel = \"presence\"\n"));
+        assert!(!saved.contains("small_text"));
+    }
+
+    #[test]
+    fn test_overlay_saves_only_its_own_keys() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("team.toml"), COMMENTED).unwrap();
+        let path = dir.path().join("me.toml");
+        let path = path.to_str().unwrap();
+        fs::write(path, "extends = \"team.toml\"\nstate = \"Pairing\"\n").unwrap();
+
+        let mut config = Config::read_file(path).unwrap();
+        assert_eq!(config.client_id, "1234567890123456789");
+        assert_eq!(config.state, "Pairing");
+
+        config.details = "Writing docs".to_string();
+        config.state = "Reviewing PRs".to_string();
+        config.save_to_file(path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "extends = \"team.toml\"\ndetails = \"Writing docs\"\nversion = 2\n"
+        );
+    }
+
+    #[test]
+    fn test_old_overlays_are_migrated_on_their_own() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("team.toml"), COMMENTED).unwrap();
+        fs::write(
+            dir.path().join("pairing.toml"),
+            "extends = \"team.toml\"\nstate = \"Pairing\"\nparty_max = 2\n",
+        )
+        .unwrap();
+        let path = dir.path().join("me.toml");
+        let content = "extends = \"pairing.toml\"\nparty_max = 4\n";
+
+        let (config, _, migration) = Config::parse_migrating(content, &path).unwrap();
+        assert_eq!(config.max_party_size, 4);
+        assert_eq!(config.state, "Pairing");
+        assert_eq!(config.details, "Coding");
+        assert_eq!(
+            (migration.from, migration.to),
+            (0, migrate::CURRENT_VERSION)
+        );
+
+        let content = "extends = \"pairing.toml\"\ndetails = \"Docs\"\n";
+        let (config, _, migration) = Config::parse_migrating(content, &path).unwrap();
+        assert_eq!(config.max_party_size, 2);
+        assert!(!migration.is_needed());
+    }
+
+    #[test]
+    fn test_json_config_stays_json() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.json");
+        let path = path.to_str().unwrap();
+        fs::write(
+            path,
+            r#"{"version": 2, "client_id": "1234567890123456789", "details": "Coding", "end_timestamp": null}"#,
+        )
+        .unwrap();
+
+        let mut config = Config::read_file(path).unwrap();
+        assert_eq!(config.details, "Coding");
+        assert_eq!(config.end_timestamp, None);
+
+        config.state = "Reviewing PRs".to_string();
+        config.save_to_file(path).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(saved["state"], "Reviewing PRs");
+        assert!(saved.get("end_timestamp").is_none());
+    }
+
+    #[test]
+    fn test_sourced_fields_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        let path = path.to_str().unwrap();
+        let content = "version = 2\nclient_id = \"1234567890123456789\"\n\
+                       state = { exec = \"git branch --show-current\", fallback = \"main\" } # branch\n";
+        fs::write(path, content).unwrap();
+
+        let mut config = Config::read_file(path).unwrap();
+        assert_eq!(config.state, "main");
+        assert_eq!(
+            config.sources["state"].exec.as_deref(),
+            Some("git branch --show-current")
+        );
+        config.save_to_file(path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), content);
+
+        config.details = "Coding".to_string();
+        let saved = config.to_string_as(Format::Toml).unwrap();
+        assert!(saved
+            .contains("\nstate = { exec = \"git branch --show-current\", fallback = \"main\" }\n"));
+        assert!(!saved.contains("sources"));
+    }
+
+    #[test]
+    fn test_rotation_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        let path = path.to_str().unwrap();
+        let content = "version = 2\nclient_id = \"1234567890123456789\"\n\n\
+                       [rotation]\ninterval = \"30s\"\norder = \"random\"\n\n\
+                       [[rotation.slides]]\ndetails = \"Coding\" # first\n\n\
+                       [[rotation.slides]]\nstate = \"Reviewing PRs\"\n";
+        fs::write(path, content).unwrap();
+
+        let mut config = Config::read_file(path).unwrap();
+        let rotation = config.rotation.as_ref().unwrap();
+        assert_eq!(rotation.order, crate::rotation::Order::Random);
+        assert_eq!(rotation.slides[1].state.as_deref(), Some("Reviewing PRs"));
+        config.save_to_file(path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), content);
+
+        config.rotation.as_mut().unwrap().slides[1].state = Some("Deploying".to_string());
+        config.save_to_file(path).unwrap();
+        let saved = Config::read_file(path).unwrap();
+        assert_eq!(saved.rotation, config.rotation);
+        assert_eq!(saved.client_id, "1234567890123456789");
+    }
 }
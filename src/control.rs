@@ -0,0 +1,259 @@
+use crate::error::{Error, Result};
+use crate::rotation::Slide;
+use crate::shell::Report;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tracing::{debug, warn};
+
+/// A request to the daemon, sent as one line of JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// Shows an activity over the configured presence for as long as the
+    /// connection stays open. Holding again on the same connection
+    /// replaces it.
+    Hold(Hold),
+    /// Tells the daemon where a shell is and what it runs, for
+    /// `{project}`, `{branch}` and `{command}`.
+    Shell(Report),
+}
+
+/// An activity shown in place of the configured one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Hold {
+    /// Fields shown over the active profile's.
+    pub activity: Slide,
+    /// Unix timestamp an elapsed timer counts from; no timer without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_timestamp: Option<i64>,
+    /// The process Discord attributes the activity to, instead of the
+    /// daemon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+/// The daemon's answer to a request.
+#[derive(Serialize, Deserialize, Debug)]
+struct Reply {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// What a connection asked the daemon for. Connections are numbered so
+/// that their holds can be told apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Hold(u64, Hold),
+    /// The connection closed, so its hold ends.
+    Release(u64),
+    Shell(Report),
+}
+
+/// The listening end of the control socket. The socket file is removed
+/// when it is dropped.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Server {
+    /// Listens on `path`, replacing a socket left behind by a daemon that
+    /// did not exit cleanly. `on_event` is called from the connections'
+    /// threads.
+    pub fn start(path: &Path, on_event: impl Fn(Event) + Send + Sync + 'static) -> Result<Self> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(Error::io(format!("Failed to remove {:?}", path), e))
+            }
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| Error::io(format!("Failed to listen on {:?}", path), e))?;
+        // Only the user may change what their presence shows.
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|e| Error::io(format!("Failed to protect {:?}", path), e))?;
+
+        let on_event: Arc<dyn Fn(Event) + Send + Sync> = Arc::new(on_event);
+        let next_id = Arc::new(AtomicU64::new(1));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Control connection failed: {}", e);
+                        continue;
+                    }
+                };
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                let on_event = Arc::clone(&on_event);
+                thread::spawn(move || serve(id, stream, &*on_event));
+            }
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve(id: u64, stream: UnixStream, on_event: &dyn Fn(Event)) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            warn!("Control connection {}: {}", id, e);
+            return;
+        }
+    };
+    let mut held = false;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        debug!("Control connection {}: {}", id, line);
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Hold(hold)) => {
+                held = true;
+                on_event(Event::Hold(id, hold));
+                Reply {
+                    ok: true,
+                    error: None,
+                }
+            }
+            Ok(Request::Shell(report)) => {
+                on_event(Event::Shell(report));
+                Reply {
+                    ok: true,
+                    error: None,
+                }
+            }
+            Err(e) => Reply {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        };
+        let reply = serde_json::to_string(&reply).expect("reply serializes");
+        if writeln!(writer, "{}", reply).is_err() {
+            break;
+        }
+    }
+    if held {
+        on_event(Event::Release(id));
+    }
+}
+
+/// A connection to the daemon. Whatever it holds is released when it is
+/// dropped, including when the process holding it dies.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connects to the daemon listening on `path`. `None` when no daemon
+    /// is listening.
+    pub fn connect(path: &Path) -> Result<Option<Self>> {
+        let stream = match UnixStream::connect(path) {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(Error::io(format!("Failed to connect to {:?}", path), e)),
+        };
+        let writer = stream
+            .try_clone()
+            .map_err(|e| Error::io("Failed to set up the control connection", e))?;
+        Ok(Some(Self {
+            reader: BufReader::new(stream),
+            writer,
+        }))
+    }
+
+    pub fn hold(&mut self, hold: &Hold) -> Result<()> {
+        self.send(&Request::Hold(hold.clone()))
+    }
+
+    pub fn report(&mut self, report: &Report) -> Result<()> {
+        self.send(&Request::Shell(report.clone()))
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        let request = serde_json::to_string(request).expect("request serializes");
+        let lost = |e| Error::io("Lost the connection to the daemon", e);
+        writeln!(self.writer, "{}", request).map_err(lost)?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line).map_err(lost)?;
+        let reply: Reply = serde_json::from_str(&line)
+            .map_err(|e| Error::Daemon(format!("Unexpected reply from the daemon: {}", e)))?;
+        match reply.error {
+            Some(error) if !reply.ok => Err(Error::Daemon(format!(
+                "The daemon refused the request: {}",
+                error
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hold_lasts_as_long_as_the_connection() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dstatus.sock");
+        assert!(Client::connect(&path).unwrap().is_none());
+
+        let (tx, rx) = mpsc::channel();
+        let server = Server::start(&path, move |event| tx.send(event).unwrap()).unwrap();
+        let hold = Hold {
+            activity: Slide {
+                details: Some("Compiling".to_string()),
+                ..Slide::default()
+            },
+            start_timestamp: Some(1_700_000_000),
+            pid: Some(4242),
+        };
+        let mut client = Client::connect(&path).unwrap().unwrap();
+        client.hold(&hold).unwrap();
+        let timeout = Duration::from_secs(5);
+        let Event::Hold(id, held) = rx.recv_timeout(timeout).unwrap() else {
+            panic!("expected a hold");
+        };
+        assert_eq!(held, hold);
+
+        drop(client);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), Event::Release(id));
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_bad_requests_are_refused() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dstatus.sock");
+        let _server = Server::start(&path, |_| {}).unwrap();
+        let mut stream = UnixStream::connect(&path).unwrap();
+        writeln!(stream, "{{\"request\": \"explode\"}}").unwrap();
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply).unwrap();
+        assert!(reply.starts_with("{\"ok\":false"));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::config::Config;
+use crate::control::Client;
+use crate::paths::Paths;
+use crate::profile;
+use crate::rich_presence::RichPresence;
+use crate::stream_manager::{candidate_dirs, find_sockets};
+use crate::validation;
+use nix::sys::signal;
+use nix::unistd::Pid;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Runs every diagnostic in order and returns the collected results.
+pub fn run(paths: &Paths) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let config = check_config(&paths.config_dir, &mut checks);
+    let sockets = check_sockets(&mut checks);
+    check_handshakes(config.as_ref(), &sockets, &mut checks);
+    let running = check_daemon(&paths.pid_file(), &mut checks);
+    check_control(&paths.control_socket(), running, &mut checks);
+    check_man_page(&mut checks);
+    check_cli(&mut checks);
+    check_autostart(dirs::home_dir().as_deref(), &mut checks);
+
+    checks
+}
+
+pub fn print_report(checks: &[Check]) {
+    for check in checks {
+        let (mark, color) = match check.status {
+            Status::Pass => ("✓", "\x1b[32m"),
+            Status::Warn => ("!", "\x1b[33m"),
+            Status::Fail => ("✗", "\x1b[31m"),
+        };
+        println!("{}{}\x1b[0m {}: {}", color, mark, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("    → {}", hint);
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    println!();
+    println!(
+        "{} checks, {} failed, {} warnings",
+        checks.len(),
+        failed,
+        warned
+    );
+}
+
+fn check_config(config_dir: &Path, checks: &mut Vec<Check>) -> Option<Config> {
+    let name = "Configuration";
+    let config_file = match profile::active_path(config_dir) {
+        Ok(path) => path,
+        Err(e) => {
+            checks.push(Check::new(name, Status::Fail, e.to_string()));
+            return None;
+        }
+    };
+
+    let content = match fs::read_to_string(&config_file) {
+        Ok(content) => content,
+        Err(e) => {
+            checks.push(
+                Check::new(
+                    name,
+                    Status::Fail,
+                    format!("Cannot read {:?}: {}", config_file, e),
+                )
+                .hint("Run 'dstatus configure' or 'dstatus load <file>' to create one"),
+            );
+            return None;
+        }
+    };
+
+    let config = match Config::parse(&content, &config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            checks.push(
+                Check::new(name, Status::Fail, e.to_string())
+                    .hint("Fix the syntax or recreate the file with 'dstatus configure'"),
+            );
+            return None;
+        }
+    };
+
+    let report = validation::validate(&config);
+    if report.is_valid() {
+        checks.push(Check::new(
+            name,
+            Status::Pass,
+            format!("{:?} is valid", config_file),
+        ));
+    } else {
+        checks.push(
+            Check::new(
+                name,
+                Status::Fail,
+                validation::format_issues(&report.errors),
+            )
+            .hint("Run 'dstatus configure' to fix the highlighted fields"),
+        );
+    }
+    if !report.warnings.is_empty() {
+        checks.push(Check::new(
+            name,
+            Status::Warn,
+            validation::format_issues(&report.warnings),
+        ));
+    }
+
+    Some(config)
+}
+
+fn check_sockets(checks: &mut Vec<Check>) -> Vec<PathBuf> {
+    let dirs = candidate_dirs();
+    let sockets: Vec<PathBuf> = dirs.iter().flat_map(|dir| find_sockets(dir)).collect();
+
+    if sockets.is_empty() {
+        let searched: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+        checks.push(
+            Check::new(
+                "Discord IPC socket",
+                Status::Fail,
+                format!("No discord-ipc-N socket in {}", searched.join(", ")),
+            )
+            .hint(
+                "Start the Discord desktop app; the browser version does not expose Rich Presence",
+            ),
+        );
+    } else {
+        let found: Vec<String> = sockets.iter().map(|s| s.display().to_string()).collect();
+        checks.push(Check::new(
+            "Discord IPC socket",
+            Status::Pass,
+            format!("Found {}", found.join(", ")),
+        ));
+    }
+
+    sockets
+}
+
+fn check_handshakes(config: Option<&Config>, sockets: &[PathBuf], checks: &mut Vec<Check>) {
+    let config = match config {
+        Some(config) if !config.client_id.is_empty() => config,
+        _ => {
+            if !sockets.is_empty() {
+                checks.push(Check::new(
+                    "Handshake",
+                    Status::Warn,
+                    "Skipped: no usable client_id in the configuration",
+                ));
+            }
+            return;
+        }
+    };
+
+    for socket in sockets {
+        let name = format!("Handshake {}", socket.display());
+        let mut presence = RichPresence::new(config.clone());
+        match presence.start_on(socket) {
+            Ok(()) => checks.push(Check::new(
+                &name,
+                Status::Pass,
+                "Discord accepted the client ID",
+            )),
+            Err(e) => checks.push(
+                Check::new(&name, Status::Fail, format!("{:#}", e))
+                    .hint("Check that client_id matches an existing Discord application"),
+            ),
+        }
+    }
+}
+
+/// Returns whether the daemon named by the PID file is running.
+fn check_daemon(pid_path: &Path, checks: &mut Vec<Check>) -> bool {
+    let name = "Daemon";
+
+    let pid_str = match fs::read_to_string(pid_path) {
+        Ok(pid_str) => pid_str,
+        Err(_) => {
+            checks.push(
+                Check::new(name, Status::Warn, "Not running (no PID file)")
+                    .hint("Run 'dstatus on'"),
+            );
+            return false;
+        }
+    };
+
+    match pid_str.trim().parse() {
+        Ok(pid_val) => {
+            if signal::kill(Pid::from_raw(pid_val), None).is_ok() {
+                checks.push(Check::new(
+                    name,
+                    Status::Pass,
+                    format!("Running with PID {}", pid_val),
+                ));
+                return true;
+            } else {
+                checks.push(
+                    Check::new(
+                        name,
+                        Status::Fail,
+                        format!(
+                            "Stale PID file {:?}: process {} is not running",
+                            pid_path, pid_val
+                        ),
+                    )
+                    .hint(
+                        "Run 'dstatus off' to clear it, then 'dstatus logs' to see why it exited",
+                    ),
+                );
+            }
+        }
+        Err(_) => checks.push(
+            Check::new(
+                name,
+                Status::Fail,
+                format!("PID file {:?} is corrupt", pid_path),
+            )
+            .hint("Run 'dstatus off' to remove it"),
+        ),
+    }
+    false
+}
+
+/// The control socket doubles as the daemon's lock: a running daemon must
+/// accept connections on it, and a stopped one should not leave it behind.
+fn check_control(socket: &Path, running: bool, checks: &mut Vec<Check>) {
+    let name = "Control socket";
+    let accepting = match Client::connect(socket) {
+        Ok(client) => client.is_some(),
+        Err(e) => {
+            checks.push(Check::new(name, Status::Fail, e.to_string()));
+            return;
+        }
+    };
+
+    match (running, accepting) {
+        (true, true) => checks.push(Check::new(
+            name,
+            Status::Pass,
+            format!("{:?} accepts connections", socket),
+        )),
+        (true, false) => checks.push(
+            Check::new(
+                name,
+                Status::Fail,
+                format!(
+                    "The daemon is running but {:?} does not accept connections",
+                    socket
+                ),
+            )
+            .hint("Restart it with 'dstatus off' and 'dstatus on'"),
+        ),
+        (false, true) => checks.push(
+            Check::new(
+                name,
+                Status::Warn,
+                format!("{:?} accepts connections but no daemon is recorded", socket),
+            )
+            .hint("Another daemon may be using this setup; stop it before starting a new one"),
+        ),
+        (false, false) if socket.exists() => checks.push(
+            Check::new(name, Status::Warn, format!("Stale socket {:?}", socket))
+                .hint("It is replaced when the daemon next starts"),
+        ),
+        (false, false) => {}
+    }
+}
+
+fn check_man_page(checks: &mut Vec<Check>) {
+    let name = "Man page";
+    let Some(home) = dirs::home_dir() else {
+        checks.push(Check::new(
+            name,
+            Status::Warn,
+            "Cannot determine home directory",
+        ));
+        return;
+    };
+
+    let man_root = home.join(".local").join("share").join("man");
+    let man_file = man_root.join("man1").join("dstatus.1");
+    if !man_file.exists() {
+        checks.push(
+            Check::new(name, Status::Warn, "Not installed").hint("Run 'dstatus install-man'"),
+        );
+        return;
+    }
+
+    let manpath = std::env::var("MANPATH").unwrap_or_default();
+    if !manpath.is_empty() && !manpath.split(':').any(|p| Path::new(p) == man_root) {
+        checks.push(
+            Check::new(
+                name,
+                Status::Warn,
+                format!("Installed, but {:?} is not in MANPATH", man_root),
+            )
+            .hint("export MANPATH=\"$HOME/.local/share/man:$MANPATH\""),
+        );
+    } else {
+        checks.push(Check::new(
+            name,
+            Status::Pass,
+            format!("Installed at {:?}", man_file),
+        ));
+    }
+}
+
+fn check_cli(checks: &mut Vec<Check>) {
+    let name = "CLI";
+    let path_env = std::env::var("PATH").unwrap_or_default();
+    let on_path = path_env
+        .split(':')
+        .map(|dir| Path::new(dir).join("dstatus"))
+        .find(|candidate| candidate.is_file());
+
+    match on_path {
+        Some(found) => checks.push(Check::new(name, Status::Pass, format!("Found {:?}", found))),
+        None => {
+            let local_bin = dirs::home_dir().map(|h| h.join(".local").join("bin"));
+            let installed = local_bin
+                .as_ref()
+                .is_some_and(|dir| dir.join("dstatus").is_file());
+            let check = if installed {
+                Check::new(
+                    name,
+                    Status::Warn,
+                    "Installed in ~/.local/bin, which is not in PATH",
+                )
+                .hint("export PATH=\"$HOME/.local/bin:$PATH\"")
+            } else {
+                Check::new(name, Status::Warn, "dstatus is not in PATH")
+                    .hint("Install it from the GUI or with the install script")
+            };
+            checks.push(check);
+        }
+    }
+}
+
+fn autostart_candidates(home: &Path) -> Vec<PathBuf> {
+    if cfg!(target_os = "macos") {
+        vec![
+            home.join("Library/LaunchAgents/rs.dstatus.plist"),
+            home.join("Library/LaunchAgents/com.dstatus.plist"),
+        ]
+    } else {
+        vec![
+            home.join(".config/autostart/dstatus.desktop"),
+            home.join(".config/systemd/user/dstatus.service"),
+        ]
+    }
+}
+
+fn check_autostart(home: Option<&Path>, checks: &mut Vec<Check>) {
+    let name = "Autostart";
+    let Some(home) = home else {
+        checks.push(Check::new(
+            name,
+            Status::Warn,
+            "Cannot determine home directory",
+        ));
+        return;
+    };
+
+    match autostart_candidates(home).into_iter().find(|p| p.exists()) {
+        Some(entry) => checks.push(Check::new(
+            name,
+            Status::Pass,
+            format!("Enabled via {:?}", entry),
+        )),
+        None => checks.push(
+            Check::new(
+                name,
+                Status::Warn,
+                "Not configured; the daemon must be started manually",
+            )
+            .hint("Add 'dstatus on' to your login items or session startup"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Server;
+    use tempfile::tempdir;
+
+    fn statuses(checks: &[Check]) -> Vec<Status> {
+        checks.iter().map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn test_config_results() {
+        let dir = tempdir().unwrap();
+        let mut checks = Vec::new();
+        assert!(check_config(dir.path(), &mut checks).is_none());
+        assert_eq!(statuses(&checks), [Status::Fail]);
+        assert!(checks[0].hint.is_some());
+
+        let file = dir.path().join("configuration.toml");
+        fs::write(&file, "client_id = \"abc\"\n").unwrap();
+        let mut checks = Vec::new();
+        assert!(check_config(dir.path(), &mut checks).is_some());
+        assert_eq!(statuses(&checks), [Status::Fail]);
+        assert!(checks[0].detail.contains("client_id"));
+
+        fs::write(&file, "client_id = \"1234567890123456789\"\n").unwrap();
+        let mut checks = Vec::new();
+        assert!(check_config(dir.path(), &mut checks).is_some());
+        assert_eq!(checks[0].status, Status::Pass);
+    }
+
+    #[test]
+    fn test_daemon_results() {
+        let dir = tempdir().unwrap();
+        let pid_file = dir.path().join("dstatus.pid");
+        let mut checks = Vec::new();
+        assert!(!check_daemon(&pid_file, &mut checks));
+        assert_eq!(statuses(&checks), [Status::Warn]);
+
+        fs::write(&pid_file, i32::MAX.to_string()).unwrap();
+        let mut checks = Vec::new();
+        assert!(!check_daemon(&pid_file, &mut checks));
+        assert_eq!(statuses(&checks), [Status::Fail]);
+        assert!(checks[0].detail.starts_with("Stale"));
+
+        fs::write(&pid_file, "not a pid").unwrap();
+        let mut checks = Vec::new();
+        assert!(!check_daemon(&pid_file, &mut checks));
+        assert!(checks[0].detail.ends_with("is corrupt"));
+
+        fs::write(&pid_file, std::process::id().to_string()).unwrap();
+        let mut checks = Vec::new();
+        assert!(check_daemon(&pid_file, &mut checks));
+        assert_eq!(statuses(&checks), [Status::Pass]);
+    }
+
+    #[test]
+    fn test_control_socket_results() {
+        let dir = tempdir().unwrap();
+        let socket = dir.path().join("dstatus.sock");
+        let mut checks = Vec::new();
+        check_control(&socket, false, &mut checks);
+        assert!(checks.is_empty());
+
+        check_control(&socket, true, &mut checks);
+        assert_eq!(statuses(&checks), [Status::Fail]);
+
+        let server = Server::start(&socket, |_| {}).unwrap();
+        let mut checks = Vec::new();
+        check_control(&socket, true, &mut checks);
+        assert_eq!(statuses(&checks), [Status::Pass]);
+        drop(server);
+
+        // A socket left behind by a daemon that was killed.
+        std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let mut checks = Vec::new();
+        check_control(&socket, false, &mut checks);
+        assert_eq!(statuses(&checks), [Status::Warn]);
+        assert!(checks[0].detail.starts_with("Stale"));
+    }
+
+    #[test]
+    fn test_autostart_results() {
+        let dir = tempdir().unwrap();
+        let mut checks = Vec::new();
+        check_autostart(Some(dir.path()), &mut checks);
+        assert_eq!(statuses(&checks), [Status::Warn]);
+
+        let entry = &autostart_candidates(dir.path())[0];
+        fs::create_dir_all(entry.parent().unwrap()).unwrap();
+        fs::write(entry, "").unwrap();
+        let mut checks = Vec::new();
+        check_autostart(Some(dir.path()), &mut checks);
+        assert_eq!(statuses(&checks), [Status::Pass]);
+
+        let mut checks = Vec::new();
+        check_autostart(None, &mut checks);
+        assert_eq!(statuses(&checks), [Status::Warn]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::{Error, Result};
+use toml::{Table, Value};
+use toml_edit::{DocumentMut, Item};
+
+/// Rewrites `existing` so that its top-level keys match `new`, touching only
+/// the keys whose values actually changed. Comments, key order, formatting
+/// and anything not owned by the caller are left exactly as they were.
+///
+/// - A key present in both is replaced only when the values differ; the
+///   comments attached to the key and value survive the replacement.
+/// - A key missing from `existing` is inserted unless `new` holds the same
+///   value as `defaults`, since leaving it out means the same thing.
+/// - A key missing from `new` is removed if `owns` claims it. Keys the
+///   caller does not own, such as unknown tables, are never removed.
+pub fn update(
+    existing: &str,
+    new: &Table,
+    owns: impl Fn(&str) -> bool,
+    defaults: &Table,
+) -> Result<String> {
+    let mut doc: DocumentMut = existing
+        .parse()
+        .map_err(|e| Error::Config(format!("Failed to parse existing document: {}", e)))?;
+    let old: Table = toml::from_str(existing)
+        .map_err(|e| Error::Config(format!("Failed to parse existing document: {}", e)))?;
+
+    let stale: Vec<String> = doc
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| owns(key) && !new.contains_key(key))
+        .collect();
+    for key in stale {
+        doc.remove(&key);
+    }
+
+    for (key, value) in new {
+        match old.get(key) {
+            Some(old_value) if old_value == value => {}
+            Some(_) => {
+                let item = to_item(key, value)?;
+                match doc.get_mut(key) {
+                    Some(slot) if slot.is_value() == item.is_value() => {
+                        replace_keeping_decor(slot, item)
+                    }
+                    // A plain value becoming a table stays on its line as
+                    // an inline table.
+                    Some(slot) if slot.is_value() && item.is_table() => {
+                        let item = item
+                            .into_value()
+                            .map(Item::Value)
+                            .unwrap_or_else(|item| item);
+                        replace_keeping_decor(slot, item)
+                    }
+                    // A `[[table]]` turning into an inline value or back
+                    // cannot keep its layout; write it afresh.
+                    _ => {
+                        doc.remove(key);
+                        doc.insert(key, item);
+                    }
+                }
+            }
+            None => {
+                if defaults.get(key) != Some(value) {
+                    doc.insert(key, to_item(key, value)?);
+                }
+            }
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+/// Formats `value` the way `toml::to_string` would, as a document item.
+fn to_item(key: &str, value: &Value) -> Result<Item> {
+    let mut wrapper = Table::new();
+    wrapper.insert(key.to_string(), value.clone());
+    let rendered = toml::to_string(&wrapper)
+        .map_err(|e| Error::Config(format!("Failed to serialize `{}`: {}", key, e)))?;
+    let mut doc: DocumentMut = rendered
+        .parse()
+        .map_err(|e| Error::Config(format!("Failed to render `{}`: {}", key, e)))?;
+    doc.remove(key)
+        .ok_or_else(|| Error::Config(format!("Failed to render `{}`", key)))
+}
+
+fn replace_keeping_decor(slot: &mut Item, mut item: Item) {
+    if let (Some(old), Some(new)) = (slot.as_value(), item.as_value_mut()) {
+        *new.decor_mut() = old.decor().clone();
+    }
+    *slot = item;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMENTED: &str = r#"# Presence for work hours
+version = 2
+client_id = "1234567890123456789" # the "Coding" app
+details = "Writing Rust"
+# state = "Reviewing PRs"
+state = "Deep work"
+
+[extra]
+owner = "team"
+
+[[buttons]]
+label = "Repo" # shown first
+url = "https://example.com"
+"#;
+
+    fn owned(key: &str) -> bool {
+        key != "extra"
+    }
+
+    #[test]
+    fn test_unchanged_document_round_trips_byte_for_byte() {
+        let table: Table = toml::from_str(COMMENTED).unwrap();
+        let updated = update(COMMENTED, &table, owned, &Table::new()).unwrap();
+        assert_eq!(updated, COMMENTED);
+    }
+
+    #[test]
+    fn test_changed_key_keeps_comments_and_order() {
+        let mut table: Table = toml::from_str(COMMENTED).unwrap();
+        table.insert(
+            "client_id".into(),
+            Value::String("9876543210987654321".into()),
+        );
+        let updated = update(COMMENTED, &table, owned, &Table::new()).unwrap();
+        assert_eq!(
+            updated,
+            COMMENTED.replace("1234567890123456789", "9876543210987654321")
+        );
+    }
+
+    #[test]
+    fn test_removes_owned_keys_and_keeps_unknown_tables() {
+        let mut table: Table = toml::from_str(COMMENTED).unwrap();
+        table.remove("buttons");
+        table.remove("extra");
+        let updated = update(COMMENTED, &table, owned, &Table::new()).unwrap();
+        assert!(!updated.contains("[[buttons]]"));
+        assert!(updated.contains("[extra]\nowner = \"team\""));
+        assert!(updated.contains("# state = \"Reviewing PRs\""));
+    }
+
+    #[test]
+    fn test_skips_absent_defaults_and_inserts_the_rest() {
+        let mut table: Table = toml::from_str(COMMENTED).unwrap();
+        table.insert("large_text".into(), Value::String(String::new()));
+        table.insert("small_text".into(), Value::String("Hi".into()));
+        let mut defaults = Table::new();
+        defaults.insert("large_text".into(), Value::String(String::new()));
+        defaults.insert("small_text".into(), Value::String(String::new()));
+
+        let updated = update(COMMENTED, &table, owned, &defaults).unwrap();
+        assert!(!updated.contains("large_text"));
+        assert!(updated.contains("small_text = \"Hi\""));
+    }
+
+    #[test]
+    fn test_array_of_tables_can_become_a_value() {
+        let mut table: Table = toml::from_str(COMMENTED).unwrap();
+        table.insert("buttons".into(), Value::Array(Vec::new()));
+        let updated = update(COMMENTED, &table, owned, &Table::new()).unwrap();
+        assert!(updated.contains("\nbuttons = []\n"), "{}", updated);
+        assert!(!updated.contains("[[buttons]]"));
+    }
+}
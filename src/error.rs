@@ -0,0 +1,132 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Process exit codes returned by every subcommand. Documented in the
+/// EXIT STATUS section of dstatus(1); keep the two in sync. `SUCCESS` and
+/// `USAGE` are listed for completeness but produced by std and clap.
+#[allow(dead_code)]
+pub mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    pub const FAILURE: i32 = 1;
+    pub const USAGE: i32 = 2;
+    pub const CONFIG: i32 = 3;
+    pub const IO: i32 = 4;
+    pub const IPC: i32 = 5;
+    pub const NETWORK: i32 = 6;
+    pub const DAEMON: i32 = 7;
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to parse {path:?} at line {line}, column {column}: {message}")]
+    ConfigParse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Discord IPC error: {0}")]
+    Ipc(String),
+    #[error("Network error: {0}")]
+    Network(String),
+    #[error("{0}")]
+    Daemon(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ConfigParse { .. } | Error::Config(_) => exit_code::CONFIG,
+            Error::Io { .. } => exit_code::IO,
+            Error::Ipc(_) => exit_code::IPC,
+            Error::Network(_) => exit_code::NETWORK,
+            Error::Daemon(_) => exit_code::DAEMON,
+            Error::Other(_) => exit_code::FAILURE,
+        }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Wraps a TOML deserialization error, resolving its byte span in
+    /// `content` to a 1-based line and column.
+    pub fn config_parse(path: &Path, content: &str, err: toml::de::Error) -> Self {
+        let (line, column) = err
+            .span()
+            .map(|span| line_column(content, span.start))
+            .unwrap_or((1, 1));
+        Error::ConfigParse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: err.message().trim().to_string(),
+        }
+    }
+
+    pub fn ipc(err: anyhow::Error) -> Self {
+        Error::Ipc(format!("{:#}", err))
+    }
+}
+
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(content.len());
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |last| last.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parse_reports_line_and_column() {
+        let content = "client_id = \"1\"\ndetails = \n";
+        let err = toml::from_str::<toml::Table>(content).unwrap_err();
+        match Error::config_parse(Path::new("c.toml"), content, err) {
+            Error::ConfigParse { line, column, .. } => {
+                assert_eq!(line, 2);
+                assert_eq!(column, 11);
+            }
+            other => panic!("Expected ConfigParse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::Config(String::new()),
+            Error::io("", io::Error::from(io::ErrorKind::NotFound)),
+            Error::Ipc(String::new()),
+            Error::Network(String::new()),
+            Error::Daemon(String::new()),
+            Error::Other(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+}
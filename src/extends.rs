@@ -0,0 +1,355 @@
+use crate::config;
+use crate::error::{Error, Result};
+use crate::format::{self, Format};
+use crate::history;
+use crate::migrate;
+use crate::source;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+use tracing::warn;
+
+/// How many bases a chain of `extends` may have.
+pub const MAX_DEPTH: usize = 8;
+
+/// Whether `origin` names a URL rather than a file.
+pub fn is_url(origin: &str) -> bool {
+    origin.starts_with("http://") || origin.starts_with("https://")
+}
+
+/// Whether `target` means the same thing wherever the file referring to it
+/// is stored, i.e. it is a URL or an absolute path.
+pub fn is_portable(target: &str) -> bool {
+    is_url(target) || Path::new(target).is_absolute()
+}
+
+/// Merges `table`, loaded from `origin`, onto the chain of bases named by
+/// its `extends` key. The result no longer contains `extends`; a table
+/// without one is returned unchanged. The bases are migrated here, but
+/// `table` itself is expected to be migrated already.
+pub fn resolve(mut table: Table, origin: &str) -> Result<Table> {
+    let Some(target) = take_extends(&mut table)? else {
+        return Ok(table);
+    };
+    let mut chain = vec![canonical(origin)];
+    let mut base = load_chain(&target, origin, &mut chain, &mut Fetch::default())?;
+    merge(&mut base, table);
+    Ok(base)
+}
+
+/// Loads the fully resolved and migrated base that `target` names, as seen
+/// from a file at `origin`.
+pub fn load(target: &str, origin: &str) -> Result<Table> {
+    load_chain(
+        target,
+        origin,
+        &mut vec![canonical(origin)],
+        &mut Fetch::default(),
+    )
+}
+
+/// Downloads the URL bases in the chain of the file at `path` again,
+/// replacing the cached copies. Returns the URLs that were fetched.
+pub fn refresh(path: &Path) -> Result<Vec<String>> {
+    let origin = path.to_string_lossy();
+    let content = config::read_source(&origin)?;
+    let format = Format::detect(&origin, &content);
+    let mut table = format::parse(&content, format, path)?;
+    let mut fetch = Fetch {
+        refresh: true,
+        ..Fetch::default()
+    };
+    if let Some(target) = take_extends(&mut table)? {
+        load_chain(&target, &origin, &mut vec![canonical(&origin)], &mut fetch)?;
+    }
+    Ok(fetch.fetched)
+}
+
+/// How URL bases are read. They are downloaded once and then read from
+/// the cache, so that reloads and saves neither wait on the network nor
+/// fail offline, until an explicit refresh downloads them again.
+#[derive(Default)]
+struct Fetch {
+    refresh: bool,
+    fetched: Vec<String>,
+}
+
+impl Fetch {
+    fn read(&mut self, location: &str) -> Result<String> {
+        if !is_url(location) {
+            return config::read_source(location);
+        }
+        let cached = cache_file(location);
+        if !self.refresh {
+            if let Some(content) = cached.as_ref().and_then(|f| fs::read_to_string(f).ok()) {
+                return Ok(content);
+            }
+        }
+        let content = config::read_source(location)?;
+        self.fetched.push(location.to_string());
+        if let Some(file) = cached {
+            let stored = file
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(|e| Error::io(format!("Failed to create {:?}", file.parent()), e))
+                .and_then(|()| history::atomic_write(&file, &content));
+            if let Err(e) = stored {
+                warn!("Failed to cache {}: {}", location, e);
+            }
+        }
+        Ok(content)
+    }
+}
+
+/// Where the downloaded copy of `url` is kept. Every character that could
+/// not appear in a file name is escaped, so that no two URLs share a file.
+fn cache_file(url: &str) -> Option<PathBuf> {
+    let name: String = url
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' => (b as char).to_string(),
+            _ => format!("_{:02x}", b),
+        })
+        .collect();
+    dirs::cache_dir().map(|dir| dir.join("dstatus").join("extends").join(name))
+}
+
+fn load_chain(
+    target: &str,
+    origin: &str,
+    chain: &mut Vec<String>,
+    fetch: &mut Fetch,
+) -> Result<Table> {
+    let location = locate(target, origin)?;
+    let key = canonical(&location);
+    if chain.contains(&key) {
+        chain.push(key);
+        return Err(Error::Config(format!(
+            "extends loop: {}",
+            chain.join(" -> ")
+        )));
+    }
+    if chain.len() > MAX_DEPTH {
+        return Err(Error::Config(format!(
+            "extends chain is deeper than {} files at {}",
+            MAX_DEPTH, location
+        )));
+    }
+    chain.push(key);
+
+    let content = fetch.read(&location)?;
+    let format = Format::detect(&location, &content);
+    let mut table = format::parse(&content, format, Path::new(&location))?;
+    if is_url(&location) {
+        for field in source::strip_exec(&mut table) {
+            warn!(
+                "{}: ignoring the command for {}; bases from URLs cannot run commands",
+                location, field
+            );
+        }
+    }
+    match take_extends(&mut table)? {
+        Some(next) => {
+            migrate::migrate_overlay(&mut table)?;
+            let mut base = load_chain(&next, &location, chain, fetch)?;
+            merge(&mut base, table);
+            Ok(base)
+        }
+        // Every file in a chain is upgraded on its own, from its own
+        // version, so that an old overlay is not mistaken for a current
+        // one once it sits on a current base.
+        None => {
+            migrate::migrate(&mut table)?;
+            Ok(table)
+        }
+    }
+}
+
+/// The local files a configuration is built from: `path` itself followed
+/// by every base it extends that is a file rather than a URL. Unreadable
+/// links end the list instead of failing, since a watcher still wants
+/// to know about the files that do exist.
+pub fn local_chain(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    let mut current = path.to_string_lossy().into_owned();
+    while files.len() <= MAX_DEPTH {
+        let Some(target) = fs::read_to_string(&current)
+            .ok()
+            .and_then(|content| {
+                let format = Format::detect(&current, &content);
+                format::parse(&content, format, Path::new(&current)).ok()
+            })
+            .and_then(|mut table| take_extends(&mut table).ok().flatten())
+        else {
+            break;
+        };
+        let Ok(location) = locate(&target, &current) else {
+            break;
+        };
+        let file = PathBuf::from(&location);
+        if is_url(&location) || files.contains(&file) {
+            break;
+        }
+        files.push(file);
+        current = location;
+    }
+    files
+}
+
+fn take_extends(table: &mut Table) -> Result<Option<String>> {
+    match table.remove("extends") {
+        None => Ok(None),
+        Some(Value::String(target)) => Ok(Some(target)),
+        Some(other) => Err(Error::Config(format!(
+            "extends: expected a path or URL, found {}",
+            other
+        ))),
+    }
+}
+
+/// Overlays `overlay` onto `base`. Tables merge key by key; any other
+/// value, including arrays such as `buttons`, replaces the base value.
+pub fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => {
+                merge(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolves `target` relative to the file or URL that refers to it. A
+/// configuration from a URL may only extend other URLs, so that it cannot
+/// pull local files into the result.
+fn locate(target: &str, origin: &str) -> Result<String> {
+    if is_url(origin) {
+        let base = reqwest::Url::parse(origin)
+            .map_err(|e| Error::Config(format!("Invalid URL '{}': {}", origin, e)))?;
+        let location = base
+            .join(target)
+            .map(|url| url.to_string())
+            .map_err(|e| Error::Config(format!("Invalid extends '{}': {}", target, e)))?;
+        if !is_url(&location) || (!is_url(target) && Path::new(target).is_absolute()) {
+            return Err(Error::Config(format!(
+                "extends: '{}' from {} is not a URL; downloaded configurations cannot extend local files",
+                target, origin
+            )));
+        }
+        return Ok(location);
+    }
+    if is_portable(target) {
+        return Ok(target.to_string());
+    }
+    let dir = Path::new(origin).parent().unwrap_or(Path::new("."));
+    Ok(dir.join(target).to_string_lossy().into_owned())
+}
+
+/// A stable name for loop detection, so `a.toml` and `./a.toml` match.
+fn canonical(location: &str) -> String {
+    if is_url(location) {
+        return location.to_string();
+    }
+    fs::canonicalize(location)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| location.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_overlay_merges_onto_base() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("base.toml"),
+            "version = 2\nclient_id = \"1234567890123456789\"\ndetails = \"Team\"\n\
+             [[buttons]]\nlabel = \"Repo\"\nurl = \"https://example.com\"\n[extra]\na = 1\nb = 2\n",
+        )
+        .unwrap();
+        let overlay_path = dir.path().join("me.toml");
+        let overlay: Table = "extends = \"base.toml\"\ndetails = \"Mine\"\n[extra]\nb = 3\n"
+            .parse()
+            .unwrap();
+
+        let merged = resolve(overlay, &overlay_path.to_string_lossy()).unwrap();
+        assert_eq!(merged["details"].as_str(), Some("Mine"));
+        assert_eq!(merged["client_id"].as_str(), Some("1234567890123456789"));
+        assert_eq!(merged["buttons"].as_array().unwrap().len(), 1);
+        assert_eq!(merged["extra"]["a"].as_integer(), Some(1));
+        assert_eq!(merged["extra"]["b"].as_integer(), Some(3));
+        assert!(!merged.contains_key("extends"));
+    }
+
+    #[test]
+    fn test_detects_loops() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.toml"), "extends = \"b.toml\"\n").unwrap();
+        fs::write(dir.path().join("b.toml"), "extends = \"./a.toml\"\n").unwrap();
+
+        let a = dir.path().join("a.toml");
+        let table: Table = fs::read_to_string(&a).unwrap().parse().unwrap();
+        let err = resolve(table, &a.to_string_lossy()).unwrap_err();
+        assert!(err.to_string().contains("extends loop"), "{}", err);
+    }
+
+    #[test]
+    fn test_local_chain_stops_at_urls_and_loops() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.toml");
+        fs::write(&a, "extends = \"b.toml\"\n").unwrap();
+        fs::write(dir.path().join("b.toml"), "extends = \"a.toml\"\n").unwrap();
+        assert_eq!(local_chain(&a), vec![a.clone(), dir.path().join("b.toml")]);
+
+        fs::write(
+            dir.path().join("b.toml"),
+            "extends = \"https://example.com/c.toml\"\n",
+        )
+        .unwrap();
+        assert_eq!(local_chain(&a).len(), 2);
+    }
+
+    #[test]
+    fn test_relative_to_url() {
+        assert_eq!(
+            locate("base.toml", "https://example.com/team/me.toml").unwrap(),
+            "https://example.com/team/base.toml"
+        );
+        assert_eq!(
+            locate(
+                "https://other.example/base.toml",
+                "https://example.com/me.toml"
+            )
+            .unwrap(),
+            "https://other.example/base.toml"
+        );
+        for local in ["/etc/base.toml", "file:///etc/base.toml"] {
+            assert!(locate(local, "https://example.com/me.toml").is_err());
+        }
+        assert_eq!(
+            locate("/etc/base.toml", "/home/me/me.toml").unwrap(),
+            "/etc/base.toml"
+        );
+    }
+
+    #[test]
+    fn test_cache_files_are_distinct() {
+        let names: Vec<PathBuf> = [
+            "https://example.com/a/b.toml",
+            "https://example.com/a_2fb.toml",
+            "https://example.com/a?b.toml",
+        ]
+        .iter()
+        .filter_map(|url| cache_file(url))
+        .collect();
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[i + 1..].contains(name));
+            assert!(!name.file_name().unwrap().to_string_lossy().contains('/'));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml::Table;
+
+/// A file format configurations can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Extensions in the order they are looked for; the first one of each
+    /// format is the one new files get.
+    const EXTENSIONS: [(&'static str, Format); 4] = [
+        ("toml", Format::Toml),
+        ("json", Format::Json),
+        ("yaml", Format::Yaml),
+        ("yml", Format::Yaml),
+    ];
+
+    /// The format implied by the extension of a path or URL, if any.
+    pub fn from_extension(location: &str) -> Option<Self> {
+        // Query strings and fragments are not part of a URL's file name.
+        let location = location.split(['?', '#']).next().unwrap_or(location);
+        let extension = Path::new(location).extension()?.to_str()?;
+        Self::EXTENSIONS
+            .iter()
+            .find(|(ext, _)| extension.eq_ignore_ascii_case(ext))
+            .map(|(_, format)| *format)
+    }
+
+    /// The format of `content`, read from `origin`: by extension when it
+    /// has a known one, otherwise by looking at the content itself.
+    pub fn detect(origin: &str, content: &str) -> Self {
+        if let Some(format) = Self::from_extension(origin) {
+            return format;
+        }
+        let start = content.trim_start();
+        if start.starts_with('{') || (start.starts_with('[') && !is_toml(content)) {
+            Format::Json
+        } else if is_toml(content) {
+            Format::Toml
+        } else {
+            Format::Yaml
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+fn is_toml(content: &str) -> bool {
+    content.parse::<Table>().is_ok()
+}
+
+/// The file named `stem` with any supported extension in `dir`, or the
+/// TOML one when there is none yet.
+pub fn find(dir: &Path, stem: &str) -> PathBuf {
+    Format::EXTENSIONS
+        .iter()
+        .map(|(ext, _)| dir.join(format!("{}.{}", stem, ext)))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(format!("{}.toml", stem)))
+}
+
+/// Parses `content` in `format` into the TOML data model every other part
+/// of dstatus works with. `null`s are dropped, so they read as unset.
+pub fn parse(content: &str, format: Format, origin: &Path) -> Result<Table> {
+    let value: serde_json::Value = match format {
+        Format::Toml => {
+            return toml::from_str(content).map_err(|e| Error::config_parse(origin, content, e))
+        }
+        Format::Json => serde_json::from_str(content).map_err(|e| Error::ConfigParse {
+            path: origin.to_path_buf(),
+            line: e.line().max(1),
+            column: e.column().max(1),
+            message: strip_position(&e.to_string()),
+        })?,
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| {
+            let (line, column) = e
+                .location()
+                .map_or((1, 1), |location| (location.line(), location.column()));
+            Error::ConfigParse {
+                path: origin.to_path_buf(),
+                line,
+                column,
+                message: strip_position(&e.to_string()),
+            }
+        })?,
+    };
+
+    match toml::Value::try_from(without_nulls(value)) {
+        Ok(toml::Value::Table(table)) => Ok(table),
+        Ok(_) => Err(Error::Config(format!(
+            "{}: expected an object at the top level",
+            origin.display()
+        ))),
+        Err(e) => Err(Error::Config(format!("{}: {}", origin.display(), e))),
+    }
+}
+
+/// Serializes `value` in `format`.
+pub fn to_string(value: &impl Serialize, format: Format) -> Result<String> {
+    let serialize_error =
+        |e: &dyn fmt::Display| Error::Config(format!("Failed to serialize configuration: {}", e));
+    match format {
+        Format::Toml => toml::to_string(value).map_err(|e| serialize_error(&e)),
+        Format::Json => serde_json::to_string_pretty(value)
+            .map(|json| json + "\n")
+            .map_err(|e| serialize_error(&e)),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| serialize_error(&e)),
+    }
+}
+
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+/// serde_json and serde_yaml append " at line X column Y", which
+/// `Error::ConfigParse` already reports.
+fn strip_position(message: &str) -> String {
+    match message.find(" at line ") {
+        Some(at) => message[..at].to_string(),
+        None => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect("a.JSON", ""), Format::Json);
+        assert_eq!(
+            Format::detect("https://example.com/c.yml?raw=1", ""),
+            Format::Yaml
+        );
+        assert_eq!(
+            Format::detect("https://example.com/c", "{\"details\": \"x\"}"),
+            Format::Json
+        );
+        assert_eq!(Format::detect("-", "details = \"x\"\n"), Format::Toml);
+        assert_eq!(
+            Format::detect("-", "[[buttons]]\nlabel = \"a\"\n"),
+            Format::Toml
+        );
+        assert_eq!(Format::detect("-", "details: x\n"), Format::Yaml);
+    }
+
+    #[test]
+    fn test_formats_parse_to_the_same_table() {
+        let toml = "details = \"Coding\"\nparty_size = 2\n[[buttons]]\nlabel = \"Repo\"\nurl = \"https://example.com\"\n";
+        let json = r#"{"details": "Coding", "party_size": 2, "end_timestamp": null,
+            "buttons": [{"label": "Repo", "url": "https://example.com"}]}"#;
+        let yaml = "details: Coding\nparty_size: 2\nbuttons:\n  - label: Repo\n    url: https://example.com\n";
+
+        let origin = Path::new("c");
+        let expected = parse(toml, Format::Toml, origin).unwrap();
+        assert_eq!(parse(json, Format::Json, origin).unwrap(), expected);
+        assert_eq!(parse(yaml, Format::Yaml, origin).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_errors_have_positions() {
+        match parse(
+            "{\n  \"details\": \"x\",\n  oops\n}",
+            Format::Json,
+            Path::new("c.json"),
+        ) {
+            Err(Error::ConfigParse { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        assert!(parse("[1, 2]", Format::Json, Path::new("c.json")).is_err());
+    }
+}
@@ -2,7 +2,11 @@ use tauri::{generate_context, generate_handler, State};
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use crate::config::{Config};
-use std::path::PathBuf;
+use crate::extends;
+use crate::paths::Paths;
+use crate::profile;
+use crate::validation;
+use std::path::{Path, PathBuf};
 
 
 
@@ -38,6 +42,7 @@ struct UpdateManifest {
 
 
 struct AppState {
+    paths: Paths,
     config: Mutex<Config>,
     config_path: Mutex<PathBuf>,
     user_templates: Mutex<Vec<UserTemplate>>,
@@ -51,6 +56,8 @@ async fn get_config(state: State<'_, AppState>) -> Result<Config, String> {
 
 #[tauri::command]
 async fn save_config(config: Config, state: State<'_, AppState>) -> Result<(), String> {
+    config.check().map_err(|e| e.to_string())?;
+
     {
         let mut app_config = state.config.lock().unwrap();
         *app_config = config.clone();
@@ -66,21 +73,72 @@ async fn save_config(config: Config, state: State<'_, AppState>) -> Result<(), S
         .map_err(|e| format!("Failed to save config: {}", e))?;
 
     // Send SIGHUP to daemon to reload config (like CLI does)
-    reload_daemon_config().await?;
+    signal_daemon(&state.paths)?;
 
     Ok(())
 }
 
+#[derive(Serialize)]
+struct ProfileList {
+    profiles: Vec<String>,
+    active: String,
+}
+
+#[tauri::command]
+async fn list_profiles(state: State<'_, AppState>) -> Result<ProfileList, String> {
+    let config_dir = &state.paths.config_dir;
+    Ok(ProfileList {
+        profiles: profile::list(config_dir).map_err(|e| e.to_string())?,
+        active: profile::active(config_dir).map_err(|e| e.to_string())?,
+    })
+}
+
+/// Makes `name` the active profile and pins it, creating it from the
+/// current config if it does not exist yet, and returns its configuration
+/// for editing.
+#[tauri::command]
+async fn use_profile(name: String, state: State<'_, AppState>) -> Result<Config, String> {
+    let config_dir = &state.paths.config_dir;
+    if !profile::exists(config_dir, &name).map_err(|e| e.to_string())? {
+        let current = state.config.lock().unwrap().clone();
+        profile::create(config_dir, &name, &current).map_err(|e| e.to_string())?;
+    }
+    profile::pin(config_dir, &name).map_err(|e| e.to_string())?;
+
+    let config_file = profile::path(config_dir, &name).map_err(|e| e.to_string())?;
+    let config = if config_file.exists() {
+        Config::read_file(&config_file.to_string_lossy()).map_err(|e| e.to_string())?
+    } else {
+        Config::default()
+    };
+    *state.config.lock().unwrap() = config.clone();
+    *state.config_path.lock().unwrap() = config_file;
+
+    signal_daemon(&state.paths)?;
+    Ok(config)
+}
+
+#[tauri::command]
+async fn delete_profile(name: String, state: State<'_, AppState>) -> Result<(), String> {
+    profile::delete(&state.paths.config_dir, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_config(config: Config) -> Result<validation::Report, String> {
+    Ok(validation::validate(&config))
+}
+
 #[tauri::command]
-async fn reload_daemon_config() -> Result<(), String> {
+async fn reload_daemon_config(state: State<'_, AppState>) -> Result<(), String> {
+    signal_daemon(&state.paths)
+}
+
+fn signal_daemon(paths: &Paths) -> Result<(), String> {
     use std::fs;
     use nix::sys::signal::{self, Signal};
     use nix::unistd::Pid;
 
-    let config_dir = crate::get_config_dir();
-    let pid_path = config_dir.join("dstatus.pid");
-
-    if let Ok(pid_str) = fs::read_to_string(pid_path) {
+    if let Ok(pid_str) = fs::read_to_string(paths.pid_file()) {
         if let Ok(pid_val) = pid_str.trim().parse() {
             let pid = Pid::from_raw(pid_val);
             signal::kill(pid, Signal::SIGHUP)
@@ -135,6 +193,11 @@ async fn load_templates() -> Result<Vec<Template>, String> {
 
 #[tauri::command]
 async fn preview_config(config: Config) -> Result<String, String> {
+    let slides = config.rotation.iter().flat_map(|rotation| &rotation.slides);
+    let slides: String = slides
+        .enumerate()
+        .map(|(i, slide)| format!("\nSlide {}: {}", i + 1, slide.summary(&config)))
+        .collect();
     Ok(format!(
         "Discord Rich Presence Preview:\n\
          Details: {}\n\
@@ -142,7 +205,7 @@ async fn preview_config(config: Config) -> Result<String, String> {
          Large Image: {} ({})\n\
          Small Image: {} ({})\n\
          Party: {}/{}\n\
-         Buttons: {}",
+         Buttons: {}{}",
         config.details,
         config.state,
         config.large_image,
@@ -151,22 +214,22 @@ async fn preview_config(config: Config) -> Result<String, String> {
         config.small_text,
         config.party_size,
         config.max_party_size,
-        config.buttons.as_ref().map_or(0, |b| b.len())
+        config.buttons.as_ref().map_or(0, |b| b.len()),
+        slides
     ))
 }
 
 #[tauri::command]
-async fn check_daemon_status() -> Result<bool, String> {
-    let config_dir = crate::get_config_dir();
-    let pid_path = config_dir.join("dstatus.pid");
-    Ok(pid_path.exists())
+async fn check_daemon_status(state: State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.paths.pid_file().exists())
 }
 
 #[tauri::command]
-async fn start_daemon() -> Result<(), String> {
+async fn start_daemon(state: State<'_, AppState>) -> Result<(), String> {
     use std::process::Command;
 
     let _child = Command::new(std::env::current_exe().unwrap())
+        .args(state.paths.child_args())
         .arg("on")
         .spawn()
         .map_err(|e| format!("Failed to start daemon: {}", e))?;
@@ -175,10 +238,11 @@ async fn start_daemon() -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn stop_daemon() -> Result<(), String> {
+async fn stop_daemon(state: State<'_, AppState>) -> Result<(), String> {
     use std::process::Command;
 
     let _output = Command::new(std::env::current_exe().unwrap())
+        .args(state.paths.child_args())
         .arg("off")
         .output()
         .map_err(|e| format!("Failed to stop daemon: {}", e))?;
@@ -195,27 +259,27 @@ async fn get_app_version() -> Result<String, String> {
 
 #[tauri::command]
 async fn load_config_from_source(source: String) -> Result<Config, String> {
-    let toml_content = if source.starts_with("http://") || source.starts_with("https://") {
-        reqwest::get(&source)
-            .await
-            .map_err(|e| format!("Failed to download from '{}': {}", source, e))?
-            .text()
-            .await
-            .map_err(|e| format!("Failed to read response from '{}': {}", source, e))?
-    } else {
-        tokio::fs::read_to_string(&source)
-            .await
-            .map_err(|e| format!("Failed to read configuration file '{}': {}", source, e))?
-    };
-
-    let mut config: Config = toml::from_str(&toml_content)
-        .map_err(|e| format!("Failed to parse TOML from '{}': {}", source, e))?;
-
-    if let Some(buttons) = &mut config.buttons {
-        if buttons.len() > 2 {
-            buttons.truncate(2);
-        }
+    // The loader shared with the CLI is blocking.
+    let from_url = extends::is_url(&source);
+    let mut config = tokio::task::spawn_blocking(move || Config::from_source(&source))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    // A downloaded configuration must not run commands on this machine
+    // unless the user asks for it explicitly.
+    let commands: Vec<&str> = config
+        .sources
+        .iter()
+        .filter(|(_, source)| from_url && source.exec.is_some())
+        .map(|(field, _)| field.as_str())
+        .collect();
+    if !commands.is_empty() {
+        return Err(format!(
+            "This configuration runs commands for {}; load it with 'dstatus load --allow-exec' if you trust it",
+            commands.join(", ")
+        ));
     }
+    config.truncate_buttons();
 
     Ok(config)
 }
@@ -236,11 +300,13 @@ fn calculate_config_hash(config: &Config) -> String {
     config.start_timestamp.hash(&mut hasher);
     config.end_timestamp.hash(&mut hasher);
     config.party_size.hash(&mut hasher);
-    config.party_max.hash(&mut hasher);
+    config.max_party_size.hash(&mut hasher);
     config.match_secret.hash(&mut hasher);
     config.join_secret.hash(&mut hasher);
     config.spectate_secret.hash(&mut hasher);
     config.instance.hash(&mut hasher);
+    config.rotation.hash(&mut hasher);
+    config.media.hash(&mut hasher);
 
     format!("{:x}", hasher.finish())
 }
@@ -267,7 +333,7 @@ async fn save_user_template(template: UserTemplate, state: State<'_, AppState>)
     templates.push(template);
 
     // Save to disk
-    save_user_templates_to_disk(&templates)?;
+    save_user_templates_to_disk(&state.paths.config_dir, &templates)?;
 
     Ok(())
 }
@@ -278,7 +344,7 @@ async fn delete_user_template(template_id: String, state: State<'_, AppState>) -
     templates.retain(|t| t.id != template_id);
 
     // Save to disk
-    save_user_templates_to_disk(&templates)?;
+    save_user_templates_to_disk(&state.paths.config_dir, &templates)?;
 
     Ok(())
 }
@@ -311,19 +377,17 @@ async fn load_user_template(template_id: String, state: State<'_, AppState>) ->
         .map_err(|e| format!("Failed to save config: {}", e))?;
 
     // Send SIGHUP to daemon to reload config
-    reload_daemon_config().await?;
+    signal_daemon(&state.paths)?;
 
     // Save templates to update last_used time
     let templates = state.user_templates.lock().unwrap();
-    save_user_templates_to_disk(&templates)?;
+    save_user_templates_to_disk(&state.paths.config_dir, &templates)?;
 
     Ok(config)
 }
 
-fn save_user_templates_to_disk(templates: &[UserTemplate]) -> Result<(), String> {
-    let config_dir = crate::get_config_dir();
-
-    std::fs::create_dir_all(&config_dir)
+fn save_user_templates_to_disk(config_dir: &Path, templates: &[UserTemplate]) -> Result<(), String> {
+    std::fs::create_dir_all(config_dir)
         .map_err(|e| format!("Failed to create config directory: {}", e))?;
 
     let templates_file = config_dir.join("user_templates.toml");
@@ -346,9 +410,7 @@ fn save_user_templates_to_disk(templates: &[UserTemplate]) -> Result<(), String>
     Ok(())
 }
 
-fn load_user_templates_from_disk() -> Result<Vec<UserTemplate>, String> {
-    let config_dir = crate::get_config_dir();
-
+fn load_user_templates_from_disk(config_dir: &Path) -> Result<Vec<UserTemplate>, String> {
     let templates_file = config_dir.join("user_templates.toml");
 
     if !templates_file.exists() {
@@ -527,26 +589,22 @@ async fn check_for_updates() -> Result<UpdateInfo, String> {
 }
 
 
-pub fn run_gui() -> Result<(), Box<dyn std::error::Error>> {
+pub fn run_gui(paths: Paths) -> Result<(), Box<dyn std::error::Error>> {
     // Load or create default config - use same path as CLI
-    let config_dir = crate::get_config_dir();
-    std::fs::create_dir_all(&config_dir)?;
-
-    let config_file = config_dir.join("configuration.toml");
+    let config_file = paths.config_file()?;
     let config = if config_file.exists() {
-        let content = std::fs::read_to_string(&config_file)?;
-        toml::from_str(&content).unwrap_or_else(|_| Config::default())
+        Config::read_file(&config_file.to_string_lossy()).unwrap_or_else(|_| Config::default())
     } else {
         let default_config = Config::default();
-        let toml_content = toml::to_string_pretty(&default_config)?;
-        std::fs::write(&config_file, toml_content)?;
+        default_config.save_to_file(&config_file.to_string_lossy())?;
         default_config
     };
 
     // Load user templates from disk
-    let user_templates = load_user_templates_from_disk().unwrap_or_else(|_| Vec::new());
+    let user_templates = load_user_templates_from_disk(&paths.config_dir).unwrap_or_else(|_| Vec::new());
 
     let state = AppState {
+        paths,
         config: Mutex::new(config),
         config_path: Mutex::new(config_file),
         user_templates: Mutex::new(user_templates),
@@ -557,6 +615,10 @@ pub fn run_gui() -> Result<(), Box<dyn std::error::Error>> {
         .invoke_handler(generate_handler![
             get_config,
             save_config,
+            validate_config,
+            list_profiles,
+            use_profile,
+            delete_profile,
             load_templates,
             preview_config,
             check_daemon_status,
@@ -0,0 +1,373 @@
+use crate::error::{Error, Result};
+use crate::profile::PROFILES_DIR;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How many previous versions of each file are kept.
+pub const HISTORY_LIMIT: usize = 20;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+/// Length of a formatted timestamp, e.g. `20261018T210000.123Z`.
+const TIMESTAMP_LEN: usize = 20;
+
+const HISTORY_DIR: &str = "history";
+
+/// How many symlinks are followed before giving up on a loop.
+const MAX_LINKS: usize = 40;
+
+/// Tells apart temporary files written by threads of one process.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub saved_at: DateTime<Utc>,
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into
+/// place, so readers see either the old or the new file, never a partial
+/// one. Concurrent writers simply race to the last rename. A symlinked
+/// `path` keeps its link: the file it points to is replaced, keeping its
+/// permissions.
+pub fn atomic_write(path: &Path, content: &str) -> Result<()> {
+    let target = resolve_links(path);
+    let dir = parent_dir(&target);
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| Error::Config(format!("{:?} is not a file path", path)))?
+        .to_string_lossy();
+    let tmp_path = dir.join(format!(
+        ".{}.tmp.{}.{}",
+        file_name,
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let write = || -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        match fs::metadata(&target) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &target)
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(Error::io(format!("Failed to write {:?}", path), e));
+    }
+
+    // Persist the rename itself; not every platform allows syncing a dir.
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// The file `path` ends up pointing to, which need not exist yet.
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match fs::read_link(&path) {
+            Ok(link) => path = parent_dir(&path).join(link),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Replaces `path` with `content`, first copying the current version into
+/// the history directory. Does nothing when the content is unchanged.
+pub fn save_with_history(path: &Path, content: &str) -> Result<()> {
+    match fs::read_to_string(path) {
+        Ok(current) if current == content => return Ok(()),
+        Ok(current) => record(path, &current)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+    atomic_write(path, content)
+}
+
+/// The history directory for `path`: `history/` in the configuration
+/// directory, which is shared by the default profile and the others.
+/// Profiles keep their place under it, as `history/profiles/`, so that
+/// their names never clash with the default profile's.
+pub fn history_dir(path: &Path) -> PathBuf {
+    let dir = parent_dir(path);
+    match dir.parent() {
+        Some(config_dir) if dir.file_name() == Some(OsStr::new(PROFILES_DIR)) => {
+            config_dir.join(HISTORY_DIR).join(PROFILES_DIR)
+        }
+        _ => dir.join(HISTORY_DIR),
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// What the entries of `path` are named after: its file name, extension
+/// included, so that `work.toml` and `work.json` keep separate histories.
+fn key(path: &Path) -> String {
+    path.file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "toml".to_string())
+}
+
+/// Adds `content` to the history of `path` as the version being replaced.
+pub fn record(path: &Path, content: &str) -> Result<()> {
+    let dir = history_dir(path);
+    fs::create_dir_all(&dir)
+        .map_err(|e| Error::io(format!("Failed to create history directory {:?}", dir), e))?;
+
+    let timestamp = Utc::now().format(TIMESTAMP_FORMAT).to_string();
+    let mut entry = dir.join(format!("{}-{}.{}", key(path), timestamp, extension(path)));
+    let mut n = 1;
+    while entry.exists() {
+        entry = dir.join(format!(
+            "{}-{}-{}.{}",
+            key(path),
+            timestamp,
+            n,
+            extension(path)
+        ));
+        n += 1;
+    }
+    atomic_write(&entry, content)?;
+
+    for old in list(path)?.into_iter().skip(HISTORY_LIMIT) {
+        let _ = fs::remove_file(old.path);
+    }
+    Ok(())
+}
+
+/// Saved versions of `path`, newest first.
+pub fn list(path: &Path) -> Result<Vec<Entry>> {
+    let dir = history_dir(path);
+    let prefix = format!("{}-", key(path));
+    // Entries written before the key had the extension in it; their own
+    // extension tells which file they belong to.
+    let legacy = format!("{}-", stem(path));
+    let suffix = format!(".{}", extension(path));
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", dir), e)),
+    };
+
+    // Saves within the same millisecond get a `-N` suffix; order on it too.
+    let mut found: Vec<(Entry, u32)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(&prefix).or_else(|| {
+                name.strip_prefix(&legacy)
+                    .filter(|_| name.ends_with(&suffix))
+            })?;
+            let timestamp = rest.get(..TIMESTAMP_LEN)?;
+            let saved_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+                .ok()?
+                .and_utc();
+            let seq = rest[TIMESTAMP_LEN..]
+                .strip_prefix('-')
+                .and_then(|s| s.split('.').next())
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            Some((
+                Entry {
+                    path: entry.path(),
+                    saved_at,
+                },
+                seq,
+            ))
+        })
+        .collect();
+    found.sort_by(|(a, a_seq), (b, b_seq)| (b.saved_at, b_seq).cmp(&(a.saved_at, a_seq)));
+    Ok(found.into_iter().map(|(entry, _)| entry).collect())
+}
+
+/// Restores the `n`th most recent version (1 = newest) of `path`. The
+/// version being replaced goes into the history too, so a rollback can
+/// itself be undone.
+pub fn rollback(path: &Path, n: usize) -> Result<Entry> {
+    let entries = list(path)?;
+    let entry = n
+        .checked_sub(1)
+        .and_then(|i| entries.get(i))
+        .cloned()
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "No version {} in the history of {:?} ({} saved)",
+                n,
+                path,
+                entries.len()
+            ))
+        })?;
+
+    let content = fs::read_to_string(&entry.path)
+        .map_err(|e| Error::io(format!("Failed to read {:?}", entry.path), e))?;
+    save_with_history(path, &content)?;
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_saves_keep_previous_versions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+
+        save_with_history(&path, "state = \"one\"\n").unwrap();
+        save_with_history(&path, "state = \"two\"\n").unwrap();
+        save_with_history(&path, "state = \"two\"\n").unwrap();
+        save_with_history(&path, "state = \"three\"\n").unwrap();
+
+        let entries = list(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            fs::read_to_string(&entries[0].path).unwrap(),
+            "state = \"two\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&entries[1].path).unwrap(),
+            "state = \"one\"\n"
+        );
+    }
+
+    #[test]
+    fn test_rollback_restores_and_records_current() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        save_with_history(&path, "state = \"one\"\n").unwrap();
+        save_with_history(&path, "state = \"two\"\n").unwrap();
+
+        rollback(&path, 1).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "state = \"one\"\n");
+        assert_eq!(
+            fs::read_to_string(&list(&path).unwrap()[0].path).unwrap(),
+            "state = \"two\"\n"
+        );
+        assert!(rollback(&path, 10).is_err());
+    }
+
+    #[test]
+    fn test_profiles_share_the_history_directory() {
+        let dir = tempdir().unwrap();
+        let profile = dir.path().join(PROFILES_DIR).join("work.toml");
+        fs::create_dir_all(profile.parent().unwrap()).unwrap();
+        save_with_history(&profile, "state = \"one\"\n").unwrap();
+        save_with_history(&profile, "state = \"two\"\n").unwrap();
+
+        let entries = list(&profile).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0]
+            .path
+            .starts_with(dir.path().join(HISTORY_DIR).join(PROFILES_DIR)));
+        assert!(list(&dir.path().join("work.toml")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_formats_keep_separate_histories() {
+        let dir = tempdir().unwrap();
+        let toml = dir.path().join("work.toml");
+        let json = dir.path().join("work.json");
+        save_with_history(&toml, "state = \"one\"\n").unwrap();
+        save_with_history(&toml, "state = \"two\"\n").unwrap();
+        save_with_history(&json, "{\"state\": \"one\"}\n").unwrap();
+        save_with_history(&json, "{\"state\": \"two\"}\n").unwrap();
+        // Named before the extension was part of the key.
+        let history = history_dir(&toml);
+        fs::write(history.join("work-20260101T000000.000Z.toml"), "").unwrap();
+        fs::write(history.join("work-20260101T000000.000Z.json"), "").unwrap();
+
+        let entries = list(&toml).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            fs::read_to_string(&entries[0].path).unwrap(),
+            "state = \"one\"\n"
+        );
+        assert!(entries[1].path.ends_with("work-20260101T000000.000Z.toml"));
+        assert_eq!(list(&json).unwrap().len(), 2);
+
+        rollback(&json, 1).unwrap();
+        assert_eq!(fs::read_to_string(&json).unwrap(), "{\"state\": \"one\"}\n");
+    }
+
+    #[test]
+    fn test_writes_keep_symlinks_and_permissions() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = tempdir().unwrap();
+        let real = dir.path().join("dotfiles").join("dstatus.toml");
+        fs::create_dir_all(real.parent().unwrap()).unwrap();
+        fs::write(&real, "state = \"one\"\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.path().join("configuration.toml");
+        symlink(Path::new("dotfiles").join("dstatus.toml"), &link).unwrap();
+
+        atomic_write(&link, "state = \"two\"\n").unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "state = \"two\"\n");
+        let mode = fs::metadata(&real).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_concurrent_writes_do_not_collide() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        atomic_write(&path, &format!("party_size = {}\n", i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_history_is_pruned() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("configuration.toml");
+        for i in 0..HISTORY_LIMIT + 5 {
+            save_with_history(&path, &format!("party_size = {}\n", i)).unwrap();
+        }
+        assert_eq!(list(&path).unwrap().len(), HISTORY_LIMIT);
+    }
+}
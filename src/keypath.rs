@@ -0,0 +1,251 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use std::path::Path;
+use toml::{Table, Value};
+
+/// One step of a key path such as `buttons[0].url`.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn parse(path: &str) -> Result<Vec<Segment>> {
+    let invalid = || Error::Config(format!("Invalid key path '{}'", path));
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut rest) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        segments.push(Segment::Key(key.to_string()));
+        while !rest.is_empty() {
+            let end = rest.find(']').ok_or_else(invalid)?;
+            let index = rest[1..end].parse().map_err(|_| invalid())?;
+            segments.push(Segment::Index(index));
+            rest = &rest[end + 1..];
+            if !rest.is_empty() && !rest.starts_with('[') {
+                return Err(invalid());
+            }
+        }
+    }
+    match segments.first() {
+        Some(Segment::Key(key)) if Config::field_names().contains(&key.as_str()) => Ok(segments),
+        Some(Segment::Key(key)) => Err(Error::Config(format!("Unknown field '{}'", key))),
+        _ => Err(invalid()),
+    }
+}
+
+fn lookup<'a>(table: &'a Table, segments: &[Segment]) -> Option<&'a Value> {
+    let (Segment::Key(first), rest) = segments.split_first()? else {
+        return None;
+    };
+    let mut value = table.get(first)?;
+    for segment in rest {
+        value = match (segment, value) {
+            (Segment::Key(key), Value::Table(table)) => table.get(key)?,
+            (Segment::Index(index), Value::Array(array)) => array.get(*index)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// The slot `segments` points at, creating missing tables on the way. An
+/// index one past the end of an array appends to it.
+fn slot<'a>(table: &'a mut Table, segments: &[Segment], path: &str) -> Result<&'a mut Value> {
+    let missing = || Error::Config(format!("'{}' does not exist", path));
+    let (Segment::Key(first), rest) = segments.split_first().ok_or_else(missing)? else {
+        return Err(missing());
+    };
+    let mut value = table
+        .entry(first.clone())
+        .or_insert_with(|| empty_for(rest.first()));
+    for (i, segment) in rest.iter().enumerate() {
+        let next = rest.get(i + 1);
+        value = match (segment, value) {
+            (Segment::Key(key), Value::Table(table)) => {
+                table.entry(key.clone()).or_insert_with(|| empty_for(next))
+            }
+            (Segment::Index(index), Value::Array(array)) => {
+                if *index == array.len() {
+                    array.push(empty_for(next));
+                }
+                array.get_mut(*index).ok_or_else(missing)?
+            }
+            _ => return Err(missing()),
+        };
+    }
+    Ok(value)
+}
+
+fn empty_for(next: Option<&Segment>) -> Value {
+    match next {
+        Some(Segment::Index(_)) => Value::Array(Vec::new()),
+        _ => Value::Table(Table::new()),
+    }
+}
+
+/// Reads the value at `path`.
+pub fn get(config: &Config, path: &str) -> Result<Value> {
+    let segments = parse(path)?;
+    lookup(&config.to_table()?, &segments)
+        .cloned()
+        .ok_or_else(|| Error::Config(format!("'{}' is not set", path)))
+}
+
+/// Returns a copy of `config` with `path` set to `raw`. `raw` is read as a
+/// TOML value when that gives the field's type, so `4`, `true` and
+/// `{ label = "Repo", url = "https://…" }` work, and as a plain string
+/// otherwise.
+pub fn set(config: &Config, path: &str, raw: &str) -> Result<Config> {
+    let segments = parse(path)?;
+    let mut last_error = None;
+    for value in candidates(raw) {
+        let mut table = config.to_table()?;
+        *slot(&mut table, &segments, path)? = value.clone();
+        match rebuild(table, &segments, path, &value) {
+            Ok(config) => return Ok(config),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Config(format!("Cannot set '{}'", path))))
+}
+
+/// Returns a copy of `config` with `path` reset: fields go back to their
+/// default and array elements are removed.
+pub fn unset(config: &Config, path: &str) -> Result<Config> {
+    let segments = parse(path)?;
+    let mut table = config.to_table()?;
+    let not_set = || Error::Config(format!("'{}' is not set", path));
+    let (last, parents) = segments.split_last().ok_or_else(not_set)?;
+    let removed = if parents.is_empty() {
+        match last {
+            Segment::Key(key) => table.remove(key),
+            Segment::Index(_) => None,
+        }
+    } else {
+        match (last, lookup_mut(&mut table, parents)) {
+            (Segment::Key(key), Some(Value::Table(parent))) => parent.remove(key),
+            (Segment::Index(index), Some(Value::Array(parent))) if *index < parent.len() => {
+                Some(parent.remove(*index))
+            }
+            _ => None,
+        }
+    };
+    if removed.is_none() {
+        return Err(not_set());
+    }
+
+    Config::from_table(table, Path::new(path))
+}
+
+fn lookup_mut<'a>(table: &'a mut Table, segments: &[Segment]) -> Option<&'a mut Value> {
+    let (Segment::Key(first), rest) = segments.split_first()? else {
+        return None;
+    };
+    let mut value = table.get_mut(first)?;
+    for segment in rest {
+        value = match (segment, value) {
+            (Segment::Key(key), Value::Table(table)) => table.get_mut(key)?,
+            (Segment::Index(index), Value::Array(array)) => array.get_mut(*index)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn candidates(raw: &str) -> Vec<Value> {
+    let mut values = Vec::new();
+    if let Ok(mut parsed) = format!("value = {}", raw).parse::<Table>() {
+        if let Some(value) = parsed.remove("value") {
+            values.push(value);
+        }
+    }
+    values.push(Value::String(raw.to_string()));
+    values
+}
+
+/// Deserializes `table` and checks that the value really landed at `path`,
+/// which catches keys that `Config` silently ignores.
+fn rebuild(table: Table, segments: &[Segment], path: &str, value: &Value) -> Result<Config> {
+    let config = Config::from_table(table, Path::new(path))?;
+    if lookup(&config.to_table()?, segments) != Some(value) {
+        return Err(Error::Config(format!("Unknown field '{}'", path)));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Button;
+
+    fn config() -> Config {
+        Config {
+            details: "Coding".to_string(),
+            buttons: Some(vec![Button {
+                label: "Repo".to_string(),
+                url: "https://example.com".to_string(),
+            }]),
+            end_timestamp: Some(1_700_000_000),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        assert_eq!(get(&config(), "details").unwrap().as_str(), Some("Coding"));
+        assert_eq!(
+            get(&config(), "buttons[0].url").unwrap().as_str(),
+            Some("https://example.com")
+        );
+        assert!(get(&config(), "start_timestamp").is_err());
+        assert!(get(&config(), "detials").is_err());
+    }
+
+    #[test]
+    fn test_set_is_type_checked() {
+        let updated = set(&config(), "state", "Reviewing PRs").unwrap();
+        assert_eq!(updated.state, "Reviewing PRs");
+        // Numbers are accepted as text for string fields...
+        assert_eq!(set(&config(), "state", "42").unwrap().state, "42");
+        // ...but text is rejected for numeric ones.
+        assert_eq!(set(&config(), "party_size", "3").unwrap().party_size, 3);
+        assert!(set(&config(), "party_size", "three").is_err());
+        assert!(set(&config(), "buttons[0].colour", "red").is_err());
+
+        let updated = set(&config(), "buttons[0].url", "https://example.org").unwrap();
+        assert_eq!(updated.buttons.unwrap()[0].url, "https://example.org");
+        let updated = set(
+            &config(),
+            "buttons[1]",
+            r#"{ label = "Docs", url = "https://docs.example.com" }"#,
+        )
+        .unwrap();
+        assert_eq!(updated.buttons.unwrap()[1].label, "Docs");
+        assert!(set(&config(), "buttons[5].url", "https://example.org").is_err());
+    }
+
+    #[test]
+    fn test_unset() {
+        assert_eq!(
+            unset(&config(), "end_timestamp").unwrap().end_timestamp,
+            None
+        );
+        assert_eq!(unset(&config(), "details").unwrap().details, "");
+        assert!(unset(&config(), "buttons[0]")
+            .unwrap()
+            .buttons
+            .unwrap()
+            .is_empty());
+        assert!(unset(&config(), "start_timestamp").is_err());
+    }
+
+    #[test]
+    fn test_rejects_malformed_paths() {
+        for path in ["", "buttons[", "buttons[x]", "buttons[0]x", ".details"] {
+            assert!(parse(path).is_err(), "{:?}", path);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::format::{self, Format};
+use crate::source;
+use crate::validation::{self, FieldIssue};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Default)]
+pub struct FileReport {
+    pub path: String,
+    pub errors: Vec<FieldIssue>,
+    pub warnings: Vec<FieldIssue>,
+}
+
+impl FileReport {
+    fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldIssue {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.warnings.push(FieldIssue {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+}
+
+/// Fields that are still accepted but should be replaced, with the hint
+/// shown to the user.
+const DEPRECATED_FIELDS: &[(&str, &str)] = &[(
+    "party_max",
+    "is deprecated and duplicates max_party_size; use max_party_size instead",
+)];
+
+pub fn lint_file(path: &Path) -> FileReport {
+    match fs::read_to_string(path) {
+        Ok(content) => lint_str(&content, path),
+        Err(e) => {
+            let mut report = FileReport {
+                path: path.display().to_string(),
+                ..FileReport::default()
+            };
+            report.error("", format!("cannot read file: {}", e));
+            report
+        }
+    }
+}
+
+/// Runs the validation rules plus the checks that only make sense on the
+/// raw file: unknown keys, deprecated fields and excess buttons, all of
+/// which `Config::from_file` would otherwise accept silently.
+pub fn lint_str(content: &str, path: &Path) -> FileReport {
+    let mut report = FileReport {
+        path: path.display().to_string(),
+        ..FileReport::default()
+    };
+
+    let format = Format::detect(&path.to_string_lossy(), content);
+    let table = match format::parse(content, format, path) {
+        Ok(table) => table,
+        Err(e) => {
+            report.error("", e.to_string());
+            return report;
+        }
+    };
+
+    let mut lifted = table.clone();
+    source::lift(&mut lifted);
+
+    let mut unknown_keys = Vec::new();
+    let mut on_ignored = |key: serde_ignored::Path| unknown_keys.push(key_path(&key));
+    // TOML is deserialized from the text so that errors point at a line,
+    // unless fields written as commands have to be lifted out first.
+    let typed = match format {
+        Format::Toml if lifted == table => {
+            serde_ignored::deserialize(toml::Deserializer::new(content), &mut on_ignored)
+                .map(|_: Config| ())
+                .map_err(|e| Error::config_parse(path, content, e))
+        }
+        _ => serde_ignored::deserialize(toml::Value::Table(lifted), &mut on_ignored)
+            .map(|_: Config| ())
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e.message()))),
+    };
+    if let Err(e) = typed {
+        report.error("", e.to_string());
+        return report;
+    }
+    for (field, hint) in DEPRECATED_FIELDS {
+        if table.contains_key(*field) {
+            report.warning(*field, *hint);
+        }
+    }
+    for key in unknown_keys {
+        if !DEPRECATED_FIELDS.iter().any(|(field, _)| *field == key) {
+            report.warning(key, "unknown key; it is ignored");
+        }
+    }
+
+    // Validate what the daemon would actually load, after migrations.
+    let config = match Config::parse_migrating(content, path) {
+        Ok((config, _, migration)) => {
+            if migration.is_needed() {
+                report.warning(
+                    "version",
+                    format!(
+                        "schema v{} is outdated (current is v{}); run 'dstatus config migrate'",
+                        migration.from, migration.to
+                    ),
+                );
+            }
+            config
+        }
+        Err(e) => {
+            report.error("", e.to_string());
+            return report;
+        }
+    };
+
+    // Discord rejects an activity with too many buttons wherever they are
+    // set, so the limit validation warns about is an error here.
+    let mut button_lists = vec![("buttons".to_string(), config.buttons.as_ref())];
+    if let Some(rotation) = &config.rotation {
+        for (i, slide) in rotation.slides.iter().enumerate() {
+            let field = format!("rotation.slides[{}].buttons", i);
+            button_lists.push((field, slide.buttons.as_ref()));
+        }
+    }
+    if let Some(set) = config.media.as_ref().and_then(|media| media.set.as_ref()) {
+        button_lists.push(("media.set.buttons".to_string(), set.buttons.as_ref()));
+    }
+    for (field, buttons) in &button_lists {
+        let count = buttons.map_or(0, |b| b.len());
+        if count > validation::MAX_BUTTONS {
+            report.error(
+                field.as_str(),
+                format!(
+                    "{} buttons defined but Discord allows {}; the extra buttons are dropped",
+                    count,
+                    validation::MAX_BUTTONS
+                ),
+            );
+        }
+    }
+
+    let validation = validation::validate(&config);
+    report.errors.extend(validation.errors);
+    // The button limits are already reported as errors above.
+    report.warnings.extend(
+        validation
+            .warnings
+            .into_iter()
+            .filter(|w| !button_lists.iter().any(|(field, _)| *field == w.field)),
+    );
+
+    report
+}
+
+/// Renders an ignored key as `buttons[0].extra`, the notation used by the
+/// validation messages.
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", key_path(parent), index),
+        Path::Map { parent, key } => {
+            let parent = key_path(parent);
+            if parent.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", parent, key)
+            }
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+pub fn print_human(reports: &[FileReport]) {
+    for report in reports {
+        for (severity, issues) in [("error", &report.errors), ("warning", &report.warnings)] {
+            for issue in issues {
+                if issue.field.is_empty() {
+                    println!("{}: {}: {}", report.path, severity, issue.message);
+                } else {
+                    println!(
+                        "{}: {}: {}: {}",
+                        report.path, severity, issue.field, issue.message
+                    );
+                }
+            }
+        }
+    }
+
+    let errors: usize = reports.iter().map(|r| r.errors.len()).sum();
+    let warnings: usize = reports.iter().map(|r| r.warnings.len()).sum();
+    println!(
+        "{} files checked, {} errors, {} warnings",
+        reports.len(),
+        errors,
+        warnings
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+version = 2
+client_id = "1234567890123456789"
+details = "Coding"
+state = "Reviewing PRs"
+large_image = ""
+large_text = ""
+small_image = ""
+small_text = ""
+party_size = 0
+max_party_size = 0
+"#;
+
+    #[test]
+    fn test_valid_file_is_clean() {
+        let report = lint_str(VALID, Path::new("valid.toml"));
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_reports_unknown_and_deprecated_keys() {
+        let content = format!(
+            "{}detials = \"typo\"\nparty_max = 4\n[[buttons]]\nlabel = \"Site\"\nurl = \"https://example.com\"\ncolor = \"red\"\n",
+            VALID
+        );
+        let report = lint_str(&content, Path::new("c.toml"));
+        let fields: Vec<&str> = report.warnings.iter().map(|w| w.field.as_str()).collect();
+        assert_eq!(fields, vec!["party_max", "detials", "buttons[0].color"]);
+    }
+
+    #[test]
+    fn test_extra_buttons_are_errors() {
+        let button = "[[buttons]]\nlabel = \"Site\"\nurl = \"https://example.com\"\n";
+        let content = format!("{}{}{}{}", VALID, button, button, button);
+        let report = lint_str(&content, Path::new("c.toml"));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].field, "buttons");
+        assert!(report.warnings.is_empty());
+
+        let content = format!(
+            "{}[[rotation.slides]]\ndetails = \"Slide\"\n{}{}{}",
+            VALID,
+            button.replace("[[buttons]]", "[[rotation.slides.buttons]]"),
+            button.replace("[[buttons]]", "[[rotation.slides.buttons]]"),
+            button.replace("[[buttons]]", "[[rotation.slides.buttons]]"),
+        );
+        let report = lint_str(&content, Path::new("c.toml"));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].field, "rotation.slides[0].buttons");
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors_are_reported() {
+        let report = lint_str("client_id = ", Path::new("c.toml"));
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].message.contains("line 1"));
+    }
+}
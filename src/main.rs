@@ -1,23 +1,55 @@
 mod connection_state;
+mod control;
+mod doctor;
+mod document;
+mod error;
+mod extends;
+mod format;
+mod history;
+mod keypath;
+mod lint;
+mod media;
+mod migrate;
+mod paths;
+mod processes;
+mod profile;
 mod rich_presence;
+mod rotation;
+mod rules;
+mod schedule;
+mod schema;
+mod shell;
+mod source;
 mod stream_manager;
+mod sysstats;
+mod template;
 mod config;
 mod tui;
+mod validation;
+mod watch;
+mod wrap;
 mod gui;
 
-use clap::{Parser, Subcommand};
-use dirs;
+use clap::{Parser, Subcommand, ValueEnum};
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
 use signal_hook::consts::SIGHUP;
 use signal_hook::iterator::Signals;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::format::Format;
+use crate::paths::Paths;
+use crate::profile::path_str;
+use crate::schedule::Schedule;
+use chrono::Utc;
 use rich_presence::RichPresence;
 
 // Test
@@ -34,57 +66,69 @@ fn display_banner() {
     println!();
 }
 
-fn get_config_dir() -> PathBuf {
-    let mut config_path = dirs::home_dir().expect("Failed to find home directory");
-    config_path.push(".config");
-    config_path.push("dstatus");
-    fs::create_dir_all(&config_path).expect("Failed to create config directory");
-    config_path
+fn home_dir() -> Result<PathBuf> {
+    dirs::home_dir().ok_or_else(|| {
+        Error::io(
+            "Failed to find home directory",
+            std::io::Error::from(ErrorKind::NotFound),
+        )
+    })
 }
 
-fn get_pid_path() -> PathBuf {
-    get_config_dir().join("dstatus.pid")
+/// Asks a running daemon to reload its configuration. Does nothing when no
+/// daemon is running.
+fn reload_daemon(paths: &Paths) -> Result<()> {
+    if let Ok(pid_str) = fs::read_to_string(paths.pid_file()) {
+        if let Ok(pid_val) = pid_str.trim().parse() {
+            let pid = Pid::from_raw(pid_val);
+            let _ = signal::kill(pid, Signal::SIGHUP);
+        }
+    }
+    Ok(())
 }
 
-fn get_log_path() -> PathBuf {
-    get_config_dir().join("dstatus.log")
+fn remove_pid_file(paths: &Paths) -> Result<()> {
+    let pid_path = paths.pid_file();
+    match fs::remove_file(&pid_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(Error::io(format!("Failed to remove PID file {:?}", pid_path), e))
+        }
+        _ => Ok(()),
+    }
 }
 
-fn install_man_page() {
+fn install_man_page() -> Result<()> {
     let man_content = include_str!("../dstatus.1");
 
-    let mut man_dir = dirs::home_dir().expect("Failed to find home directory");
-    man_dir.push(".local");
-    man_dir.push("share");
-    man_dir.push("man");
-    man_dir.push("man1");
+    let local_man_path = home_dir()?.join(".local").join("share").join("man");
+    let man_dir = local_man_path.join("man1");
 
-    if let Err(e) = fs::create_dir_all(&man_dir) {
-        eprintln!("Failed to create man directory: {}", e);
-        std::process::exit(1);
-    }
+    fs::create_dir_all(&man_dir)
+        .map_err(|e| Error::io("Failed to create man directory", e))?;
 
     let man_file = man_dir.join("dstatus.1");
-    if let Err(e) = fs::write(&man_file, man_content) {
-        eprintln!("Failed to write man page: {}", e);
-        std::process::exit(1);
-    }
+    fs::write(&man_file, man_content).map_err(|e| Error::io("Failed to write man page", e))?;
 
     println!("Man page installed to: {}", man_file.display());
     println!("You can now run: man dstatus");
 
     let manpath = std::env::var("MANPATH").unwrap_or_default();
-    let local_man_path = format!("{}/.local/share/man", dirs::home_dir().unwrap().display());
 
-    if !manpath.contains(&local_man_path) {
+    if !manpath.contains(&*local_man_path.to_string_lossy()) {
         println!();
         println!("Note: To ensure 'man dstatus' works, add this to your shell profile:");
         println!("export MANPATH=\"$HOME/.local/share/man:$MANPATH\"");
     }
+
+    Ok(())
 }
 
 #[derive(Parser)]
 struct Args {
+    /// Directory holding the configuration, PID file and logs, instead of
+    /// the XDG directories
+    #[arg(long, global = true, env = paths::CONFIG_DIR_ENV, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -96,24 +140,223 @@ enum Commands {
     /// Stops the Rich Presence daemon
     Off,
     /// Creates a new configuration file
-    Configure,
+    Configure {
+        /// Profile to edit (defaults to the active profile)
+        #[arg(long)]
+        profile: Option<String>,
+    },
     /// Launch the graphical user interface
     Gui,
     /// Loads a configuration file from the specified path or URL
     Load {
-        /// Path to the configuration.toml file to load or URL to download from
+        /// Path to a TOML, JSON or YAML configuration file, or URL to download from
         source: String,
+        /// Keep the fields of a downloaded configuration that run shell commands
+        #[arg(long)]
+        allow_exec: bool,
     },
+    /// Prints the active configuration, with any `extends` bases merged in
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = Format::Toml)]
+        format: Format,
+    },
+    /// Prints the JSON Schema of the configuration file
+    Schema,
     /// Shows the daemon logs
     Logs,
     /// Updates dstatus to the latest version
     Update,
     /// Installs the man page to user-local directory
     InstallMan,
+    /// Checks the configuration, Discord connection and installation
+    Doctor,
+    /// Checks configuration files and templates for errors
+    Lint {
+        /// Configuration files to check
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+        /// Treat warnings as errors
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+    /// Manages the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manages named configuration profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Inspects the schedule that picks profiles by the time of day
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommands,
+    },
+    /// Inspects the rules that pick the presence by conditions
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommands,
+    },
+    /// Runs a command, showing it in Discord until it exits
+    Run {
+        /// First line of the activity (defaults to the command line)
+        #[arg(long)]
+        details: Option<String>,
+        /// Second line of the activity
+        #[arg(long)]
+        state: Option<String>,
+        /// Asset key or URL of the large image
+        #[arg(long)]
+        large_image: Option<String>,
+        /// Asset key or URL of the small image
+        #[arg(long)]
+        small_image: Option<String>,
+        /// Show how the command exited once it is done
+        #[arg(long)]
+        show_status: bool,
+        /// How long the exit status stays shown
+        #[arg(long, default_value = "10s", value_name = "DURATION")]
+        linger: String,
+        /// Command to run, after `--`
+        #[arg(required = true, last = true)]
+        command: Vec<String>,
+    },
+    /// Prints the hook that reports a shell's directory and command to
+    /// the daemon, for {project}, {branch} and {command}
+    ShellInit {
+        #[arg(value_enum)]
+        shell: shell::Shell,
+    },
+    /// Reports a shell's directory and command to the daemon; run by the
+    /// shell hook
+    #[command(hide = true)]
+    ShellReport {
+        #[arg(long)]
+        pid: u32,
+        #[arg(long)]
+        cwd: PathBuf,
+        #[arg(long)]
+        command: Option<String>,
+    },
     #[command(hide = true)]
     InternalRun,
 }
 
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Upgrades a configuration file to the current schema version
+    Migrate {
+        /// File to migrate (defaults to the active configuration)
+        file: Option<PathBuf>,
+        /// Show what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Prints a field, e.g. `details` or `buttons[0].url`
+    Get { key: String },
+    /// Sets a field, checking the value against the field's type
+    Set { key: String, value: String },
+    /// Resets a field to its default, or removes an array element
+    Unset { key: String },
+    /// Prints the effective configuration after merging `extends` bases
+    Resolve {
+        /// File to resolve (defaults to the active configuration)
+        file: Option<PathBuf>,
+    },
+    /// Downloads the `extends` bases given as URLs again
+    Refresh,
+    /// Lists the saved previous versions of the configuration
+    History,
+    /// Restores a previous version of the configuration
+    Rollback {
+        /// Which version to restore, as numbered by 'dstatus config history'
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// Lists the profiles, marking the active one
+    List,
+    /// Creates an empty profile
+    New { name: String },
+    /// Makes a profile active and pins it, so that rules and the schedule
+    /// leave it active until it is released
+    Use { name: String },
+    /// Releases the pinned profile, letting rules and the schedule switch
+    /// profiles again
+    Release,
+    /// Creates a profile as a copy of another
+    Copy { from: String, to: String },
+    /// Deletes a profile
+    Delete { name: String },
+    /// Prints a profile (defaults to the active profile)
+    Show { name: Option<String> },
+}
+
+#[derive(Subcommand)]
+enum ScheduleCommands {
+    /// Shows which rule is in effect at a time, and when the next one starts
+    Explain {
+        /// Time to check, such as 2026-10-18T21:00, in the schedule's
+        /// timezone (defaults to now)
+        #[arg(long)]
+        at: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RulesCommands {
+    /// Checks every rule against the system now and shows what they decide
+    Test,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// Commands whose output is meant for other programs skip the banner.
+fn wants_banner(command: &Commands) -> bool {
+    !matches!(
+        command,
+        Commands::Lint {
+            format: OutputFormat::Json,
+            ..
+        } | Commands::Export { .. }
+            | Commands::Schema
+            | Commands::Gui
+            | Commands::Run { .. }
+            | Commands::ShellInit { .. }
+            | Commands::ShellReport { .. }
+            | Commands::Profile {
+            command: ProfileCommands::Show { .. }
+        } | Commands::Config {
+            command: ConfigCommands::Resolve { .. }
+                | ConfigCommands::Get { .. }
+                | ConfigCommands::Set { .. }
+                | ConfigCommands::Unset { .. }
+        }
+    )
+}
+
+/// Commands that rewrite the schema file when it is out of date. Editors
+/// read it from disk, so it should match the version that runs the daemon.
+fn installs_schema(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Schema | Commands::Configure { .. } | Commands::InternalRun
+    )
+}
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
@@ -122,183 +365,170 @@ fn main() {
         .map(|path| path.to_string_lossy().contains(".app/Contents/MacOS/"))
         .unwrap_or(false);
 
-    // Show GUI if:
-    // 1. No arguments AND launched from .app bundle, OR
-    // 2. Explicitly requested with "gui" command
-    if (args.len() == 1 && is_app_bundle) || (args.len() > 1 && args[1] == "gui") {
-        main_gui();
+    // Show the GUI when launched from the .app bundle without arguments;
+    // `dstatus gui` goes through the CLI so that it takes `--config-dir`.
+    let result = if args.len() == 1 && is_app_bundle {
+        Paths::resolve(None).and_then(|paths| main_gui(&paths))
     } else {
-        main_cli();
+        main_cli()
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
     }
 }
 
-fn main_gui() {
-    if let Err(e) = gui::run_gui() {
-        eprintln!("Failed to start GUI: {}", e);
-        std::process::exit(1);
-    }
+fn main_gui(paths: &Paths) -> Result<()> {
+    paths.create_dirs()?;
+    gui::run_gui(paths.clone()).map_err(|e| Error::Other(format!("Failed to start GUI: {}", e)))
 }
 
-fn main_cli() {
-    display_banner();
+fn main_cli() -> Result<()> {
     let args = Args::parse();
+    if wants_banner(&args.command) {
+        display_banner();
+    }
+    let paths = &Paths::resolve(args.config_dir)?;
+    // Shell hooks run this at every prompt; it only talks to the daemon.
+    if !matches!(args.command, Commands::ShellReport { .. }) {
+        paths.create_dirs()?;
+    }
+    if installs_schema(&args.command) {
+        if let Err(e) = schema::install(&paths.config_dir) {
+            eprintln!("Warning: {}", e);
+        }
+    }
 
     match args.command {
         Commands::On => {
-            if let Ok(pid_str) = fs::read_to_string(get_pid_path()) {
-                eprintln!("Daemon is already running with PID {}", pid_str);
-                return;
+            let pid_path = paths.pid_file();
+            if let Ok(pid_str) = fs::read_to_string(&pid_path) {
+                return Err(Error::Daemon(format!(
+                    "Daemon is already running with PID {}",
+                    pid_str.trim()
+                )));
             }
 
-            let log_file = fs::File::create(get_log_path()).expect("Failed to create log file");
-            let stderr = log_file.try_clone().expect("Failed to clone log file handle");
+            let log_path = paths.log_file();
+            let log_file = fs::File::create(&log_path)
+                .map_err(|e| Error::io(format!("Failed to create log file {:?}", log_path), e))?;
+            let stderr = log_file
+                .try_clone()
+                .map_err(|e| Error::io("Failed to clone log file handle", e))?;
+            let exe = std::env::current_exe()
+                .map_err(|e| Error::io("Failed to locate the dstatus executable", e))?;
 
-            let child = Command::new(std::env::current_exe().unwrap())
+            let child = Command::new(exe)
+                .args(paths.child_args())
                 .arg("internal-run")
                 .stdout(log_file)
                 .stderr(stderr)
                 .envs(std::env::vars())
                 .spawn()
-                .expect("Failed to spawn daemon");
+                .map_err(|e| Error::io("Failed to spawn daemon", e))?;
 
-            fs::write(get_pid_path(), child.id().to_string()).expect("Failed to write PID file");
+            fs::write(&pid_path, child.id().to_string())
+                .map_err(|e| Error::io(format!("Failed to write PID file {:?}", pid_path), e))?;
             println!("Daemon started with PID {}", child.id());
         }
         Commands::Off => {
-            if let Ok(pid_str) = fs::read_to_string(get_pid_path()) {
-                if let Ok(pid_val) = pid_str.trim().parse() {
-                    let pid = Pid::from_raw(pid_val);
-                    if signal::kill(pid, Signal::SIGTERM).is_ok() {
-                        fs::remove_file(get_pid_path()).unwrap();
-                        println!("Daemon stopped");
-                    } else {
-                        eprintln!("Failed to stop daemon. It may have already been stopped.");
-                        fs::remove_file(get_pid_path()).unwrap();
+            let pid_str = fs::read_to_string(paths.pid_file())
+                .map_err(|_| Error::Daemon("Daemon is not running".to_string()))?;
+            remove_pid_file(paths)?;
+
+            let pid_val = pid_str
+                .trim()
+                .parse()
+                .map_err(|_| Error::Daemon("Invalid PID file. Removed it.".to_string()))?;
+            if signal::kill(Pid::from_raw(pid_val), Signal::SIGTERM).is_err() {
+                return Err(Error::Daemon(
+                    "Failed to stop daemon. It may have already been stopped.".to_string(),
+                ));
+            }
+            // Killed daemons leave their socket behind.
+            let _ = fs::remove_file(paths.control_socket());
+            println!("Daemon stopped");
+        }
+        Commands::Configure { profile } => {
+            let config_file = match profile {
+                Some(name) => {
+                    let config_dir = &paths.config_dir;
+                    if !profile::exists(config_dir, &name)? {
+                        return Err(Error::Config(format!(
+                            "Profile '{}' does not exist; create it with 'dstatus profile new {}'",
+                            name, name
+                        )));
                     }
-                } else {
-                    eprintln!("Invalid PID file. Removing it.");
-                    fs::remove_file(get_pid_path()).unwrap();
+                    profile::path(config_dir, &name)?
                 }
-            } else {
-                eprintln!("Daemon is not running");
-            }
-        }
-        Commands::Configure => {
-            let config_file = get_config_dir().join("configuration.toml");
-            let config = Config::from_file(config_file.to_str().unwrap()).unwrap_or_else(|_| {
-                Config {
-                    name: "".to_string(),
-                    description: "".to_string(),
-                    client_id: "".to_string(),
-                    details: "".to_string(),
-                    state: "".to_string(),
-                    large_image: "".to_string(),
-                    large_text: "".to_string(),
-                    small_image: "".to_string(),
-                    small_text: "".to_string(),
-                    party_size: 0,
-                    max_party_size: 0,
-                    buttons: None,
-                    start_timestamp: None,
-                    end_timestamp: None,
-                    party_max: None,
-                    match_secret: None,
-                    join_secret: None,
-                    spectate_secret: None,
-                    instance: None,
-                }
-            });
-            let updated_config = tui::run_tui(config).unwrap();
-            updated_config
-                .save_to_file(config_file.to_str().unwrap())
-                .unwrap();
-
-            if let Ok(pid_str) = fs::read_to_string(get_pid_path()) {
-                if let Ok(pid_val) = pid_str.trim().parse() {
-                    let pid = Pid::from_raw(pid_val);
-                    let _ = signal::kill(pid, Signal::SIGHUP);
+                None => paths.config_file()?,
+            };
+            let config = match Config::read_file(path_str(&config_file)?) {
+                Ok(config) => config,
+                Err(Error::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                    Config::default()
                 }
-            }
+                Err(e) => return Err(e),
+            };
+            let Some(updated_config) =
+                tui::run_tui(config).map_err(|e| Error::io("Terminal UI failed", e))?
+            else {
+                println!("Left {:?} unchanged", config_file);
+                return Ok(());
+            };
+            updated_config.save_to_file(path_str(&config_file)?)?;
+
+            reload_daemon(paths)?;
 
             println!("Configuration saved to {:?}", config_file);
         }
         Commands::Gui => {
-            if let Err(e) = gui::run_gui() {
-                eprintln!("Failed to start GUI: {}", e);
-                std::process::exit(1);
-            }
-        }
-        Commands::Load { source } => {
-            let toml_content = if source.starts_with("http://") || source.starts_with("https://") {
-                match reqwest::blocking::get(&source) {
-                    Ok(response) => {
-                        if !response.status().is_success() {
-                            eprintln!("Error: Failed to download from '{}': HTTP {}", source, response.status());
-                            std::process::exit(1);
-                        }
-                        match response.text() {
-                            Ok(content) => {
-                                println!("Downloaded configuration from '{}'", source);
-                                content
-                            }
-                            Err(e) => {
-                                eprintln!("Error: Failed to read response from '{}': {}", source, e);
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error: Failed to download from '{}': {}", source, e);
-                        std::process::exit(1);
-                    }
-                }
-            } else {
-                let path = std::path::Path::new(&source);
-                if !path.exists() {
-                    eprintln!("Error: Configuration file '{}' does not exist", source);
-                    std::process::exit(1);
-                }
-                match fs::read_to_string(path) {
-                    Ok(content) => content,
-                    Err(e) => {
-                        eprintln!("Error: Failed to read configuration file '{}': {}", source, e);
-                        std::process::exit(1);
+            main_gui(paths)?;
+        }
+        Commands::Load { source, allow_exec } => {
+            let mut config = Config::from_source(&source)?;
+            if extends::is_url(&source) {
+                println!("Downloaded configuration from '{}'", source);
+                if !allow_exec {
+                    for field in config.strip_exec() {
+                        eprintln!(
+                            "Warning: {}: left out the command it runs; use --allow-exec to keep it",
+                            field
+                        );
                     }
                 }
-            };
-
-            match toml::from_str::<Config>(&toml_content) {
-                Ok(mut config) => {
-                    if let Some(buttons) = &mut config.buttons {
-                        if buttons.len() > 2 {
-                            buttons.truncate(2);
-                        }
-                    }
+            }
+            // A relative base only makes sense next to the original file.
+            if config
+                .extends
+                .as_deref()
+                .is_some_and(|target| !extends::is_portable(target))
+            {
+                config.extends = None;
+            }
+            for warning in config.check()?.warnings {
+                eprintln!("Warning: {}: {}", warning.field, warning.message);
+            }
+            config.truncate_buttons();
 
-                    let config_file = get_config_dir().join("configuration.toml");
+            let config_file = paths.config_file()?;
+            config.save_to_file(path_str(&config_file)?)?;
 
-                    if let Err(e) = config.save_to_file(config_file.to_str().unwrap()) {
-                        eprintln!("Error: Failed to save configuration: {}", e);
-                        std::process::exit(1);
-                    }
+            reload_daemon(paths)?;
 
-                    if let Ok(pid_str) = fs::read_to_string(get_pid_path()) {
-                        if let Ok(pid_val) = pid_str.trim().parse() {
-                            let pid = Pid::from_raw(pid_val);
-                            let _ = signal::kill(pid, Signal::SIGHUP);
-                        }
-                    }
-
-                    println!("Configuration loaded from '{}' and saved to {:?}", source, config_file);
-                }
-                Err(e) => {
-                    eprintln!("Error: Failed to parse TOML configuration from '{}': {}", source, e);
-                    std::process::exit(1);
-                }
-            }
+            println!("Configuration loaded from '{}' and saved to {:?}", source, config_file);
+        }
+        Commands::Export { format } => {
+            let mut config = Config::from_source(path_str(&paths.config_file()?)?)?;
+            config.extends = None;
+            print!("{}", config.to_string_as(format)?);
+        }
+        Commands::Schema => {
+            print!("{}", schema::generate());
         }
         Commands::Logs => {
-            let log_path = get_log_path();
+            let log_path = paths.log_file();
             if let Ok(logs) = fs::read_to_string(log_path) {
                 println!("{}", logs);
             } else {
@@ -310,88 +540,1005 @@ fn main_cli() {
 
             let install_script_url = "https://raw.githubusercontent.com/HudsonGraeme/dstatus-rs/main/scripts/install.sh";
 
-            let output = Command::new("curl")
+            let curl_output = Command::new("curl")
                 .args(["-sSL", install_script_url])
-                .output();
+                .output()
+                .map_err(|e| {
+                    Error::io("curl command failed. Please make sure curl is installed and try again", e)
+                })?;
+            if !curl_output.status.success() {
+                return Err(Error::Network("Failed to download install script".to_string()));
+            }
+            let install_script = String::from_utf8_lossy(&curl_output.stdout);
 
-            match output {
-                Ok(curl_output) if curl_output.status.success() => {
-                    let install_script = String::from_utf8_lossy(&curl_output.stdout);
+            let mut bash_process = Command::new("bash")
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .map_err(|e| Error::io("Failed to start bash process", e))?;
 
-                    let mut bash_process = Command::new("bash")
-                        .stdin(std::process::Stdio::piped())
-                        .stdout(std::process::Stdio::piped())
-                        .stderr(std::process::Stdio::piped())
-                        .spawn()
-                        .expect("Failed to start bash process");
+            if let Some(stdin) = bash_process.stdin.as_mut() {
+                stdin
+                    .write_all(install_script.as_bytes())
+                    .map_err(|e| Error::io("Failed to write to bash stdin", e))?;
+            }
 
-                    if let Some(stdin) = bash_process.stdin.as_mut() {
-                        stdin.write_all(install_script.as_bytes()).expect("Failed to write to bash stdin");
-                    }
+            let install_output = bash_process
+                .wait_with_output()
+                .map_err(|e| Error::io("Failed to wait for bash process", e))?;
 
-                    let install_output = bash_process.wait_with_output().expect("Failed to wait for bash process");
+            if !install_output.status.success() {
+                return Err(Error::Other(format!(
+                    "Update failed:\n{}",
+                    String::from_utf8_lossy(&install_output.stderr)
+                )));
+            }
+            println!("✓ Successfully updated dstatus!");
+            println!("{}", String::from_utf8_lossy(&install_output.stdout));
+        }
+        Commands::InstallMan => {
+            install_man_page()?;
+        }
+        Commands::Doctor => {
+            let checks = doctor::run(paths);
+            doctor::print_report(&checks);
+            let failed = checks.iter().filter(|c| c.status == doctor::Status::Fail).count();
+            if failed > 0 {
+                return Err(Error::Other(format!("{} checks failed", failed)));
+            }
+        }
+        Commands::Lint {
+            files,
+            format,
+            deny_warnings,
+        } => {
+            let reports: Vec<lint::FileReport> =
+                files.iter().map(|file| lint::lint_file(file)).collect();
+            match format {
+                OutputFormat::Human => lint::print_human(&reports),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&reports)
+                        .map_err(|e| Error::Other(format!("Failed to serialize report: {}", e)))?
+                ),
+            }
 
-                    if install_output.status.success() {
-                        println!("✓ Successfully updated dstatus!");
-                        println!("{}", String::from_utf8_lossy(&install_output.stdout));
-                    } else {
-                        eprintln!("✗ Update failed:");
-                        eprintln!("{}", String::from_utf8_lossy(&install_output.stderr));
-                        std::process::exit(1);
-                    }
+            let errors: usize = reports.iter().map(|r| r.errors.len()).sum();
+            let warnings: usize = reports.iter().map(|r| r.warnings.len()).sum();
+            if errors > 0 || (deny_warnings && warnings > 0) {
+                return Err(Error::Config(format!(
+                    "lint found {} errors and {} warnings",
+                    errors, warnings
+                )));
+            }
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Migrate { file, dry_run } => {
+                let file = match file {
+                    Some(file) => file,
+                    None => paths.config_file()?,
+                };
+                migrate_config(paths, &file, dry_run)?;
+            }
+            ConfigCommands::Get { key } => {
+                let config = Config::read_file(path_str(&paths.config_file()?)?)?;
+                match keypath::get(&config, &key)? {
+                    toml::Value::String(text) => println!("{}", text),
+                    value => println!("{}", value),
                 }
-                Ok(_) => {
-                    eprintln!("✗ Failed to download install script");
-                    std::process::exit(1);
+            }
+            ConfigCommands::Set { key, value } => {
+                let updated = edit_config(paths, |config| keypath::set(config, &key, &value))?;
+                println!("{} = {}", key, keypath::get(&updated, &key)?);
+            }
+            ConfigCommands::Unset { key } => {
+                edit_config(paths, |config| keypath::unset(config, &key))?;
+                println!("Unset {}", key);
+            }
+            ConfigCommands::Resolve { file } => {
+                let file = match file {
+                    Some(file) => file,
+                    None => paths.config_file()?,
+                };
+                let mut config = Config::from_source(path_str(&file)?)?;
+                config.extends = None;
+                print!("{}", config.to_string_as(Format::Toml)?);
+            }
+            ConfigCommands::Refresh => {
+                let config_file = paths.config_file()?;
+                let fetched = extends::refresh(&config_file)?;
+                if fetched.is_empty() {
+                    println!("{:?} extends no URL", config_file);
                 }
-                Err(e) => {
-                    eprintln!("✗ curl command failed: {}", e);
-                    eprintln!("Please make sure curl is installed and try again.");
-                    std::process::exit(1);
+                for url in &fetched {
+                    println!("Downloaded {}", url);
+                }
+                reload_daemon(paths)?;
+            }
+            ConfigCommands::History => {
+                let config_file = paths.config_file()?;
+                let entries = history::list(&config_file)?;
+                if entries.is_empty() {
+                    println!("No previous versions of {:?}", config_file);
+                }
+                for (i, entry) in entries.iter().enumerate() {
+                    println!(
+                        "{:>3}  {}  {}",
+                        i + 1,
+                        entry
+                            .saved_at
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S"),
+                        entry.path.display()
+                    );
                 }
             }
+            ConfigCommands::Rollback { n } => {
+                let config_file = paths.config_file()?;
+                let entry = history::rollback(&config_file, n)?;
+                println!(
+                    "Restored the version saved at {}",
+                    entry
+                        .saved_at
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                );
+                reload_daemon(paths)?;
+            }
+        },
+        Commands::Profile { command } => profile_command(paths, command)?,
+        Commands::Schedule { command } => schedule_command(paths, command)?,
+        Commands::Rules { command } => rules_command(paths, command)?,
+        Commands::Run {
+            details,
+            state,
+            large_image,
+            small_image,
+            show_status,
+            linger,
+            command,
+        } => {
+            let options = wrap::Options {
+                activity: rotation::Slide {
+                    details: Some(details.unwrap_or_else(|| wrap::default_details(&command))),
+                    state,
+                    large_image,
+                    small_image,
+                    ..rotation::Slide::default()
+                },
+                show_status,
+                linger: source::parse_duration(&linger)?,
+                command,
+            };
+            let code = wrap::run(paths, options)?;
+            std::process::exit(code);
         }
-        Commands::InstallMan => {
-            install_man_page();
+        Commands::ShellInit { shell } => print!("{}", shell::init_script(shell)),
+        Commands::ShellReport { pid, cwd, command } => {
+            // Shells keep reporting while the daemon is off.
+            if let Some(mut client) = control::Client::connect(&paths.control_socket())? {
+                client.report(&shell::Report { pid, cwd, command })?;
+            }
         }
         Commands::InternalRun => {
-            if let Err(e) = run() {
-                eprintln!("Error: {:?}", e);
-                fs::remove_file(get_pid_path()).unwrap();
-                std::process::exit(1);
+            if let Err(e) = run(paths) {
+                remove_pid_file(paths)?;
+                return Err(e);
             }
         }
     }
+
+    Ok(())
 }
 
-fn run() -> anyhow::Result<()> {
-    let mut signals = Signals::new(&[SIGHUP])?;
-    let config_file = get_config_dir().join("configuration.toml");
+/// Applies `edit` to the active configuration, refusing results that
+/// Discord would reject, then saves and reloads the daemon.
+fn edit_config(paths: &Paths, edit: impl FnOnce(&Config) -> Result<Config>) -> Result<Config> {
+    let config_file = paths.config_file()?;
+    let config = match Config::read_file(path_str(&config_file)?) {
+        Ok(config) => config,
+        Err(Error::Io { source, .. }) if source.kind() == ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(e),
+    };
+    let updated = edit(&config)?;
+    for warning in updated.check()?.warnings {
+        eprintln!("Warning: {}: {}", warning.field, warning.message);
+    }
+    updated.save_to_file(path_str(&config_file)?)?;
+    reload_daemon(paths)?;
+    Ok(updated)
+}
+
+fn schedule_command(paths: &Paths, command: ScheduleCommands) -> Result<()> {
+    let config_dir = &paths.config_dir;
+    match command {
+        ScheduleCommands::Explain { at } => {
+            let path = schedule::path(config_dir);
+            let schedule = schedule::load(config_dir)?.ok_or_else(|| {
+                Error::Config(format!(
+                    "No schedule at {:?}; see 'man dstatus' for how to write one",
+                    path
+                ))
+            })?;
+            let at = match at {
+                Some(text) => schedule.resolve(&text)?,
+                None => Utc::now(),
+            };
+            println!("Schedule {:?} ({})", path, schedule.timezone());
+            println!("At {}:", schedule.display(at));
+            let matching = schedule.matching(at);
+            for (i, rule) in schedule.rules().iter().enumerate() {
+                let marker = if Some(i) == matching { "*" } else { " " };
+                let name = rule
+                    .name
+                    .as_ref()
+                    .map_or(String::new(), |name| format!(" \"{}\"", name));
+                let missing = match &rule.action {
+                    schedule::Action::Profile(name) if !profile::exists(config_dir, name)? => {
+                        " (no such profile)"
+                    }
+                    _ => "",
+                };
+                println!(
+                    "{} {}.{} {}: {}{}",
+                    marker,
+                    i + 1,
+                    name,
+                    rule.describe(),
+                    rule.action,
+                    missing
+                );
+            }
+            match matching {
+                Some(i) => println!("Rule {} applies: {}", i + 1, schedule.rules()[i].action),
+                None => println!("No rule applies; the active profile is left as it is"),
+            }
+            match schedule.next_change(at) {
+                Some(next) => {
+                    let action = schedule
+                        .matching(next)
+                        .map_or("no rule applies".to_string(), |i| {
+                            format!("rule {} ({})", i + 1, schedule.rules()[i].action)
+                        });
+                    println!("Next change: {} -> {}", schedule.display(next), action);
+                }
+                None => println!("No change within the next week"),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rules_command(paths: &Paths, command: RulesCommands) -> Result<()> {
+    let config_dir = &paths.config_dir;
+    match command {
+        RulesCommands::Test => {
+            let path = rules::path(config_dir);
+            let rules = rules::load(config_dir)?.ok_or_else(|| {
+                Error::Config(format!(
+                    "No rules at {:?}; see 'man dstatus' for how to write them",
+                    path
+                ))
+            })?;
+            // `env` conditions see the daemon's environment, not this shell's.
+            let proc_root = Path::new(processes::PROC_ROOT);
+            let daemon_env = fs::read_to_string(paths.pid_file())
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+                .and_then(|pid| processes::environ(proc_root, pid));
+            let from_daemon = daemon_env.is_some();
+            let env = daemon_env.unwrap_or_else(|| std::env::vars().collect());
+            let facts = rules::Facts::gather(proc_root, env);
+            let evaluations = rules.evaluate(&facts);
+            println!("Rules {:?} ({})", path, rules.timezone());
+            if !from_daemon {
+                println!("No daemon is running; env conditions use this shell's environment");
+            }
+            println!("At {}:", rules.display(facts.now));
+            for (i, (rule, evaluation)) in rules.rules().iter().zip(&evaluations).enumerate() {
+                let marker = if evaluation.matched { "*" } else { " " };
+                let name = rule
+                    .name
+                    .as_ref()
+                    .map_or(String::new(), |name| format!(" \"{}\"", name));
+                let missing = match &rule.profile {
+                    Some(name) if !profile::exists(config_dir, name)? => " (no such profile)",
+                    _ => "",
+                };
+                println!(
+                    "{} {}.{} priority {}: {}{}",
+                    marker,
+                    i + 1,
+                    name,
+                    rule.priority,
+                    rule.actions(),
+                    missing
+                );
+                if evaluation.checks.is_empty() {
+                    println!("      always matches");
+                }
+                for check in &evaluation.checks {
+                    let held = if check.held { "yes" } else { "no " };
+                    println!("      {} {}: {}", held, check.condition, check.detail);
+                }
+                for condition in &evaluation.skipped {
+                    println!("       -  {}: not checked", condition);
+                }
+            }
+
+            let (outcome, reasons) = rules.decide(&evaluations);
+            if reasons.is_empty() {
+                println!("No rule matches");
+            } else {
+                println!("Decided by the rules:");
+                for (part, i) in &reasons {
+                    let part = match *part {
+                        "profile" => {
+                            format!("profile '{}'", outcome.profile.as_deref().unwrap_or(""))
+                        }
+                        "clear" if outcome.clear == Some(true) => "clear the presence".to_string(),
+                        "clear" => "show the presence".to_string(),
+                        field => format!("set {}", field),
+                    };
+                    println!("  {} (rule {})", part, i + 1);
+                }
+            }
+            let left: Vec<&str> = [
+                ("the profile", outcome.profile.is_none()),
+                ("the fields", outcome.set.is_none()),
+                ("whether to clear", outcome.clear.is_none()),
+            ]
+            .into_iter()
+            .filter(|(_, left)| *left)
+            .map(|(part, _)| part)
+            .collect();
+            if !left.is_empty() {
+                println!(
+                    "Left to the process rules and the schedule: {}",
+                    left.join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn profile_command(paths: &Paths, command: ProfileCommands) -> Result<()> {
+    let config_dir = &paths.config_dir;
+    match command {
+        ProfileCommands::List => {
+            let active = profile::active(config_dir)?;
+            let pinned = profile::pinned(config_dir)?;
+            for name in profile::list(config_dir)? {
+                let marker = if name == active { "*" } else { " " };
+                if pinned.as_ref() == Some(&name) {
+                    println!("{} {} (pinned)", marker, name);
+                } else {
+                    println!("{} {}", marker, name);
+                }
+            }
+        }
+        ProfileCommands::New { name } => {
+            let path = profile::create(config_dir, &name, &Config::default())?;
+            println!("Created profile '{}' at {:?}", name, path);
+            println!("Edit it with: dstatus configure --profile {}", name);
+        }
+        ProfileCommands::Use { name } => {
+            profile::pin(config_dir, &name)?;
+            reload_daemon(paths)?;
+            println!("Switched to profile '{}'", name);
+            println!("Rules and the schedule leave it active until 'dstatus profile release'");
+        }
+        ProfileCommands::Release => {
+            if profile::release(config_dir)? {
+                reload_daemon(paths)?;
+                println!("Released the pinned profile");
+            } else {
+                println!("No profile is pinned");
+            }
+        }
+        ProfileCommands::Copy { from, to } => {
+            let path = profile::copy(config_dir, &from, &to)?;
+            println!("Copied profile '{}' to '{}' at {:?}", from, to, path);
+        }
+        ProfileCommands::Delete { name } => {
+            profile::delete(config_dir, &name)?;
+            println!("Deleted profile '{}'", name);
+        }
+        ProfileCommands::Show { name } => {
+            let name = match name {
+                Some(name) => name,
+                None => profile::active(config_dir)?,
+            };
+            let path = profile::path(config_dir, &name)?;
+            let content = fs::read_to_string(&path).map_err(|e| match e.kind() {
+                ErrorKind::NotFound if profile::exists(config_dir, &name).unwrap_or(false) => {
+                    Error::Config(format!(
+                        "Profile '{}' has no configuration yet; run 'dstatus configure'",
+                        name
+                    ))
+                }
+                ErrorKind::NotFound => Error::Config(format!(
+                    "Profile '{}' does not exist; see 'dstatus profile list'",
+                    name
+                )),
+                _ => Error::io(format!("Failed to read {:?}", path), e),
+            })?;
+            println!("# Profile '{}' ({})", name, path.display());
+            print!("{}", content);
+        }
+    }
+    Ok(())
+}
+
+fn migrate_config(paths: &Paths, file: &Path, dry_run: bool) -> Result<()> {
+    let content = fs::read_to_string(file)
+        .map_err(|e| Error::io(format!("Failed to read {:?}", file), e))?;
+    let (config, table, migration) = Config::parse_migrating(&content, file)?;
+
+    if !migration.is_needed() {
+        println!(
+            "{:?} is already at schema version {}",
+            file,
+            migrate::CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{:?}: schema v{} → v{}",
+        file, migration.from, migration.to
+    );
+    for change in &migration.changes {
+        println!("  {}", change);
+    }
+
+    // Writing the merged table would flatten the base into the overlay.
+    if config.extends.is_some() {
+        println!(
+            "{:?} extends another file, so it is only migrated in memory; make the changes above by hand to update it",
+            file
+        );
+        return Ok(());
+    }
+
+    if dry_run {
+        let migrated = toml::to_string(&table)
+            .map_err(|e| Error::Config(format!("Failed to serialize configuration: {}", e)))?;
+        println!();
+        println!("Would write:");
+        println!("{}", migrated);
+        return Ok(());
+    }
+
+    // Loading performs the migration and writes the backup.
+    Config::read_file(path_str(file)?)?;
+    println!(
+        "Migrated. The original was saved to {}.v{}.bak",
+        file.display(),
+        migration.from
+    );
+
+    reload_daemon(paths)
+}
+
+/// Why the daemon loop woke up before its next update.
+enum Reload {
+    Signal,
+    Changed(Vec<PathBuf>),
+    /// The output of a command behind a field changed.
+    Output,
+    /// The schedule switched the active profile.
+    Schedule,
+    /// The process rule that applies changed.
+    Processes,
+    /// What `rules.toml` decides changed.
+    Rules,
+    /// A media player started, changed or stopped a track.
+    Media,
+    /// What the shells report changed.
+    Shell,
+    /// Another command held or released an activity.
+    Control(control::Event),
+}
+
+/// The files that make up the active configuration: the profile pointer
+/// and pin, the schedule and rules files, the profile's file and the local
+/// bases it extends.
+fn watched_files(paths: &Paths) -> Vec<PathBuf> {
+    let mut files = vec![
+        profile::pointer_path(&paths.config_dir),
+        profile::pin_path(&paths.config_dir),
+        schedule::path(&paths.config_dir),
+        processes::path(&paths.config_dir),
+        rules::path(&paths.config_dir),
+        shell::path(&paths.config_dir),
+    ];
+    if let Ok(config_file) = paths.config_file() {
+        files.extend(extends::local_chain(&config_file));
+    }
+    files
+}
+
+/// Applies the rule in effect now, if it is not the one last applied.
+/// Rules only apply as they start. The profile is left alone while it is
+/// `pinned`, by hand or by `rules.toml`. Returns whether the activity
+/// should be cleared, when a new rule took effect.
+fn follow_schedule(
+    schedule: &Schedule,
+    applied: &mut Option<Option<usize>>,
+    config_dir: &Path,
+    pinned: bool,
+) -> Option<bool> {
+    let matching = schedule.matching(Utc::now());
+    if *applied == Some(matching) {
+        return None;
+    }
+    *applied = Some(matching);
+    let Some(rule) = matching.map(|i| &schedule.rules()[i]) else {
+        return Some(false);
+    };
+    println!("Schedule: {} ({})", rule.action, rule.describe());
+    if let schedule::Action::Profile(name) = &rule.action {
+        if !pinned && profile::active(config_dir).ok().as_ref() != Some(name) {
+            if let Err(e) = profile::set_active(config_dir, name) {
+                eprintln!("Schedule could not switch to profile '{}': {}", name, e);
+            }
+        }
+    }
+    Some(rule.action == schedule::Action::Clear)
+}
+
+/// Applies the process rule that matched, or undoes the last one. A rule's
+/// profile stays active while the rule matches, and the profile that was
+/// active before comes back afterwards; neither happens while a profile is
+/// `pinned`, by hand or by `rules.toml`. Returns whether the active profile
+/// changed.
+fn follow_processes(
+    rules: Option<&processes::Rules>,
+    matched: Option<&processes::Match>,
+    restore: &mut Option<String>,
+    config_dir: &Path,
+    presence: &mut RichPresence,
+    pinned: bool,
+) -> bool {
+    let rule = rules
+        .zip(matched)
+        .map(|(rules, matched)| (&rules.rules()[matched.rule], rules.overrides(matched)));
+    let target = match rule {
+        Some((rule, overrides)) => {
+            println!(
+                "Process rule: {} ({})",
+                rule.describe(),
+                matched.map_or("", |matched| &matched.process)
+            );
+            presence.set_overrides(overrides);
+            match &rule.profile {
+                Some(name) => {
+                    if restore.is_none() {
+                        *restore = profile::active(config_dir).ok();
+                    }
+                    Some(name.clone())
+                }
+                None => restore.take(),
+            }
+        }
+        None => {
+            presence.set_overrides(None);
+            restore.take()
+        }
+    };
+    match target {
+        Some(target) if !pinned => switch_profile(config_dir, &target),
+        _ => false,
+    }
+}
+
+/// Applies what `rules.toml` decided, leaving the profile alone while one
+/// is `pinned` by hand; see `rules::Switcher`. Returns whether the active
+/// profile changed.
+fn follow_rules(
+    outcome: rules::Outcome,
+    switcher: &mut rules::Switcher,
+    pinned: bool,
+    config_dir: &Path,
+    presence: &mut RichPresence,
+) -> bool {
+    presence.set_rules(outcome.set, outcome.clear);
+    let active = profile::active(config_dir).ok();
+    match switcher.follow(outcome.profile, active, pinned) {
+        Some(target) => {
+            println!("Rules: profile '{}'", target);
+            switch_profile(config_dir, &target)
+        }
+        None => false,
+    }
+}
+
+/// Makes `name` the active profile. Returns whether it was not already.
+fn switch_profile(config_dir: &Path, name: &str) -> bool {
+    if profile::active(config_dir).ok().as_deref() == Some(name) {
+        return false;
+    }
+    match profile::set_active(config_dir, name) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Could not switch to profile '{}': {}", name, e);
+            false
+        }
+    }
+}
+
+/// When the schedule next switches rules, as a deadline for the loop.
+fn next_switch(schedule: &Option<Schedule>) -> Option<Instant> {
+    let now = Utc::now();
+    let next = schedule.as_ref()?.next_change(now)?;
+    Some(Instant::now() + (next - now).to_std().ok()?)
+}
+
+fn run(paths: &Paths) -> Result<()> {
+    let mut signals =
+        Signals::new([SIGHUP]).map_err(|e| Error::io("Failed to install signal handler", e))?;
+    let config_dir = &paths.config_dir;
 
     tracing_subscriber::registry()
         .with(fmt::layer().with_target(true).with_ansi(false))
         .with(EnvFilter::from_default_env())
         .init();
 
-    let mut config = Config::from_file(config_file.to_str().unwrap())?;
+    // The schedule goes first, since it may pick the profile, unless one
+    // was pinned by hand.
+    let mut manual = profile::pinned(config_dir)?;
+    let mut schedule = schedule::load(config_dir)?;
+    let mut applied = None;
+    let pinned = manual.is_some();
+    let cleared = schedule
+        .as_ref()
+        .and_then(|schedule| follow_schedule(schedule, &mut applied, config_dir, pinned));
+
+    let mut profile_name = profile::active(config_dir)?;
+    let mut config = Config::from_file(path_str(&profile::path(config_dir, &profile_name)?)?)?;
+    println!("Using profile '{}'", profile_name);
     let mut presence = RichPresence::new(config.clone());
-    presence.start()?;
+    presence.set_profile(&profile_name);
+    presence.set_cleared(cleared.unwrap_or(false));
+    presence.start().map_err(Error::ipc)?;
+
+    let (tx, rx) = mpsc::channel();
+    let signal_tx = tx.clone();
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP && signal_tx.send(Reload::Signal).is_err() {
+                break;
+            }
+        }
+    });
+    let output_tx = tx.clone();
+    let start_sources = move |config: &Config| {
+        let output_tx = output_tx.clone();
+        source::Runner::start(&config.sources, move || {
+            let _ = output_tx.send(Reload::Output);
+        })
+    };
+    let mut sources = start_sources(&config);
+    presence.set_outputs(sources.outputs());
+    let media_tx = tx.clone();
+    let start_media = move |config: &Config| {
+        let media_tx = media_tx.clone();
+        let media = config.media.clone()?;
+        Some(media::Watcher::start(media, move || {
+            let _ = media_tx.send(Reload::Media);
+        }))
+    };
+    let mut media_watcher = start_media(&config);
+    let schedule_tx = tx.clone();
+    let processes_tx = tx.clone();
+    let start_processes = move |rules: &Option<processes::Rules>| {
+        let processes_tx = processes_tx.clone();
+        let rules = rules.clone()?;
+        Some(processes::Watcher::start(
+            rules,
+            PathBuf::from(processes::PROC_ROOT),
+            move || {
+                let _ = processes_tx.send(Reload::Processes);
+            },
+        ))
+    };
+    let mut process_rules = processes::load(config_dir)?;
+    let mut process_watcher = start_processes(&process_rules);
+    let mut restore = None;
+    let rules_tx = tx.clone();
+    let start_rules = move |rule_set: &Option<rules::Rules>| {
+        let rules_tx = rules_tx.clone();
+        let rule_set = rule_set.clone()?;
+        Some(rules::Watcher::start(
+            rule_set,
+            PathBuf::from(processes::PROC_ROOT),
+            move || {
+                let _ = rules_tx.send(Reload::Rules);
+            },
+        ))
+    };
+    let mut rule_set = rules::load(config_dir)?;
+    let mut rules_watcher = start_rules(&rule_set);
+    let mut switcher = rules::Switcher::default();
+    let control_tx = tx.clone();
+    let _control = control::Server::start(&paths.control_socket(), move |event| {
+        let _ = control_tx.send(Reload::Control(event));
+    })?;
+    let shell_tx = tx.clone();
+    let mut privacy = shell::load(config_dir)?;
+    let shell_tracker = shell::Tracker::start(privacy.clone().unwrap_or_default(), move || {
+        let _ = shell_tx.send(Reload::Shell);
+    });
+    // Holds stack up; the latest one is shown.
+    let mut holds: Vec<(u64, control::Hold)> = Vec::new();
+    let mut watcher = watch::FileWatcher::new(move |files| {
+        let _ = tx.send(Reload::Changed(files));
+    })?;
+    watcher.watch(&watched_files(paths));
 
+    // Placeholders such as {time} change on their own, so the activity is
+    // rendered on every tick; it is only sent when the result changed.
+    let interval = Duration::from_secs(15);
     loop {
-        for signal in signals.pending() {
-            if signal == SIGHUP {
-                println!("Reloading configuration...");
-                match Config::from_file(config_file.to_str().unwrap()) {
-                    Ok(new_config) => {
-                        config = new_config;
-                        presence.update_config(config.clone());
+        let pinned = manual.is_some() || switcher.applied().is_some();
+        let followed = schedule
+            .as_ref()
+            .and_then(|schedule| follow_schedule(schedule, &mut applied, config_dir, pinned));
+        if let Some(cleared) = followed {
+            presence.set_cleared(cleared);
+            let _ = schedule_tx.send(Reload::Schedule);
+        }
+        presence.set_activity().map_err(Error::ipc)?;
+        let next_update = Instant::now() + interval;
+
+        // Slides, rate-limited updates and the schedule may be due before
+        // the next tick.
+        while let Some(timeout) = [presence.wake_at(), next_switch(&schedule)]
+            .into_iter()
+            .flatten()
+            .fold(next_update, Instant::min)
+            .checked_duration_since(Instant::now())
+        {
+            let reason = match rx.recv_timeout(timeout) {
+                Ok(reason) => reason,
+                Err(_) => break,
+            };
+            match reason {
+                Reload::Signal => println!("Reloading configuration..."),
+                Reload::Changed(files) => println!("Configuration changed: {:?}", files),
+                Reload::Output => {
+                    presence.set_activity().map_err(Error::ipc)?;
+                    continue;
+                }
+                Reload::Media => {
+                    presence.set_track(media_watcher.as_ref().and_then(|w| w.current()));
+                    presence.set_activity().map_err(Error::ipc)?;
+                    continue;
+                }
+                Reload::Shell => {
+                    presence.set_shell(shell_tracker.current());
+                    presence.set_activity().map_err(Error::ipc)?;
+                    continue;
+                }
+                Reload::Schedule => {}
+                Reload::Processes => {
+                    let matched = process_watcher.as_ref().and_then(|w| w.current());
+                    let switched = follow_processes(
+                        process_rules.as_ref(),
+                        matched.as_ref(),
+                        &mut restore,
+                        config_dir,
+                        &mut presence,
+                        manual.is_some() || switcher.applied().is_some(),
+                    );
+                    if !switched {
+                        presence.set_activity().map_err(Error::ipc)?;
+                        continue;
+                    }
+                }
+                Reload::Rules => {
+                    let outcome = rules_watcher
+                        .as_ref()
+                        .map(rules::Watcher::current)
+                        .unwrap_or_default();
+                    let switched = follow_rules(
+                        outcome,
+                        &mut switcher,
+                        manual.is_some(),
+                        config_dir,
+                        &mut presence,
+                    );
+                    if !switched {
+                        presence.set_activity().map_err(Error::ipc)?;
+                        continue;
                     }
-                    Err(e) => eprintln!("Failed to reload config: {}", e),
+                }
+                Reload::Control(event) => {
+                    match event {
+                        control::Event::Hold(id, hold) => {
+                            match holds.iter_mut().find(|(held, _)| *held == id) {
+                                Some(entry) => entry.1 = hold,
+                                None => holds.push((id, hold)),
+                            }
+                        }
+                        control::Event::Release(id) => holds.retain(|(held, _)| *held != id),
+                        control::Event::Shell(report) => {
+                            shell_tracker.report(report);
+                            continue;
+                        }
+                    }
+                    presence.set_held(holds.last().map(|(_, hold)| hold.clone()));
+                    presence.set_activity().map_err(Error::ipc)?;
+                    continue;
                 }
             }
-        }
 
-        presence.set_activity()?;
-        std::thread::sleep(std::time::Duration::from_secs(15));
+            // A pin outranks the rules, process rules and schedule; once it
+            // is released, they apply what they pick now.
+            let pin = profile::pinned(config_dir).unwrap_or_else(|e| {
+                eprintln!("Failed to read the pinned profile: {}", e);
+                None
+            });
+            if pin != manual {
+                match &pin {
+                    Some(name) => println!("Profile '{}' pinned", name),
+                    None => println!("Profile released"),
+                }
+                manual = pin;
+                // What a process rule replaced is not brought back over a
+                // pinned profile.
+                if manual.is_some() {
+                    restore = None;
+                }
+                let outcome = rules_watcher
+                    .as_ref()
+                    .map(rules::Watcher::current)
+                    .unwrap_or_default();
+                follow_rules(
+                    outcome,
+                    &mut switcher,
+                    manual.is_some(),
+                    config_dir,
+                    &mut presence,
+                );
+                let pinned = manual.is_some() || switcher.applied().is_some();
+                let matched = process_watcher.as_ref().and_then(|w| w.current());
+                follow_processes(
+                    process_rules.as_ref(),
+                    matched.as_ref(),
+                    &mut restore,
+                    config_dir,
+                    &mut presence,
+                    pinned,
+                );
+                applied = None;
+                let followed = schedule.as_ref().and_then(|schedule| {
+                    follow_schedule(schedule, &mut applied, config_dir, pinned)
+                });
+                presence.set_cleared(followed.unwrap_or(false));
+            }
+
+            match schedule::load(config_dir) {
+                Ok(reloaded) if reloaded != schedule => {
+                    println!("Schedule changed");
+                    schedule = reloaded;
+                    applied = None;
+                    let pinned = manual.is_some() || switcher.applied().is_some();
+                    let followed = schedule.as_ref().and_then(|schedule| {
+                        follow_schedule(schedule, &mut applied, config_dir, pinned)
+                    });
+                    presence.set_cleared(followed.unwrap_or(false));
+                    presence.set_activity().map_err(Error::ipc)?;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to reload the schedule, keeping the previous one: {}", e),
+            }
+            match processes::load(config_dir) {
+                Ok(reloaded) if reloaded != process_rules => {
+                    println!("Process rules changed");
+                    process_rules = reloaded;
+                    // The new watcher reports its first scan on its own.
+                    process_watcher = start_processes(&process_rules);
+                    if process_watcher.is_none() {
+                        let pinned = manual.is_some() || switcher.applied().is_some();
+                        follow_processes(
+                            None,
+                            None,
+                            &mut restore,
+                            config_dir,
+                            &mut presence,
+                            pinned,
+                        );
+                        presence.set_activity().map_err(Error::ipc)?;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!(
+                    "Failed to reload the process rules, keeping the previous ones: {}",
+                    e
+                ),
+            }
+            match rules::load(config_dir) {
+                Ok(reloaded) if reloaded != rule_set => {
+                    println!("Rules changed");
+                    rule_set = reloaded;
+                    // The new watcher reports its first check on its own.
+                    rules_watcher = start_rules(&rule_set);
+                    if rules_watcher.is_none() {
+                        follow_rules(
+                            rules::Outcome::default(),
+                            &mut switcher,
+                            manual.is_some(),
+                            config_dir,
+                            &mut presence,
+                        );
+                        presence.set_activity().map_err(Error::ipc)?;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!(
+                    "Failed to reload the rules, keeping the previous ones: {}",
+                    e
+                ),
+            }
+
+            match shell::load(config_dir) {
+                Ok(reloaded) if reloaded != privacy => {
+                    println!("Shell settings changed");
+                    privacy = reloaded;
+                    shell_tracker.set_privacy(privacy.clone().unwrap_or_default());
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!(
+                    "Failed to reload the shell settings, keeping the previous ones: {}",
+                    e
+                ),
+            }
+
+            // The active profile may have changed as well as its contents.
+            let reloaded = profile::active(config_dir).and_then(|name| {
+                let config_file = profile::path(config_dir, &name)?;
+                Ok((name, Config::from_file(path_str(&config_file)?)?))
+            });
+            // Whatever happened, the set of files may be different now.
+            watcher.watch(&watched_files(paths));
+            let (name, new_config) = match reloaded {
+                Ok(reloaded) => reloaded,
+                // Keep showing the last good configuration until the file is
+                // fixed; a half-saved edit must not take the presence down.
+                Err(e) => {
+                    eprintln!("Failed to reload config, keeping the previous one: {}", e);
+                    continue;
+                }
+            };
+
+            if name != profile_name {
+                println!("Switched to profile '{}'", name);
+                presence.set_profile(&name);
+                profile_name = name;
+            } else if new_config.to_table().ok() == config.to_table().ok() {
+                continue;
+            }
+            let client_changed = new_config.client_id != config.client_id;
+            if new_config.sources != config.sources {
+                sources = start_sources(&new_config);
+                presence.set_outputs(sources.outputs());
+            }
+            if new_config.media != config.media {
+                // The new watcher reports the track it finds on its own.
+                media_watcher = start_media(&new_config);
+                presence.set_track(None);
+            }
+            config = new_config;
+            presence.update_config(config.clone());
+            // The handshake binds the connection to one application.
+            if client_changed {
+                presence.start().map_err(Error::ipc)?;
+            }
+            presence.set_activity().map_err(Error::ipc)?;
+        }
     }
 }
@@ -0,0 +1,563 @@
+use crate::config::Config;
+use crate::rotation::Slide;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use zbus::blocking::fdo::DBusProxy;
+use zbus::blocking::{proxy, Connection, Proxy};
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Discord's activity type for "Listening to".
+pub const LISTENING: u8 = 2;
+
+/// How often the players are asked what they play.
+const POLL: Duration = Duration::from_secs(2);
+/// How far, in seconds, the position may drift from where the track
+/// should be before it counts as a seek.
+const DRIFT: i64 = 2;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const MPD_PORT: u16 = 6600;
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Shows the track a media player is playing as a Listening activity, and
+/// nothing while playback is paused:
+///
+/// ```toml
+/// [media]
+/// players = ["spotify"]
+/// mpd = "localhost:6600"
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Media {
+    /// MPRIS players to follow, by the name they take on the session bus,
+    /// such as `spotify` or `vlc`. Every player when left out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub players: Vec<String>,
+    /// Whether to follow players on the D-Bus session bus. Defaults to
+    /// true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpris: Option<bool>,
+    /// MPD server to follow, as `host`, `host:port` or a socket path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpd: Option<String>,
+    /// Fields shown while a track plays, over `{title}`, `{artist}` and
+    /// `{album}` in the first line, second line and large image tooltip.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set: Option<Slide>,
+}
+
+/// A track that is playing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// Unix timestamp the track started at, counting back from its
+    /// position.
+    pub started: i64,
+    /// Unix timestamp the track ends at; unknown for streams.
+    pub ends: Option<i64>,
+    /// The MPRIS name of the player, or `mpd`.
+    pub player: String,
+}
+
+impl Media {
+    fn follows_mpris(&self) -> bool {
+        self.mpris.unwrap_or(true)
+    }
+
+    /// Puts the fields for `track` over `config`, with timers matching its
+    /// position.
+    pub fn apply(&self, track: &Track, config: &mut Config) {
+        let defaults = Slide {
+            details: Some("{title}".to_string()),
+            state: Some("{artist}".to_string()),
+            large_text: Some("{album}".to_string()),
+            ..Slide::default()
+        };
+        match &self.set {
+            Some(set) => set.clone().or(&defaults).apply(config),
+            None => defaults.apply(config),
+        }
+        config.start_timestamp = Some(track.started);
+        config.end_timestamp = track.ends;
+    }
+}
+
+/// The players a `Media` section follows, with what polling them needs
+/// to remember.
+struct Players {
+    media: Media,
+    /// Connected on first use, and dropped when it fails.
+    bus: Option<Connection>,
+    /// Whether MPD failed last time, so that failing is only reported once.
+    mpd_failed: bool,
+}
+
+impl Players {
+    fn new(media: Media) -> Self {
+        Self {
+            media,
+            bus: None,
+            mpd_failed: false,
+        }
+    }
+
+    /// The track playing now: the first MPRIS player that plays, then
+    /// MPD.
+    fn playing(&mut self) -> Option<Track> {
+        if self.media.follows_mpris() {
+            if self.bus.is_none() {
+                self.bus = Connection::session()
+                    .inspect_err(|e| debug!("No session bus: {}", e))
+                    .ok();
+            }
+            if let Some(connection) = &self.bus {
+                match read_mpris(connection, &self.media.players) {
+                    Ok(Some(track)) => return Some(track),
+                    Ok(None) => {}
+                    Err(e) => {
+                        debug!("Failed to ask the MPRIS players: {}", e);
+                        self.bus = None;
+                    }
+                }
+            }
+        }
+        let address = self.media.mpd.as_deref()?;
+        match read_mpd(address) {
+            Ok(track) => {
+                self.mpd_failed = false;
+                track
+            }
+            Err(e) => {
+                if !self.mpd_failed {
+                    warn!("Failed to ask MPD at {}: {}", address, e);
+                }
+                self.mpd_failed = true;
+                None
+            }
+        }
+    }
+}
+
+impl Track {
+    fn new(
+        player: &str,
+        title: String,
+        artist: String,
+        album: String,
+        position: Duration,
+        length: Option<Duration>,
+    ) -> Self {
+        let started = Utc::now().timestamp() - position.as_secs() as i64;
+        Self {
+            title,
+            artist,
+            album,
+            started,
+            ends: length
+                .filter(|length| !length.is_zero())
+                .map(|length| started + length.as_secs() as i64),
+            player: player.to_string(),
+        }
+    }
+
+    /// Whether `other` is this track at about the same position, so that
+    /// showing it again would change nothing.
+    fn same(&self, other: &Self) -> bool {
+        (&self.title, &self.artist, &self.album, &self.player)
+            == (&other.title, &other.artist, &other.album, &other.player)
+            && (self.started - other.started).abs() <= DRIFT
+            && self.ends.is_some() == other.ends.is_some()
+    }
+
+    /// The track as the log shows it.
+    fn describe(&self) -> String {
+        match self.artist.as_str() {
+            "" => format!("{} ({})", self.title, self.player),
+            artist => format!("{} by {} ({})", self.title, artist, self.player),
+        }
+    }
+}
+
+/// The track of the first player on the bus that is playing one, among
+/// `players` when any are given.
+fn read_mpris(connection: &Connection, players: &[String]) -> zbus::Result<Option<Track>> {
+    let mut names: Vec<String> = DBusProxy::new(connection)?
+        .list_names()?
+        .into_iter()
+        .map(|name| name.to_string())
+        .filter(|name| name.starts_with(MPRIS_PREFIX))
+        .collect();
+    names.sort();
+    for name in names {
+        // Players may add an instance to their name, as in `vlc.instance42`.
+        let player = name[MPRIS_PREFIX.len()..]
+            .split('.')
+            .next()
+            .unwrap_or_default();
+        if !players.is_empty() && !players.iter().any(|p| p.eq_ignore_ascii_case(player)) {
+            continue;
+        }
+        // Each poll reads afresh; a cache would keep a stale position.
+        let proxy: Proxy = proxy::Builder::new(connection)
+            .destination(name.as_str())?
+            .path(MPRIS_PATH)?
+            .interface(MPRIS_PLAYER)?
+            .cache_properties(CacheProperties::No)
+            .build()?;
+        // A player that quit in the meantime is skipped.
+        let Ok(status) = proxy.get_property::<String>("PlaybackStatus") else {
+            continue;
+        };
+        if status != "Playing" {
+            continue;
+        }
+        let metadata: HashMap<String, OwnedValue> =
+            proxy.get_property("Metadata").unwrap_or_default();
+        let title = text(&metadata, "xesam:title")
+            .or_else(|| {
+                let url = text(&metadata, "xesam:url")?;
+                Some(url.rsplit('/').next()?.to_string())
+            })
+            .unwrap_or_default();
+        // Both are in microseconds; not every player reports a position.
+        let position = proxy.get_property::<i64>("Position").unwrap_or_default();
+        let length = metadata.get("mpris:length").and_then(|v| integer(v));
+        return Ok(Some(Track::new(
+            player,
+            title,
+            text(&metadata, "xesam:artist").unwrap_or_default(),
+            text(&metadata, "xesam:album").unwrap_or_default(),
+            Duration::from_micros(position.max(0) as u64),
+            length.map(|length| Duration::from_micros(length.max(0) as u64)),
+        )));
+    }
+    Ok(None)
+}
+
+/// A metadata string, or a list of them such as the artists, joined.
+fn text(metadata: &HashMap<String, OwnedValue>, key: &str) -> Option<String> {
+    fn strings(value: &Value, out: &mut Vec<String>) {
+        match value {
+            Value::Str(s) => out.push(s.to_string()),
+            Value::Array(array) => array.iter().for_each(|v| strings(v, out)),
+            Value::Value(inner) => strings(inner, out),
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    strings(metadata.get(key)?, &mut out);
+    out.retain(|s| !s.is_empty());
+    (!out.is_empty()).then(|| out.join(", "))
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::I64(n) => Some(*n),
+        Value::U64(n) => i64::try_from(*n).ok(),
+        Value::I32(n) => Some(i64::from(*n)),
+        Value::U32(n) => Some(i64::from(*n)),
+        Value::Value(inner) => integer(inner),
+        _ => None,
+    }
+}
+
+/// The track MPD at `address` plays, if it is playing.
+fn read_mpd(address: &str) -> io::Result<Option<Track>> {
+    if address.starts_with('/') {
+        let stream = UnixStream::connect(address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        return ask_mpd(stream);
+    }
+    let address = match address.contains(':') {
+        true => address.to_string(),
+        false => format!("{}:{}", address, MPD_PORT),
+    };
+    let resolved = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not resolve", address),
+        )
+    })?;
+    let stream = TcpStream::connect_timeout(&resolved, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    ask_mpd(stream)
+}
+
+fn ask_mpd<S: Read + Write>(stream: S) -> io::Result<Option<Track>> {
+    let mut stream = BufReader::new(stream);
+    let mut greeting = String::new();
+    stream.read_line(&mut greeting)?;
+    if !greeting.starts_with("OK MPD ") {
+        return Err(io::Error::other(format!(
+            "not an MPD server: {}",
+            greeting.trim()
+        )));
+    }
+    let status = mpd_command(&mut stream, "status")?;
+    let field = |pairs: &[(String, String)], key: &str| {
+        let values: Vec<&str> = pairs
+            .iter()
+            .filter(|(k, v)| k == key && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+            .collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    };
+    if field(&status, "state").as_deref() != Some("play") {
+        return Ok(None);
+    }
+    let seconds = |value: Option<String>| {
+        let seconds: f64 = value?.parse().ok()?;
+        Duration::try_from_secs_f64(seconds).ok()
+    };
+    let position = seconds(field(&status, "elapsed")).unwrap_or_default();
+    let length = seconds(field(&status, "duration"));
+    let song = mpd_command(&mut stream, "currentsong")?;
+    let _ = stream.get_mut().write_all(b"close\n");
+    let title = field(&song, "Title")
+        .or_else(|| Some(field(&song, "file")?.rsplit('/').next()?.to_string()))
+        .unwrap_or_default();
+    Ok(Some(Track::new(
+        "mpd",
+        title,
+        field(&song, "Artist").unwrap_or_default(),
+        field(&song, "Album").unwrap_or_default(),
+        position,
+        length,
+    )))
+}
+
+/// Sends `command` and reads the `key: value` lines of the answer.
+fn mpd_command<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    command: &str,
+) -> io::Result<Vec<(String, String)>> {
+    writeln!(stream.get_mut(), "{}", command)?;
+    let mut pairs = Vec::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end_matches('\n');
+        if line == "OK" {
+            return Ok(pairs);
+        }
+        if line.starts_with("ACK ") {
+            return Err(io::Error::other(line.to_string()));
+        }
+        if let Some((key, value)) = line.split_once(": ") {
+            pairs.push((key.to_string(), value.to_string()));
+        }
+    }
+}
+
+/// Asks the players what they play in the background and keeps the track.
+pub struct Watcher {
+    current: Arc<Mutex<Option<Track>>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl Watcher {
+    /// Starts polling the players `media` follows. `on_change` is called
+    /// from the polling thread when a track starts, seeks, pauses or
+    /// stops.
+    pub fn start(media: Media, on_change: impl Fn() + Send + 'static) -> Self {
+        let current = Arc::new(Mutex::new(None::<Track>));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let shared = Arc::clone(&current);
+        thread::spawn(move || {
+            let mut players = Players::new(media);
+            loop {
+                let track = players.playing();
+                let changed = {
+                    let mut current = shared.lock().unwrap();
+                    let changed = match (&*current, &track) {
+                        (Some(old), Some(new)) => !old.same(new),
+                        (old, new) => old.is_some() != new.is_some(),
+                    };
+                    if changed {
+                        match &track {
+                            Some(track) => info!("Now playing: {}", track.describe()),
+                            None => info!("Nothing playing"),
+                        }
+                        *current = track;
+                    }
+                    changed
+                };
+                if changed {
+                    on_change();
+                }
+                match stop_rx.recv_timeout(POLL) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+        Self {
+            current,
+            _stop: stop_tx,
+        }
+    }
+
+    /// The track playing now, if any.
+    pub fn current(&self) -> Option<Track> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::process::{Child, Command, Stdio};
+
+    /// Answers like MPD playing a track 83 seconds in.
+    fn fake_mpd(state: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            writer.write_all(b"OK MPD 0.23.5\n").unwrap();
+            for line in BufReader::new(stream).lines() {
+                let reply = match line.unwrap().as_str() {
+                    "status" => format!(
+                        "volume: 80\nstate: {}\nelapsed: 83.250\nduration: 241.000\nOK\n",
+                        state
+                    ),
+                    "currentsong" => "file: music/Boards of Canada/Roygbiv.flac\n\
+                                      Artist: Boards of Canada\nTitle: Roygbiv\n\
+                                      Album: Music Has the Right to Children\nOK\n"
+                        .to_string(),
+                    _ => break,
+                };
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_mpd() {
+        let track = read_mpd(&fake_mpd("play")).unwrap().unwrap();
+        assert_eq!(track.title, "Roygbiv");
+        assert_eq!(track.artist, "Boards of Canada");
+        assert_eq!(track.album, "Music Has the Right to Children");
+        let now = Utc::now().timestamp();
+        assert!((now - 83 - track.started).abs() <= 1);
+        assert_eq!(track.ends, Some(track.started + 241));
+
+        assert_eq!(read_mpd(&fake_mpd("pause")).unwrap(), None);
+    }
+
+    struct FakePlayer {
+        status: String,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.clone()
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            HashMap::from([
+                (
+                    "xesam:title".to_string(),
+                    OwnedValue::from(zbus::zvariant::Str::from("Xtal")),
+                ),
+                (
+                    "xesam:artist".to_string(),
+                    Value::from(vec!["Aphex Twin"]).try_into().unwrap(),
+                ),
+                ("mpris:length".to_string(), OwnedValue::from(293_000_000i64)),
+            ])
+        }
+
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            60_000_000
+        }
+    }
+
+    /// A bus of the test's own, stopped when dropped.
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Runs against a bus of its own, so it needs `dbus-daemon`.
+    #[test]
+    fn test_mpris() {
+        let Ok(daemon) = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon is not installed; skipping");
+            return;
+        };
+        let mut daemon = Bus(daemon);
+        let mut address = String::new();
+        BufReader::new(daemon.0.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let serve = |name: &str, status: &str| {
+            zbus::blocking::connection::Builder::address(address.trim())
+                .unwrap()
+                .name(format!("{}{}", MPRIS_PREFIX, name))
+                .unwrap()
+                .serve_at(
+                    MPRIS_PATH,
+                    FakePlayer {
+                        status: status.to_string(),
+                    },
+                )
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let _paused = serve("audacious", "Paused");
+        let _playing = serve("vlc.instance42", "Playing");
+        let connection = zbus::blocking::connection::Builder::address(address.trim())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let track = read_mpris(&connection, &[]).unwrap().unwrap();
+        assert_eq!(track.player, "vlc");
+        assert_eq!(track.title, "Xtal");
+        assert_eq!(track.artist, "Aphex Twin");
+        assert_eq!(track.album, "");
+        assert_eq!(track.ends, Some(track.started + 293));
+        let only = ["audacious".to_string()];
+        assert_eq!(read_mpris(&connection, &only).unwrap(), None);
+
+        let mut config = Config::default();
+        Media::default().apply(&track, &mut config);
+        assert_eq!(config.details, "{title}");
+        assert_eq!(config.start_timestamp, Some(track.started));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::{Error, Result};
+use toml::{Table, Value};
+
+/// Schema version written by this build. Bump it together with a new entry
+/// in `STEPS` whenever the on-disk format changes.
+pub const CURRENT_VERSION: u32 = 2;
+
+type Step = fn(&mut Table) -> Vec<String>;
+
+/// Upgrade steps, indexed by the version they upgrade from.
+const STEPS: &[Step] = &[v0_fill_required_fields, v1_unify_party_max];
+
+/// The steps for overlays: the same, except that filling in fields is left
+/// to the base.
+const OVERLAY_STEPS: &[Step] = &[|_| Vec::new(), v1_unify_party_max];
+
+#[derive(Debug, Default)]
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    pub changes: Vec<String>,
+}
+
+impl Migration {
+    pub fn is_needed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+/// Reads the schema version of a raw configuration. Files written before
+/// versioning existed have no `version` key and count as version 0.
+pub fn version_of(table: &Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(Value::Integer(v)) if *v >= 0 => Ok(*v as u32),
+        Some(other) => Err(Error::Config(format!(
+            "version: expected a non-negative integer, found {}",
+            other
+        ))),
+    }
+}
+
+/// Upgrades `table` in place to `CURRENT_VERSION`, one step at a time.
+pub fn migrate(table: &mut Table) -> Result<Migration> {
+    upgrade(table, STEPS)
+}
+
+/// Upgrades an overlay, a file with `extends`, from its own version.
+/// Overlays often leave `version` to their base, so one only counts as
+/// migrated when a step changed it.
+pub fn migrate_overlay(table: &mut Table) -> Result<Migration> {
+    let mut migration = upgrade(table, OVERLAY_STEPS)?;
+    if migration.changes.is_empty() {
+        migration.to = migration.from;
+    }
+    Ok(migration)
+}
+
+fn upgrade(table: &mut Table, steps: &[Step]) -> Result<Migration> {
+    let from = version_of(table)?;
+    if from > CURRENT_VERSION {
+        return Err(Error::Config(format!(
+            "version {} was written by a newer dstatus (this build supports up to {}); please update",
+            from, CURRENT_VERSION
+        )));
+    }
+
+    let mut migration = Migration {
+        from,
+        to: from,
+        changes: Vec::new(),
+    };
+    for (version, step) in steps.iter().enumerate().skip(from as usize) {
+        for change in step(table) {
+            migration
+                .changes
+                .push(format!("v{} → v{}: {}", version, version + 1, change));
+        }
+        migration.to = version as u32 + 1;
+    }
+    if migration.is_needed() {
+        table.insert(
+            "version".to_string(),
+            Value::Integer(CURRENT_VERSION as i64),
+        );
+    }
+
+    Ok(migration)
+}
+
+/// v0 files predate `#[serde(default)]` on the text fields; write every
+/// missing field out explicitly so the file documents itself. The fields
+/// and values are those of v1, spelled out so that later additions to
+/// `Config` do not change what this step writes.
+fn v0_fill_required_fields(table: &mut Table) -> Vec<String> {
+    let text = || Value::String(String::new());
+    let v1_fields = [
+        ("client_id", text()),
+        ("description", text()),
+        ("details", text()),
+        ("large_image", text()),
+        ("large_text", text()),
+        ("max_party_size", Value::Integer(0)),
+        ("name", text()),
+        ("party_size", Value::Integer(0)),
+        ("small_image", text()),
+        ("small_text", text()),
+        ("state", text()),
+    ];
+
+    let mut changes = Vec::new();
+    for (key, value) in v1_fields {
+        if table.contains_key(key) {
+            continue;
+        }
+        changes.push(format!("added missing `{}` = {}", key, value));
+        table.insert(key.to_string(), value);
+    }
+    changes
+}
+
+/// `party_max` duplicated `max_party_size`; fold it in and drop it.
+fn v1_unify_party_max(table: &mut Table) -> Vec<String> {
+    let Some(party_max) = table.remove("party_max") else {
+        return Vec::new();
+    };
+
+    let current = table
+        .get("max_party_size")
+        .and_then(Value::as_integer)
+        .unwrap_or(0);
+    if current == 0 {
+        table.insert("max_party_size".to_string(), party_max.clone());
+        vec![format!(
+            "moved `party_max` = {} into `max_party_size`",
+            party_max
+        )]
+    } else {
+        vec![format!(
+            "removed `party_max` = {}; keeping `max_party_size` = {}",
+            party_max, current
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrates_v0_step_by_step() {
+        let mut table: Table = "client_id = \"1\"\nparty_max = 4\n".parse().unwrap();
+        let migration = migrate(&mut table).unwrap();
+
+        assert_eq!(migration.from, 0);
+        assert_eq!(migration.to, CURRENT_VERSION);
+        assert_eq!(table["details"].as_str(), Some(""));
+        assert_eq!(table["party_size"].as_integer(), Some(0));
+        // Fields added after v1 are left to their serde defaults.
+        assert!(!table.contains_key("rotation"));
+        assert_eq!(table.len(), 12);
+        assert!(!table.contains_key("party_max"));
+        assert_eq!(table["max_party_size"].as_integer(), Some(4));
+        assert_eq!(table["version"].as_integer(), Some(CURRENT_VERSION as i64));
+    }
+
+    #[test]
+    fn test_current_version_is_untouched() {
+        let content = format!("version = {}\nclient_id = \"1\"\n", CURRENT_VERSION);
+        let mut table: Table = content.parse().unwrap();
+        let migration = migrate(&mut table).unwrap();
+
+        assert!(!migration.is_needed());
+        assert!(migration.changes.is_empty());
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_rejects_newer_versions() {
+        let content = format!("version = {}\n", CURRENT_VERSION + 1);
+        let mut table: Table = content.parse().unwrap();
+        assert!(migrate(&mut table).is_err());
+    }
+}
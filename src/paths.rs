@@ -0,0 +1,171 @@
+use crate::error::{Error, Result};
+use crate::profile;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Environment variable that overrides the configuration directory.
+pub const CONFIG_DIR_ENV: &str = "DSTATUS_CONFIG_DIR";
+
+/// Where dstatus keeps its files. Configuration goes in `config_dir`; the
+/// PID file and logs go in `state_dir`.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config_dir: PathBuf,
+    pub state_dir: PathBuf,
+    /// Set when the directory was overridden, so that child processes can
+    /// be pointed at the same setup.
+    overridden: Option<PathBuf>,
+}
+
+impl Paths {
+    /// Resolves the directories from, in order: `config_dir` (the
+    /// `--config-dir` flag), `DSTATUS_CONFIG_DIR`, then the XDG base
+    /// directories. An overridden setup keeps its state next to its
+    /// configuration so that independent setups never share a daemon.
+    pub fn resolve(config_dir: Option<PathBuf>) -> Result<Self> {
+        Self::resolve_with(config_dir, |key| std::env::var_os(key), dirs::home_dir())
+    }
+
+    fn resolve_with(
+        config_dir: Option<PathBuf>,
+        var: impl Fn(&str) -> Option<OsString>,
+        home: Option<PathBuf>,
+    ) -> Result<Self> {
+        let overridden = config_dir.or_else(|| {
+            var(CONFIG_DIR_ENV)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        });
+        if let Some(dir) = &overridden {
+            return Ok(Self {
+                config_dir: dir.clone(),
+                state_dir: dir.clone(),
+                overridden: overridden.clone(),
+            });
+        }
+
+        let home = || {
+            home.clone().ok_or_else(|| {
+                Error::io(
+                    "Failed to find home directory",
+                    std::io::Error::from(ErrorKind::NotFound),
+                )
+            })
+        };
+        // The XDG spec says relative values are invalid and must be ignored.
+        let xdg = |key: &str, fallback: &[&str]| -> Result<PathBuf> {
+            match var(key).map(PathBuf::from) {
+                Some(dir) if dir.is_absolute() => Ok(dir.join("dstatus")),
+                _ => Ok(fallback
+                    .iter()
+                    .fold(home()?, |path, part| path.join(part))
+                    .join("dstatus")),
+            }
+        };
+
+        Ok(Self {
+            config_dir: xdg("XDG_CONFIG_HOME", &[".config"])?,
+            state_dir: xdg("XDG_STATE_HOME", &[".local", "state"])?,
+            overridden: None,
+        })
+    }
+
+    /// Creates both directories if needed.
+    pub fn create_dirs(&self) -> Result<()> {
+        for dir in [&self.config_dir, &self.state_dir] {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io(format!("Failed to create directory {:?}", dir), e))?;
+        }
+
+        // Older versions kept the PID file with the configuration; move it
+        // so that a daemon started by them can still be stopped.
+        let legacy_pid = self.config_dir.join("dstatus.pid");
+        if self.state_dir != self.config_dir && legacy_pid.exists() && !self.pid_file().exists() {
+            let _ = fs::rename(&legacy_pid, self.pid_file());
+        }
+        Ok(())
+    }
+
+    /// The configuration file of the active profile.
+    pub fn config_file(&self) -> Result<PathBuf> {
+        profile::active_path(&self.config_dir)
+    }
+
+    pub fn pid_file(&self) -> PathBuf {
+        self.state_dir.join("dstatus.pid")
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.state_dir.join("dstatus.log")
+    }
+
+    /// The socket the daemon takes requests from other commands on.
+    pub fn control_socket(&self) -> PathBuf {
+        self.state_dir.join("dstatus.sock")
+    }
+
+    /// Arguments that make a spawned dstatus use these same directories.
+    pub fn child_args(&self) -> Vec<OsString> {
+        match &self.overridden {
+            Some(dir) => vec!["--config-dir".into(), dir.clone().into_os_string()],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn resolve(flag: Option<&str>, vars: &[(&str, &str)]) -> Paths {
+        let vars: Vec<(String, OsString)> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect();
+        Paths::resolve_with(
+            flag.map(PathBuf::from),
+            |key| vars.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone()),
+            Some(PathBuf::from("/home/me")),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_defaults_follow_home() {
+        let paths = resolve(None, &[]);
+        assert_eq!(paths.config_dir, Path::new("/home/me/.config/dstatus"));
+        assert_eq!(
+            paths.pid_file(),
+            Path::new("/home/me/.local/state/dstatus/dstatus.pid")
+        );
+        assert!(paths.child_args().is_empty());
+    }
+
+    #[test]
+    fn test_xdg_dirs_are_honored_when_absolute() {
+        let paths = resolve(
+            None,
+            &[
+                ("XDG_CONFIG_HOME", "/xdg/config"),
+                ("XDG_STATE_HOME", "relative"),
+            ],
+        );
+        assert_eq!(paths.config_dir, Path::new("/xdg/config/dstatus"));
+        assert_eq!(paths.state_dir, Path::new("/home/me/.local/state/dstatus"));
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let env = [(CONFIG_DIR_ENV, "/env"), ("XDG_CONFIG_HOME", "/xdg")];
+        let paths = resolve(None, &env);
+        assert_eq!(paths.config_dir, Path::new("/env"));
+        assert_eq!(paths.log_file(), Path::new("/env/dstatus.log"));
+
+        let paths = resolve(Some("/flag"), &env);
+        assert_eq!(paths.config_dir, Path::new("/flag"));
+        assert_eq!(paths.child_args(), vec!["--config-dir", "/flag"]);
+    }
+}
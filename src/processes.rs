@@ -0,0 +1,603 @@
+use crate::error::{Error, Result};
+use crate::format;
+use crate::profile;
+use crate::rotation::Slide;
+use crate::source;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+const FILE_STEM: &str = "processes";
+
+/// Where running processes are listed.
+pub const PROC_ROOT: &str = "/proc";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(10);
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// File extensions `{language}` recognizes in a process's arguments, with
+/// the name it stands for. The names double as image keys.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("css", "css"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("hs", "haskell"),
+    ("html", "html"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("json", "json"),
+    ("kt", "kotlin"),
+    ("lua", "lua"),
+    ("md", "markdown"),
+    ("php", "php"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("swift", "swift"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("zig", "zig"),
+];
+
+/// The process rules file, `processes.toml` unless it was written in
+/// another format.
+pub fn path(config_dir: &Path) -> PathBuf {
+    format::find(config_dir, FILE_STEM)
+}
+
+/// The process rules in `config_dir`, or `None` when there is no rules
+/// file.
+pub fn load(config_dir: &Path) -> Result<Option<Rules>> {
+    let path = path(config_dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => Rules::parse(&content, &path).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+}
+
+/// The rules file as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    interval: Option<String>,
+    #[serde(default)]
+    debounce: Option<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    processes: Vec<String>,
+    #[serde(default)]
+    args: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    set: Option<Slide>,
+}
+
+/// Rules picking a presence by the applications that are running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    /// How often `/proc` is scanned.
+    pub interval: Duration,
+    /// How long a different result must hold before it is applied, so
+    /// that processes that come and go quickly do not flip the presence.
+    pub debounce: Duration,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: Option<String>,
+    processes: Vec<String>,
+    args: Option<Regex>,
+    pub priority: i32,
+    pub profile: Option<String>,
+    set: Option<Slide>,
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.processes == other.processes
+            && self.args.as_ref().map(Regex::as_str) == other.args.as_ref().map(Regex::as_str)
+            && self.priority == other.priority
+            && self.profile == other.profile
+            && self.set == other.set
+    }
+}
+
+/// The rule that applies and the process that made it apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub rule: usize,
+    pub process: String,
+    /// Guessed from the files the process was started with.
+    pub language: Option<String>,
+}
+
+/// A running process, as far as the rules care.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    /// What the process is called: its `comm`, and the file names of its
+    /// executable and of its first argument.
+    pub names: Vec<String>,
+    /// The command line after the program.
+    pub args: Vec<String>,
+}
+
+impl Rules {
+    /// Parses a rules file; `origin` picks the format and labels errors.
+    pub fn parse(content: &str, origin: &Path) -> Result<Self> {
+        let table = format::parse(
+            content,
+            format::Format::detect(&origin.to_string_lossy(), content),
+            origin,
+        )?;
+        let label = origin.display();
+        let file: File = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| Error::Config(format!("{}: {}", label, e.message())))?;
+        let duration = |value: &Option<String>, default, key| {
+            value
+                .as_deref()
+                .map_or(Ok(default), source::parse_duration)
+                .map_err(|e| Error::Config(format!("{}: {}: {}", label, key, e)))
+        };
+        let interval = duration(&file.interval, DEFAULT_INTERVAL, "interval")?;
+        if interval < MIN_INTERVAL {
+            return Err(Error::Config(format!(
+                "{}: interval must be at least {:?}",
+                label, MIN_INTERVAL
+            )));
+        }
+        let debounce = duration(&file.debounce, DEFAULT_DEBOUNCE, "debounce")?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                Rule::compile(rule).map_err(|message| {
+                    Error::Config(format!("{}: rule {}: {}", label, i + 1, message))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            interval,
+            debounce,
+            rules,
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The rule with the highest priority that a running process matches.
+    /// Of rules with the same priority, the one written first wins.
+    pub fn evaluate(&self, processes: &[Process]) -> Option<Match> {
+        let mut best: Option<Match> = None;
+        for (i, rule) in self.rules.iter().enumerate() {
+            if best
+                .as_ref()
+                .is_some_and(|best| self.rules[best.rule].priority >= rule.priority)
+            {
+                continue;
+            }
+            if let Some(process) = processes.iter().find(|process| rule.matches(process)) {
+                best = Some(Match {
+                    rule: i,
+                    process: rule.matched_name(process),
+                    language: language(&process.args),
+                });
+            }
+        }
+        best
+    }
+
+    /// The fields the matched rule sets, with `{process}` and `{language}`
+    /// filled in.
+    pub fn overrides(&self, matched: &Match) -> Option<Slide> {
+        let set = self.rules.get(matched.rule)?.set.as_ref()?;
+        Some(fill(set, &matched.process, matched.language.as_deref()))
+    }
+}
+
+/// `set` with `{process}` and `{language}` filled in.
+pub fn fill(set: &Slide, process: &str, language: Option<&str>) -> Slide {
+    let fill = |text: &Option<String>| {
+        text.as_ref().map(|text| {
+            text.replace("{process}", process)
+                .replace("{language}", language.unwrap_or(""))
+        })
+    };
+    Slide {
+        details: fill(&set.details),
+        state: fill(&set.state),
+        large_image: fill(&set.large_image),
+        large_text: fill(&set.large_text),
+        small_image: fill(&set.small_image),
+        small_text: fill(&set.small_text),
+        buttons: set.buttons.clone(),
+    }
+}
+
+impl Rule {
+    fn compile(rule: RuleFile) -> std::result::Result<Self, String> {
+        if rule.processes.is_empty() {
+            return Err("needs processes to look for".to_string());
+        }
+        if rule.profile.is_none() && rule.set.is_none() {
+            return Err("needs a profile, fields to set, or both".to_string());
+        }
+        if let Some(name) = &rule.profile {
+            profile::validate_name(name).map_err(|e| e.to_string())?;
+        }
+        let args = rule
+            .args
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("args: {}", e))?;
+        Ok(Self {
+            name: rule.name,
+            processes: rule.processes,
+            args,
+            priority: rule.priority,
+            profile: rule.profile,
+            set: rule.set,
+        })
+    }
+
+    fn matches(&self, process: &Process) -> bool {
+        process
+            .names
+            .iter()
+            .any(|name| self.processes.contains(name))
+            && self
+                .args
+                .as_ref()
+                .is_none_or(|args| args.is_match(&process.args.join(" ")))
+    }
+
+    fn matched_name(&self, process: &Process) -> String {
+        process
+            .names
+            .iter()
+            .find(|name| self.processes.contains(name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The rule's name, or the processes it looks for.
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.processes.join(", "),
+        }
+    }
+}
+
+/// The processes listed under `root`, normally `/proc`. Processes that
+/// exit while being read, or cannot be read, are skipped.
+pub fn scan(root: &Path) -> Vec<Process> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut processes: Vec<Process> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse().ok()?;
+            read_process(&entry.path(), pid)
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+/// The environment process `pid` was started with, as listed under
+/// `root`. `None` when it cannot be read, such as when the process has
+/// exited or belongs to another user.
+pub fn environ(root: &Path, pid: u32) -> Option<HashMap<String, String>> {
+    let environ = fs::read(root.join(pid.to_string()).join("environ")).ok()?;
+    Some(
+        environ
+            .split(|byte| *byte == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                let (name, value) = entry.split_once('=')?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect(),
+    )
+}
+
+fn read_process(dir: &Path, pid: u32) -> Option<Process> {
+    let mut names = Vec::new();
+    if let Ok(comm) = fs::read_to_string(dir.join("comm")) {
+        names.push(comm.trim_end().to_string());
+    }
+    if let Ok(exe) = fs::read_link(dir.join("exe")) {
+        names.extend(file_name(&exe.to_string_lossy()));
+    }
+    let cmdline = fs::read(dir.join("cmdline")).unwrap_or_default();
+    let mut argv = cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    if let Some(program) = argv.next() {
+        names.extend(file_name(&program));
+    }
+    names.retain(|name| !name.is_empty());
+    names.dedup();
+    if names.is_empty() {
+        return None;
+    }
+    Some(Process {
+        pid,
+        names,
+        args: argv.collect(),
+    })
+}
+
+fn file_name(path: &str) -> Option<String> {
+    Some(path.rsplit('/').next()?.to_string())
+}
+
+/// The language of the first argument with a known file extension.
+pub fn language(args: &[String]) -> Option<String> {
+    args.iter().find_map(|arg| {
+        let name = file_name(arg)?;
+        let (_, extension) = name.rsplit_once('.')?;
+        let extension = extension.to_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, language)| language.to_string())
+    })
+}
+
+/// Holds a changing value back until it has stayed the same for `delay`.
+#[derive(Debug)]
+struct Debounce<T> {
+    delay: Duration,
+    applied: Option<T>,
+    pending: Option<(T, Instant)>,
+}
+
+impl<T: Clone + PartialEq> Debounce<T> {
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            applied: None,
+            pending: None,
+        }
+    }
+
+    /// Records the latest value, returning the one to apply when that
+    /// changed. The first value applies at once.
+    fn observe(&mut self, value: T, now: Instant) -> Option<T> {
+        if self.applied.is_none() {
+            self.applied = Some(value.clone());
+            return Some(value);
+        }
+        if self.applied.as_ref() == Some(&value) {
+            self.pending = None;
+            return None;
+        }
+        match &self.pending {
+            Some((pending, since)) if *pending == value => {
+                if now.duration_since(*since) < self.delay {
+                    return None;
+                }
+            }
+            _ => {
+                self.pending = Some((value, now));
+                return None;
+            }
+        }
+        self.pending = None;
+        self.applied = Some(value.clone());
+        Some(value)
+    }
+}
+
+/// Scans for processes in the background and keeps the debounced result.
+pub struct Watcher {
+    current: Arc<Mutex<Option<Match>>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl Watcher {
+    /// Starts scanning `root` every `rules.interval`. `on_change` is called
+    /// from the scanning thread after the first scan and whenever the
+    /// result changes.
+    pub fn start(rules: Rules, root: PathBuf, on_change: impl Fn() + Send + 'static) -> Self {
+        let current = Arc::new(Mutex::new(None));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let shared = Arc::clone(&current);
+        thread::spawn(move || {
+            // Scans finish within the interval, so the result only needs
+            // to hold for `debounce` on top of the scan that saw it.
+            let mut debounce = Debounce::new(rules.debounce);
+            loop {
+                let matched = rules.evaluate(&scan(&root));
+                if let Some(matched) = debounce.observe(matched, Instant::now()) {
+                    debug!("Process rules: {:?}", matched);
+                    *shared.lock().unwrap() = matched;
+                    on_change();
+                }
+                match stop_rx.recv_timeout(rules.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+        Self {
+            current,
+            _stop: stop_tx,
+        }
+    }
+
+    /// The rule that applies now, if any.
+    pub fn current(&self) -> Option<Match> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const RULES: &str = r#"
+interval = "2s"
+
+[[rule]]
+name = "Coding"
+processes = ["nvim", "code"]
+priority = 10
+profile = "coding"
+set = { small_image = "{language}", small_text = "Writing {language} in {process}" }
+
+[[rule]]
+processes = ["blender"]
+priority = 20
+set = { details = "Modelling" }
+
+[[rule]]
+processes = ["python3"]
+args = "manage\\.py runserver"
+profile = "web"
+"#;
+
+    fn rules() -> Rules {
+        Rules::parse(RULES, Path::new("processes.toml")).unwrap()
+    }
+
+    /// Writes a process the way the kernel lists it.
+    fn spawn(root: &Path, pid: u32, comm: &str, argv: &[&str]) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("comm"), format!("{}\n", comm)).unwrap();
+        let mut cmdline = argv.join("\0");
+        cmdline.push('\0');
+        fs::write(dir.join("cmdline"), cmdline).unwrap();
+    }
+
+    #[test]
+    fn test_scan_fake_proc() {
+        let root = tempdir().unwrap();
+        spawn(root.path(), 42, "nvim", &["/usr/bin/nvim", "src/main.rs"]);
+        // Kernel threads have no command line.
+        spawn(root.path(), 2, "kthreadd", &[]);
+        fs::create_dir(root.path().join("self-test")).unwrap();
+
+        let processes = scan(root.path());
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[1].names, vec!["nvim"]);
+        assert_eq!(processes[1].args, vec!["src/main.rs"]);
+
+        let matched = rules().evaluate(&processes).unwrap();
+        assert_eq!(matched.rule, 0);
+        assert_eq!(matched.language.as_deref(), Some("rust"));
+        let set = rules().overrides(&matched).unwrap();
+        assert_eq!(set.small_image.as_deref(), Some("rust"));
+        assert_eq!(set.small_text.as_deref(), Some("Writing rust in nvim"));
+
+        fs::write(
+            root.path().join("42").join("environ"),
+            "TERM=xterm\0A=b=c\0",
+        )
+        .unwrap();
+        let env = environ(root.path(), 42).unwrap();
+        assert_eq!(env["TERM"], "xterm");
+        assert_eq!(env["A"], "b=c");
+        assert!(environ(root.path(), 2).is_none());
+    }
+
+    #[test]
+    fn test_priorities_and_args() {
+        let root = tempdir().unwrap();
+        spawn(
+            root.path(),
+            10,
+            "code",
+            &["/usr/share/code/code", "--new-window"],
+        );
+        spawn(
+            root.path(),
+            11,
+            "python3",
+            &["python3", "manage.py", "migrate"],
+        );
+        let rules = rules();
+        assert_eq!(rules.evaluate(&scan(root.path())).unwrap().rule, 0);
+
+        spawn(root.path(), 12, "blender", &["blender", "scene.blend"]);
+        let matched = rules.evaluate(&scan(root.path())).unwrap();
+        assert_eq!((matched.rule, matched.language), (1, None));
+
+        fs::remove_dir_all(root.path().join("10")).unwrap();
+        fs::remove_dir_all(root.path().join("12")).unwrap();
+        assert_eq!(rules.evaluate(&scan(root.path())), None);
+        spawn(
+            root.path(),
+            13,
+            "python3",
+            &["python3", "manage.py", "runserver"],
+        );
+        assert_eq!(rules.evaluate(&scan(root.path())).unwrap().rule, 2);
+    }
+
+    #[test]
+    fn test_debounce_ignores_flapping() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut debounce = Debounce::new(Duration::from_secs(10));
+        assert_eq!(debounce.observe("idle", at(0)), Some("idle"));
+        assert_eq!(debounce.observe("coding", at(5)), None);
+        // Gone again before the delay: nothing changes.
+        assert_eq!(debounce.observe("idle", at(10)), None);
+        assert_eq!(debounce.observe("coding", at(15)), None);
+        assert_eq!(debounce.observe("coding", at(20)), None);
+        assert_eq!(debounce.observe("coding", at(25)), Some("coding"));
+        assert_eq!(debounce.observe("coding", at(30)), None);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let parse = |content| Rules::parse(content, Path::new("processes.toml"));
+        assert!(parse("[[rule]]\nprofile = \"coding\"\n").is_err());
+        assert!(parse("[[rule]]\nprocesses = [\"nvim\"]\n").is_err());
+        assert!(parse("interval = \"100ms\"\n").is_err());
+        assert!(parse("[[rule]]\nprocesses = [\"x\"]\nargs = \"(\"\nprofile = \"a\"\n").is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::format::{self, Format};
+use crate::history;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The profile stored in `configuration.toml` (or `.json`, `.yaml`), so
+/// existing setups keep working unchanged.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const ACTIVE_FILE: &str = "active_profile";
+
+/// Present while a profile chosen by hand is pinned; names that profile.
+const PIN_FILE: &str = "pinned_profile";
+
+/// The directory, inside the configuration directory, holding the
+/// profiles other than the default one.
+pub const PROFILES_DIR: &str = "profiles";
+
+/// Profile names become file names, so keep them to a safe alphabet.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "Invalid profile name '{}': use letters, digits, '-' and '_'",
+            name
+        )))
+    }
+}
+
+pub fn profiles_dir(config_dir: &Path) -> PathBuf {
+    config_dir.join(PROFILES_DIR)
+}
+
+/// The file holding profile `name`, in whichever supported format it
+/// exists, or the TOML file it would be created as.
+pub fn path(config_dir: &Path, name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+    if name == DEFAULT_PROFILE {
+        Ok(format::find(config_dir, "configuration"))
+    } else {
+        Ok(format::find(&profiles_dir(config_dir), name))
+    }
+}
+
+pub fn exists(config_dir: &Path, name: &str) -> Result<bool> {
+    Ok(name == DEFAULT_PROFILE || path(config_dir, name)?.exists())
+}
+
+/// The file recording the active profile.
+pub fn pointer_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(ACTIVE_FILE)
+}
+
+/// The name of the active profile. A missing pointer, or one naming a
+/// profile that no longer exists, means the default profile.
+pub fn active(config_dir: &Path) -> Result<String> {
+    let pointer = pointer_path(config_dir);
+    let name = match fs::read_to_string(&pointer) {
+        Ok(content) => content.trim().to_string(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(DEFAULT_PROFILE.to_string()),
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", pointer), e)),
+    };
+    if validate_name(&name).is_ok() && exists(config_dir, &name)? {
+        Ok(name)
+    } else {
+        Ok(DEFAULT_PROFILE.to_string())
+    }
+}
+
+/// The file of the active profile.
+pub fn active_path(config_dir: &Path) -> Result<PathBuf> {
+    path(config_dir, &active(config_dir)?)
+}
+
+pub fn set_active(config_dir: &Path, name: &str) -> Result<()> {
+    if !exists(config_dir, name)? {
+        return Err(not_found(name));
+    }
+    history::atomic_write(&pointer_path(config_dir), &format!("{}\n", name))
+}
+
+/// The file recording the pinned profile.
+pub fn pin_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(PIN_FILE)
+}
+
+/// Makes `name` the active profile and pins it, so that rules, process
+/// rules and the schedule leave it active until it is released.
+pub fn pin(config_dir: &Path, name: &str) -> Result<()> {
+    set_active(config_dir, name)?;
+    history::atomic_write(&pin_path(config_dir), &format!("{}\n", name))
+}
+
+/// Unpins the profile. Returns whether one was pinned.
+pub fn release(config_dir: &Path) -> Result<bool> {
+    let pin = pin_path(config_dir);
+    match fs::remove_file(&pin) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::io(format!("Failed to remove {:?}", pin), e)),
+    }
+}
+
+/// The pinned profile, if any. A pin only holds for the profile it names,
+/// so one left behind by a switch made some other way is ignored.
+pub fn pinned(config_dir: &Path) -> Result<Option<String>> {
+    let pin = pin_path(config_dir);
+    let name = match fs::read_to_string(&pin) {
+        Ok(content) => content.trim().to_string(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", pin), e)),
+    };
+    Ok((active(config_dir)? == name).then_some(name))
+}
+
+/// All profile names, the default first and the rest sorted.
+pub fn list(config_dir: &Path) -> Result<Vec<String>> {
+    let dir = profiles_dir(config_dir);
+    let mut names: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                Format::from_extension(path.to_str()?)?;
+                let name = path.file_stem()?.to_str()?.to_string();
+                (validate_name(&name).is_ok() && name != DEFAULT_PROFILE).then_some(name)
+            })
+            .collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", dir), e)),
+    };
+    names.sort();
+    names.dedup();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+/// Creates profile `name` holding `config`.
+pub fn create(config_dir: &Path, name: &str, config: &Config) -> Result<PathBuf> {
+    if exists(config_dir, name)? {
+        return Err(Error::Config(format!("Profile '{}' already exists", name)));
+    }
+    let path = path(config_dir, name)?;
+    fs::create_dir_all(profiles_dir(config_dir))
+        .map_err(|e| Error::io("Failed to create profiles directory", e))?;
+    config.save_to_file(path_str(&path)?)?;
+    Ok(path)
+}
+
+/// Copies profile `from` to a new profile `to`, comments included.
+pub fn copy(config_dir: &Path, from: &str, to: &str) -> Result<PathBuf> {
+    let source = path(config_dir, from)?;
+    let content = fs::read_to_string(&source).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => not_found(from),
+        _ => Error::io(format!("Failed to read {:?}", source), e),
+    })?;
+    if exists(config_dir, to)? {
+        return Err(Error::Config(format!("Profile '{}' already exists", to)));
+    }
+    // Keep the format, since the content is copied verbatim.
+    let target = match source.extension() {
+        Some(extension) => path(config_dir, to)?.with_extension(extension),
+        None => path(config_dir, to)?,
+    };
+    fs::create_dir_all(profiles_dir(config_dir))
+        .map_err(|e| Error::io("Failed to create profiles directory", e))?;
+    history::atomic_write(&target, &content)?;
+    Ok(target)
+}
+
+/// Deletes profile `name`. The default profile and the active one cannot
+/// be deleted; the file is kept in the history so it can be recovered.
+pub fn delete(config_dir: &Path, name: &str) -> Result<()> {
+    if name == DEFAULT_PROFILE {
+        return Err(Error::Config(
+            "The default profile cannot be deleted".to_string(),
+        ));
+    }
+    if active(config_dir)? == name {
+        return Err(Error::Config(format!(
+            "Profile '{}' is active; switch to another profile first",
+            name
+        )));
+    }
+    let path = path(config_dir, name)?;
+    let content = fs::read_to_string(&path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => not_found(name),
+        _ => Error::io(format!("Failed to read {:?}", path), e),
+    })?;
+    history::record(&path, &content)?;
+    fs::remove_file(&path).map_err(|e| Error::io(format!("Failed to delete {:?}", path), e))
+}
+
+fn not_found(name: &str) -> Error {
+    Error::Config(format!(
+        "Profile '{}' does not exist; see 'dstatus profile list'",
+        name
+    ))
+}
+
+/// `path` as a `&str`, for the functions that take one.
+pub(crate) fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::Config(format!("Path {:?} is not valid UTF-8", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_default_profile_is_active_without_pointer() {
+        let dir = tempdir().unwrap();
+        assert_eq!(active(dir.path()).unwrap(), DEFAULT_PROFILE);
+        assert_eq!(
+            active_path(dir.path()).unwrap(),
+            dir.path().join("configuration.toml")
+        );
+        assert_eq!(list(dir.path()).unwrap(), vec![DEFAULT_PROFILE]);
+    }
+
+    #[test]
+    fn test_create_use_copy_delete() {
+        let dir = tempdir().unwrap();
+        let config = Config {
+            state: "Gaming".to_string(),
+            ..Config::default()
+        };
+        create(dir.path(), "gaming", &config).unwrap();
+        copy(dir.path(), "gaming", "on-call").unwrap();
+        assert!(create(dir.path(), "gaming", &config).is_err());
+        assert_eq!(
+            list(dir.path()).unwrap(),
+            vec![DEFAULT_PROFILE, "gaming", "on-call"]
+        );
+
+        set_active(dir.path(), "on-call").unwrap();
+        assert_eq!(active(dir.path()).unwrap(), "on-call");
+        let loaded = Config::read_file(path_str(&active_path(dir.path()).unwrap()).unwrap());
+        assert_eq!(loaded.unwrap().state, "Gaming");
+
+        assert!(delete(dir.path(), "on-call").is_err());
+        assert!(delete(dir.path(), DEFAULT_PROFILE).is_err());
+        delete(dir.path(), "gaming").unwrap();
+        assert!(set_active(dir.path(), "gaming").is_err());
+    }
+
+    #[test]
+    fn test_pin_and_release() {
+        let dir = tempdir().unwrap();
+        create(dir.path(), "gaming", &Config::default()).unwrap();
+        assert_eq!(pinned(dir.path()).unwrap(), None);
+
+        pin(dir.path(), "gaming").unwrap();
+        assert_eq!(active(dir.path()).unwrap(), "gaming");
+        assert_eq!(pinned(dir.path()).unwrap().as_deref(), Some("gaming"));
+
+        set_active(dir.path(), DEFAULT_PROFILE).unwrap();
+        assert_eq!(pinned(dir.path()).unwrap(), None);
+
+        assert!(release(dir.path()).unwrap());
+        assert!(!release(dir.path()).unwrap());
+        assert!(pin(dir.path(), "missing").is_err());
+        assert!(!pin_path(dir.path()).exists());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_names() {
+        for name in ["", "../etc", "a/b", "-x", "with space"] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+        assert!(validate_name("on_call-2").is_ok());
+    }
+}
@@ -1,10 +1,26 @@
 use crate::config::Config;
+use crate::control::Hold;
+use crate::media::{self, Track};
+use crate::profile;
+use crate::rotation::{Carousel, Slide};
+use crate::rules;
+use crate::shell;
+use crate::source::Outputs;
 use crate::stream_manager::{Activity, IncomingMessage, StreamManager};
+use crate::template;
 use anyhow::{Context, Result};
 use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tracing::{debug, info};
 use uuid::Uuid;
 
+/// Discord accepts at most this many activity updates per window; later
+/// ones are dropped without an error.
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(20);
+
 #[derive(Serialize, Debug)]
 pub struct Hello {
     v: u8,
@@ -21,7 +37,8 @@ struct SetActivity {
 #[derive(Serialize, Debug)]
 struct SetActivityArgs {
     pid: u32,
-    activity: Activity,
+    /// `None` clears the activity.
+    activity: Option<Activity>,
 }
 
 impl Hello {
@@ -35,28 +52,152 @@ impl Hello {
 
 pub struct RichPresence {
     config: Config,
+    profile: String,
+    started: Instant,
+    outputs: Outputs,
+    /// What Discord is showing, so that unchanged activities are not resent.
+    /// `Some(None)` once the activity was cleared.
+    last_sent: Option<Option<Activity>>,
+    /// When the last few activities were sent, for the rate limit.
+    sent_at: VecDeque<Instant>,
+    /// Set when an update was held back by the rate limit.
+    pending: bool,
+    carousel: Option<Carousel>,
+    /// Fields set by the process rule that applies, over any slide.
+    overrides: Option<Slide>,
+    /// Fields set by `rules.toml`, over the process rule's.
+    rule_fields: Option<Slide>,
+    /// Whether the schedule asked for no activity.
+    cleared: bool,
+    /// Whether `rules.toml` asked for no activity, or for one despite the
+    /// schedule.
+    rule_clear: Option<bool>,
+    /// An activity asked for over the control socket, shown in place of
+    /// everything else.
+    held: Option<Hold>,
+    /// The track playing, shown in place of the slides when the
+    /// configuration follows media players.
+    track: Option<Track>,
+    /// What the shells reported, for `{project}`, `{branch}` and
+    /// `{command}`.
+    shell: shell::Status,
     stream_manager: StreamManager,
 }
 
 impl RichPresence {
     pub fn new(config: Config) -> Self {
+        let started = Instant::now();
         Self {
+            carousel: config
+                .rotation
+                .as_ref()
+                .and_then(|rotation| Carousel::new(rotation, started)),
             config,
+            profile: profile::DEFAULT_PROFILE.to_string(),
+            started,
+            outputs: Outputs::default(),
+            last_sent: None,
+            sent_at: VecDeque::new(),
+            pending: false,
+            overrides: None,
+            rule_fields: None,
+            cleared: false,
+            rule_clear: None,
+            held: None,
+            track: None,
+            shell: shell::Status::default(),
             stream_manager: StreamManager::new(),
         }
     }
 
+    /// Replaces the configuration. The rotation starts over only when it
+    /// changed.
     pub fn update_config(&mut self, config: Config) {
+        if config.rotation != self.config.rotation {
+            self.carousel = config
+                .rotation
+                .as_ref()
+                .and_then(|rotation| Carousel::new(rotation, Instant::now()));
+        }
         self.config = config;
     }
 
+    /// When `set_activity` next has something to do on its own: the next
+    /// slide, or an update held back by the rate limit.
+    pub fn wake_at(&self) -> Option<Instant> {
+        let retry = match (self.pending, self.sent_at.front()) {
+            (true, Some(oldest)) => Some(*oldest + RATE_WINDOW),
+            _ => None,
+        };
+        let slide = self.carousel.as_ref().map(Carousel::next_at);
+        retry.into_iter().chain(slide).min()
+    }
+
+    /// Where the text of fields sourced from commands is read from.
+    pub fn set_outputs(&mut self, outputs: Outputs) {
+        self.outputs = outputs;
+    }
+
+    /// Fields to show over the configured ones, from a process rule.
+    pub fn set_overrides(&mut self, overrides: Option<Slide>) {
+        self.overrides = overrides;
+    }
+
+    /// What `rules.toml` decided: fields to show over the process rule's,
+    /// and whether to show nothing, which outranks the schedule but not a
+    /// paused media player.
+    pub fn set_rules(&mut self, fields: Option<Slide>, clear: Option<bool>) {
+        self.rule_fields = fields;
+        self.rule_clear = clear;
+    }
+
+    /// Whether to show nothing instead of the configured activity.
+    pub fn set_cleared(&mut self, cleared: bool) {
+        self.cleared = cleared;
+    }
+
+    /// An activity to show in place of the configured one, the slides,
+    /// the overrides and the schedule, until it is set back to `None`.
+    pub fn set_held(&mut self, held: Option<Hold>) {
+        self.held = held;
+    }
+
+    /// The track playing, or `None` while playback is paused or stopped.
+    pub fn set_track(&mut self, track: Option<Track>) {
+        self.track = track;
+    }
+
+    pub fn set_shell(&mut self, shell: shell::Status) {
+        self.shell = shell;
+    }
+
+    /// The profile name `{profile}` renders as.
+    pub fn set_profile(&mut self, profile: &str) {
+        self.profile = profile.to_string();
+    }
+
     pub fn start(&mut self) -> Result<(), anyhow::Error> {
         info!(
             "Connecting to Discord with client ID: {}",
             self.config.client_id
         );
         self.stream_manager.connect()?;
+        self.last_sent = None;
+        self.handshake()
+    }
 
+    /// Connects to one specific IPC socket and performs the handshake,
+    /// instead of picking the first socket that accepts a connection. Reads
+    /// time out so a wedged client cannot hang the caller.
+    pub fn start_on(&mut self, socket_path: &Path) -> Result<()> {
+        self.stream_manager.connect_path(socket_path)?;
+        self.stream_manager
+            .set_read_timeout(Some(Duration::from_secs(5)))?;
+        self.last_sent = None;
+        self.handshake()
+    }
+
+    fn handshake(&mut self) -> Result<()> {
         let handshake = Hello::new(&self.config.client_id);
         debug!("Sending handshake: {:?}", handshake);
         self.stream_manager.write(&handshake, 0x0)?;
@@ -91,36 +232,79 @@ impl RichPresence {
         Ok(())
     }
 
+    /// Moves the rotation on when a slide is due, renders the placeholders
+    /// and sends the activity, unless it is the same as the one last sent
+    /// on this connection. Updates beyond Discord's rate limit are held
+    /// back until `wake_at`.
     pub fn set_activity(&mut self) -> Result<()> {
-        let buttons = self.config.buttons.clone();
-
-        let activity = Activity {
-            state: Some(self.config.state.clone()),
-            details: Some(self.config.details.clone()),
-            timestamps: None,
-            assets: Some(serde_json::json!({
-                "large_image": self.config.large_image.clone(),
-                "large_text": self.config.large_text.clone(),
-                "small_image": self.config.small_image.clone(),
-                "small_text": self.config.small_text.clone()
-            })),
-            party: Some(serde_json::json!({
-                "size": [self.config.party_size, self.config.max_party_size]
-            })),
-            secrets: None,
-            buttons: if buttons.as_ref().map_or(false, |b| !b.is_empty()) {
-                buttons
-            } else {
-                None
-            },
-            instance: Some(false),
-        };
+        let now = Instant::now();
+        let mut config = self.config.clone();
+        let playing = self.config.media.as_ref().zip(self.track.as_ref());
+        if let Some(held) = &self.held {
+            held.activity.apply(&mut config);
+            config.start_timestamp = held.start_timestamp;
+            config.end_timestamp = None;
+        } else {
+            if let Some((media, track)) = playing {
+                media.apply(track, &mut config);
+            } else if let (Some(carousel), Some(rotation)) =
+                (&mut self.carousel, &self.config.rotation)
+            {
+                if carousel.advance(now) {
+                    debug!("Showing slide {}", carousel.current() + 1);
+                }
+                rotation.slides[carousel.current()].apply(&mut config);
+            }
+            if let Some(overrides) = &self.overrides {
+                overrides.apply(&mut config);
+            }
+            if let Some(fields) = &self.rule_fields {
+                fields.apply(&mut config);
+            }
+        }
+        let mut context = template::Context::current(&self.profile, self.started);
+        context.track = self.track.clone();
+        context.shell = self.shell.clone();
+        let mut config = template::render_config(&config, &context);
+        self.outputs.apply(&mut config);
+        // Following media players, nothing is shown while none plays.
+        let paused = self.config.media.is_some() && self.track.is_none();
+        let cleared = rules::hidden(self.rule_clear, self.cleared, paused);
+        let listening = self.held.is_none() && playing.is_some();
+        let activity = (self.held.is_some() || !cleared).then(|| Activity {
+            kind: listening.then_some(media::LISTENING),
+            ..build_activity(&config)
+        });
+        if self.last_sent.as_ref() == Some(&activity) {
+            debug!("Activity unchanged; not resending");
+            self.pending = false;
+            return Ok(());
+        }
+
+        while self
+            .sent_at
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= RATE_WINDOW)
+        {
+            self.sent_at.pop_front();
+        }
+        if self.sent_at.len() >= RATE_LIMIT {
+            debug!("Rate limited; holding the activity back");
+            self.pending = true;
+            return Ok(());
+        }
+        self.sent_at.push_back(now);
+        self.pending = false;
 
         let nonce = Uuid::new_v4().to_string();
         let payload = SetActivity {
             cmd: "SET_ACTIVITY",
             args: SetActivityArgs {
-                pid: std::process::id(),
+                pid: self
+                    .held
+                    .as_ref()
+                    .and_then(|held| held.pid)
+                    .unwrap_or_else(std::process::id),
                 activity,
             },
             nonce: nonce.clone(),
@@ -128,6 +312,7 @@ impl RichPresence {
 
         debug!("Sending activity update: {:?}", payload);
         self.stream_manager.write(&payload, 1)?;
+        self.last_sent = Some(payload.args.activity);
 
         // Wait for activity response
         let (op, response) = self.stream_manager.read()?;
@@ -139,3 +324,71 @@ impl RichPresence {
         Ok(())
     }
 }
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Builds the activity payload, leaving out every optional field that is
+/// empty. Discord rejects empty strings rather than ignoring them.
+fn build_activity(config: &Config) -> Activity {
+    let mut assets = serde_json::Map::new();
+    for (key, value) in [
+        ("large_image", &config.large_image),
+        ("large_text", &config.large_text),
+        ("small_image", &config.small_image),
+        ("small_text", &config.small_text),
+    ] {
+        if !value.is_empty() {
+            assets.insert(key.to_string(), serde_json::Value::String(value.clone()));
+        }
+    }
+
+    let mut timestamps = serde_json::Map::new();
+    for (key, value) in [
+        ("start", config.start_timestamp),
+        ("end", config.end_timestamp),
+    ] {
+        if let Some(value) = value {
+            timestamps.insert(key.to_string(), value.into());
+        }
+    }
+
+    let buttons = config
+        .buttons
+        .as_ref()
+        .filter(|buttons| !buttons.is_empty())
+        .cloned();
+
+    Activity {
+        kind: None,
+        state: non_empty(&config.state),
+        details: non_empty(&config.details),
+        // Slides share these, so the elapsed time keeps counting across
+        // them.
+        timestamps: if timestamps.is_empty() {
+            None
+        } else {
+            Some(serde_json::Value::Object(timestamps))
+        },
+        assets: if assets.is_empty() {
+            None
+        } else {
+            Some(serde_json::Value::Object(assets))
+        },
+        party: if config.max_party_size > 0 {
+            Some(serde_json::json!({
+                "size": [config.party_size, config.max_party_size]
+            }))
+        } else {
+            None
+        },
+        secrets: None,
+        buttons,
+        instance: Some(false),
+    }
+}
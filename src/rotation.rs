@@ -0,0 +1,280 @@
+use crate::config::{Button, Config};
+use crate::error::Result;
+use crate::source;
+use crate::validation;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Discord shows other users at most one presence update every 15
+/// seconds, so slides that change faster would be skipped.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Activities the daemon cycles through:
+///
+/// ```toml
+/// [rotation]
+/// interval = "30s"
+///
+/// [[rotation.slides]]
+/// details = "Writing docs"
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Rotation {
+    /// How long each slide is shown, such as `30s` or `5m`. Defaults to
+    /// `60s`, and must be at least `15s`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// Whether slides are shown in order or at random.
+    #[serde(default)]
+    pub order: Order,
+    /// The activities shown in turn.
+    #[serde(default)]
+    pub slides: Vec<Slide>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Sequential,
+    Random,
+}
+
+/// One activity of a rotation. Fields it leaves out keep the value of the
+/// configuration, as do the party, timestamps and secrets.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Slide {
+    /// First line of the activity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::TEXT_MAX))]
+    pub details: Option<String>,
+    /// Second line of the activity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::TEXT_MAX))]
+    pub state: Option<String>,
+    /// Asset key or URL of the large image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::IMAGE_KEY_MAX))]
+    pub large_image: Option<String>,
+    /// Tooltip of the large image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::TEXT_MAX))]
+    pub large_text: Option<String>,
+    /// Asset key or URL of the small image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::IMAGE_KEY_MAX))]
+    pub small_image: Option<String>,
+    /// Tooltip of the small image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::TEXT_MAX))]
+    pub small_text: Option<String>,
+    /// Buttons shown under the activity. Discord shows at most two.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(length(max = validation::MAX_BUTTONS))]
+    pub buttons: Option<Vec<Button>>,
+}
+
+impl Rotation {
+    pub fn interval(&self) -> Result<Duration> {
+        self.interval
+            .as_deref()
+            .map_or(Ok(DEFAULT_INTERVAL), source::parse_duration)
+    }
+}
+
+impl Slide {
+    /// The text fields the slide sets, by name.
+    pub fn texts(&self) -> [(&'static str, Option<&String>); 4] {
+        [
+            ("details", self.details.as_ref()),
+            ("state", self.state.as_ref()),
+            ("large_text", self.large_text.as_ref()),
+            ("small_text", self.small_text.as_ref()),
+        ]
+    }
+
+    /// Puts the slide's fields over `config`. A field the slide sets no
+    /// longer comes from a command.
+    pub fn apply(&self, config: &mut Config) {
+        for (value, field) in [
+            (&self.details, &mut config.details),
+            (&self.state, &mut config.state),
+            (&self.large_image, &mut config.large_image),
+            (&self.large_text, &mut config.large_text),
+            (&self.small_image, &mut config.small_image),
+            (&self.small_text, &mut config.small_text),
+        ] {
+            if let Some(value) = value {
+                field.clone_from(value);
+            }
+        }
+        for (field, value) in self.texts() {
+            if value.is_some() {
+                config.sources.remove(field);
+            }
+        }
+        if self.buttons.is_some() {
+            config.buttons.clone_from(&self.buttons);
+        }
+    }
+
+    /// The slide's fields, with those it leaves out taken from `lower`.
+    pub fn or(self, lower: &Slide) -> Slide {
+        Slide {
+            details: self.details.or_else(|| lower.details.clone()),
+            state: self.state.or_else(|| lower.state.clone()),
+            large_image: self.large_image.or_else(|| lower.large_image.clone()),
+            large_text: self.large_text.or_else(|| lower.large_text.clone()),
+            small_image: self.small_image.or_else(|| lower.small_image.clone()),
+            small_text: self.small_text.or_else(|| lower.small_text.clone()),
+            buttons: self.buttons.or_else(|| lower.buttons.clone()),
+        }
+    }
+
+    /// One line describing the slide, for previews.
+    pub fn summary(&self, config: &Config) -> String {
+        let mut shown = config.clone();
+        self.apply(&mut shown);
+        let mut parts: Vec<&str> = [&shown.details, &shown.state]
+            .into_iter()
+            .filter(|text| !text.is_empty())
+            .map(String::as_str)
+            .collect();
+        if parts.is_empty() {
+            parts.push("(no text)");
+        }
+        parts.join(" · ")
+    }
+}
+
+/// Which slide of a rotation is showing, and until when.
+#[derive(Debug)]
+pub struct Carousel {
+    len: usize,
+    order: Order,
+    interval: Duration,
+    current: usize,
+    next_at: Instant,
+}
+
+impl Carousel {
+    /// Starts on the first slide. `None` when there is nothing to rotate.
+    pub fn new(rotation: &Rotation, now: Instant) -> Option<Self> {
+        if rotation.slides.is_empty() {
+            return None;
+        }
+        let interval = rotation.interval().unwrap_or(DEFAULT_INTERVAL);
+        Some(Self {
+            len: rotation.slides.len(),
+            order: rotation.order,
+            interval,
+            current: 0,
+            next_at: now + interval,
+        })
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// When the next slide is due.
+    pub fn next_at(&self) -> Instant {
+        self.next_at
+    }
+
+    /// Moves to the next slide once the current one has been shown for the
+    /// interval, returning whether it did.
+    pub fn advance(&mut self, now: Instant) -> bool {
+        if now < self.next_at {
+            return false;
+        }
+        self.current = match self.order {
+            _ if self.len == 1 => 0,
+            Order::Sequential => (self.current + 1) % self.len,
+            // Any slide but the current one, so that every change shows.
+            Order::Random => (self.current + 1 + random() % (self.len - 1)) % self.len,
+        };
+        self.next_at = now + self.interval;
+        true
+    }
+}
+
+/// A random number from the standard library's per-process hash keys,
+/// which is plenty for picking a slide.
+fn random() -> usize {
+    RandomState::new().build_hasher().finish() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(order: Order) -> Rotation {
+        let slide = |details: &str| Slide {
+            details: Some(details.to_string()),
+            ..Slide::default()
+        };
+        Rotation {
+            interval: Some("30s".to_string()),
+            order,
+            slides: vec![slide("one"), slide("two"), slide("three")],
+        }
+    }
+
+    #[test]
+    fn test_sequential_rotation() {
+        let start = Instant::now();
+        let mut carousel = Carousel::new(&rotation(Order::Sequential), start).unwrap();
+        assert!(!carousel.advance(start + Duration::from_secs(29)));
+        let mut shown = vec![carousel.current()];
+        for step in 1..=3 {
+            assert!(carousel.advance(start + Duration::from_secs(30 * step)));
+            shown.push(carousel.current());
+        }
+        assert_eq!(shown, vec![0, 1, 2, 0]);
+        assert_eq!(carousel.next_at(), start + Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_random_rotation_always_changes_slide() {
+        let start = Instant::now();
+        let mut carousel = Carousel::new(&rotation(Order::Random), start).unwrap();
+        for step in 1..=20 {
+            let previous = carousel.current();
+            carousel.advance(start + Duration::from_secs(30 * step));
+            assert_ne!(carousel.current(), previous);
+            assert!(carousel.current() < 3);
+        }
+    }
+
+    #[test]
+    fn test_slide_overrides_only_its_fields() {
+        let mut config = Config {
+            details: "Coding".to_string(),
+            state: "fallback".to_string(),
+            large_image: "logo".to_string(),
+            start_timestamp: Some(1_700_000_000),
+            ..Config::default()
+        };
+        config
+            .sources
+            .insert("state".to_string(), source::FieldSource::default());
+        let slide = Slide {
+            state: Some("Reviewing".to_string()),
+            ..Slide::default()
+        };
+        slide.apply(&mut config);
+        assert_eq!(config.details, "Coding");
+        assert_eq!(config.state, "Reviewing");
+        assert_eq!(config.large_image, "logo");
+        assert_eq!(config.start_timestamp, Some(1_700_000_000));
+        assert!(config.sources.is_empty());
+    }
+}
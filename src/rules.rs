@@ -0,0 +1,780 @@
+use crate::error::{Error, Result};
+use crate::format;
+use crate::processes::{self, Process};
+use crate::profile;
+use crate::rotation::Slide;
+use crate::schedule::{Window, Zone};
+use crate::source;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+
+const FILE_STEM: &str = "rules";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a `command` condition may run before it counts as not holding.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The rules file, `rules.toml` unless it was written in another format.
+pub fn path(config_dir: &Path) -> PathBuf {
+    format::find(config_dir, FILE_STEM)
+}
+
+/// The rules in `config_dir`, or `None` when there is no rules file.
+pub fn load(config_dir: &Path) -> Result<Option<Rules>> {
+    let path = path(config_dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => Rules::parse(&content, &path).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+}
+
+/// The rules file as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    interval: Option<String>,
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    days: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    processes: Vec<String>,
+    #[serde(default)]
+    file: Option<String>,
+    #[serde(default)]
+    env: Option<String>,
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    command: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    set: Option<Slide>,
+    #[serde(default)]
+    clear: Option<bool>,
+}
+
+/// Rules that each combine conditions with what to do while they all
+/// hold. Each part of the result is decided by the highest-ranked rule
+/// that matches and has a say in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    /// How often the conditions are checked.
+    pub interval: Duration,
+    zone: Zone,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: Option<String>,
+    pub priority: i32,
+    conditions: Vec<Condition>,
+    pub profile: Option<String>,
+    set: Option<Slide>,
+    pub clear: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Time(Window),
+    /// Any of these processes is running.
+    Processes(Vec<String>),
+    /// The file exists.
+    File(String),
+    /// The variable is set and not empty, or set to `value`, in the
+    /// environment the daemon was started with.
+    Env {
+        name: String,
+        value: Option<String>,
+    },
+    Hostname(String),
+    /// The command exits successfully.
+    Command(String),
+}
+
+/// What the rules see of the system, gathered once per check.
+#[derive(Debug, Clone)]
+pub struct Facts {
+    pub now: DateTime<Utc>,
+    pub processes: Vec<Process>,
+    pub hostname: String,
+    pub env: HashMap<String, String>,
+}
+
+/// How one condition turned out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub condition: String,
+    pub held: bool,
+    /// What was found, such as `nvim is running (pid 42)`.
+    pub detail: String,
+}
+
+/// How one rule turned out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    /// The conditions checked, in order, up to the first that did not
+    /// hold.
+    pub checks: Vec<Check>,
+    /// The conditions left unchecked after one did not hold.
+    pub skipped: Vec<String>,
+    /// Every condition held.
+    pub matched: bool,
+    /// The process a `processes` condition found, with its language, for
+    /// `{process}` and `{language}`.
+    process: Option<(String, Option<String>)>,
+}
+
+/// What the rules decided. Parts left `None` are up to the process rules
+/// and the schedule.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    pub profile: Option<String>,
+    pub set: Option<Slide>,
+    pub clear: Option<bool>,
+}
+
+impl Rules {
+    /// Parses a rules file; `origin` picks the format and labels errors.
+    pub fn parse(content: &str, origin: &Path) -> Result<Self> {
+        let table = format::parse(
+            content,
+            format::Format::detect(&origin.to_string_lossy(), content),
+            origin,
+        )?;
+        let label = origin.display();
+        let file: File = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| Error::Config(format!("{}: {}", label, e.message())))?;
+        let interval = file
+            .interval
+            .as_deref()
+            .map_or(Ok(DEFAULT_INTERVAL), source::parse_duration)
+            .map_err(|e| Error::Config(format!("{}: interval: {}", label, e)))?;
+        if interval < MIN_INTERVAL {
+            return Err(Error::Config(format!(
+                "{}: interval must be at least {:?}",
+                label, MIN_INTERVAL
+            )));
+        }
+        let zone = Zone::parse(file.timezone.as_deref())
+            .map_err(|message| Error::Config(format!("{}: {}", label, message)))?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                Rule::compile(rule).map_err(|message| {
+                    Error::Config(format!("{}: rule {}: {}", label, i + 1, message))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            interval,
+            zone,
+            rules,
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The timezone times are read in, for display.
+    pub fn timezone(&self) -> String {
+        self.zone.name()
+    }
+
+    /// `at` as a wall-clock time in the rules' timezone.
+    pub fn display(&self, at: DateTime<Utc>) -> String {
+        self.zone.display(at)
+    }
+
+    /// Checks every condition of every rule.
+    pub fn evaluate(&self, facts: &Facts) -> Vec<Evaluation> {
+        self.rules
+            .iter()
+            .map(|rule| rule.evaluate(&self.zone, facts))
+            .collect()
+    }
+
+    /// The indices of the rules that matched, highest-ranked first: by
+    /// priority, then in the order they are written.
+    fn ranked(&self, evaluations: &[Evaluation]) -> Vec<usize> {
+        let mut ranked: Vec<usize> = (0..self.rules.len())
+            .filter(|i| {
+                evaluations
+                    .get(*i)
+                    .is_some_and(|evaluation| evaluation.matched)
+            })
+            .collect();
+        ranked.sort_by_key(|i| Reverse(self.rules[*i].priority));
+        ranked
+    }
+
+    /// Merges the rules that matched. Each part is taken from the
+    /// highest-ranked rule that sets it, field by field for `set`. Also
+    /// returns which rule decided each part, by index.
+    pub fn decide(&self, evaluations: &[Evaluation]) -> (Outcome, Vec<(&'static str, usize)>) {
+        let mut outcome = Outcome::default();
+        let mut reasons = Vec::new();
+        for i in self.ranked(evaluations) {
+            let rule = &self.rules[i];
+            if outcome.profile.is_none() && rule.profile.is_some() {
+                outcome.profile.clone_from(&rule.profile);
+                reasons.push(("profile", i));
+            }
+            if outcome.clear.is_none() && rule.clear.is_some() {
+                outcome.clear = rule.clear;
+                reasons.push(("clear", i));
+            }
+            if let Some(set) = &rule.set {
+                let set = match &evaluations[i].process {
+                    Some((process, language)) => processes::fill(set, process, language.as_deref()),
+                    None => set.clone(),
+                };
+                let decided = outcome.set.as_ref().map(fields).unwrap_or_default();
+                for field in fields(&set) {
+                    if !decided.contains(&field) {
+                        reasons.push((field, i));
+                    }
+                }
+                outcome.set = Some(match outcome.set.take() {
+                    Some(upper) => upper.or(&set),
+                    None => set,
+                });
+            }
+        }
+        (outcome, reasons)
+    }
+
+    /// Checks the conditions and merges the rules that matched.
+    pub fn outcome(&self, facts: &Facts) -> Outcome {
+        self.decide(&self.evaluate(facts)).0
+    }
+}
+
+impl Rule {
+    fn compile(rule: RuleFile) -> std::result::Result<Self, String> {
+        if rule.profile.is_none() && rule.set.is_none() && rule.clear.is_none() {
+            return Err("needs a profile, fields to set or clear".to_string());
+        }
+        if rule.set.is_some() && rule.clear == Some(true) {
+            return Err("fields to set would not show with clear = true".to_string());
+        }
+        if let Some(name) = &rule.profile {
+            profile::validate_name(name).map_err(|e| e.to_string())?;
+        }
+        let mut conditions = Vec::new();
+        if rule.days.is_some() || rule.from.is_some() || rule.to.is_some() {
+            conditions.push(Condition::Time(Window::parse(
+                rule.days,
+                rule.from.as_deref(),
+                rule.to.as_deref(),
+            )?));
+        }
+        if !rule.processes.is_empty() {
+            conditions.push(Condition::Processes(rule.processes));
+        }
+        if let Some(file) = rule.file {
+            conditions.push(Condition::File(file));
+        }
+        if let Some(env) = rule.env {
+            let (name, value) = match env.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (env, None),
+            };
+            if name.is_empty() {
+                return Err("env: needs a variable name".to_string());
+            }
+            conditions.push(Condition::Env { name, value });
+        }
+        if let Some(hostname) = rule.hostname {
+            conditions.push(Condition::Hostname(hostname));
+        }
+        if let Some(command) = rule.command {
+            conditions.push(Condition::Command(command));
+        }
+        Ok(Self {
+            name: rule.name,
+            priority: rule.priority,
+            conditions,
+            profile: rule.profile,
+            set: rule.set,
+            clear: rule.clear,
+        })
+    }
+
+    /// Checks the conditions in order, stopping at the first that does not
+    /// hold so that a `command` only runs when everything before it holds.
+    fn evaluate(&self, zone: &Zone, facts: &Facts) -> Evaluation {
+        let mut process = None;
+        let mut checks: Vec<Check> = Vec::new();
+        let mut conditions = self.conditions.iter();
+        for condition in conditions.by_ref() {
+            let (held, detail) = match condition {
+                Condition::Time(window) => (
+                    window.contains(zone.wall(facts.now)),
+                    format!("it is {}", zone.display(facts.now)),
+                ),
+                Condition::Processes(names) => {
+                    let found = facts.processes.iter().find_map(|running| {
+                        let name = running.names.iter().find(|name| names.contains(name))?;
+                        Some((running, name))
+                    });
+                    match found {
+                        Some((running, name)) => {
+                            process = Some((name.clone(), processes::language(&running.args)));
+                            (true, format!("{} is running (pid {})", name, running.pid))
+                        }
+                        None => (false, "none is running".to_string()),
+                    }
+                }
+                Condition::File(file) => {
+                    if source::expand_home(file).exists() {
+                        (true, "exists".to_string())
+                    } else {
+                        (false, "not found".to_string())
+                    }
+                }
+                Condition::Env { name, value } => match (facts.env.get(name), value) {
+                    (None, _) => (false, "not set".to_string()),
+                    (Some(actual), Some(value)) => (actual == value, format!("is '{}'", actual)),
+                    (Some(actual), None) => (!actual.is_empty(), format!("is '{}'", actual)),
+                },
+                Condition::Hostname(hostname) => (
+                    hostname.eq_ignore_ascii_case(&facts.hostname),
+                    format!("this is '{}'", facts.hostname),
+                ),
+                Condition::Command(command) => match source::run(command, COMMAND_TIMEOUT) {
+                    Ok(_) => (true, "succeeded".to_string()),
+                    Err(e) => (false, e),
+                },
+            };
+            checks.push(Check {
+                condition: condition.to_string(),
+                held,
+                detail,
+            });
+            if !held {
+                break;
+            }
+        }
+        Evaluation {
+            matched: checks.iter().all(|check| check.held),
+            checks,
+            skipped: conditions.map(Condition::to_string).collect(),
+            process,
+        }
+    }
+
+    /// What the rule does, such as `profile 'work', set details`.
+    pub fn actions(&self) -> String {
+        let mut actions = Vec::new();
+        if let Some(name) = &self.profile {
+            actions.push(format!("profile '{}'", name));
+        }
+        if let Some(set) = &self.set {
+            actions.push(format!("set {}", fields(set).join(", ")));
+        }
+        match self.clear {
+            Some(true) => actions.push("clear the presence".to_string()),
+            Some(false) => actions.push("show the presence".to_string()),
+            None => {}
+        }
+        actions.join(", ")
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Time(window) => write!(f, "time {}", window.describe()),
+            Condition::Processes(names) => write!(f, "processes {}", names.join(", ")),
+            Condition::File(file) => write!(f, "file {}", file),
+            Condition::Env {
+                name,
+                value: Some(value),
+            } => write!(f, "env {}={}", name, value),
+            Condition::Env { name, value: None } => write!(f, "env {}", name),
+            Condition::Hostname(hostname) => write!(f, "hostname {}", hostname),
+            Condition::Command(command) => write!(f, "command {}", command),
+        }
+    }
+}
+
+impl Facts {
+    /// The system as it is now, with processes listed under `proc_root`
+    /// and `env` as the environment `env` conditions look at.
+    pub fn gather(proc_root: &Path, env: HashMap<String, String>) -> Self {
+        Self {
+            now: Utc::now(),
+            processes: processes::scan(proc_root),
+            hostname: nix::unistd::gethostname()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+                .unwrap_or_default(),
+            env,
+        }
+    }
+}
+
+/// Whether nothing is shown, unless `dstatus run` holds an activity. A
+/// followed media player that is paused or stopped hides the presence
+/// whatever the rules say; otherwise the rules' `clear` outranks the
+/// schedule's.
+pub fn hidden(rule_clear: Option<bool>, scheduled: bool, paused: bool) -> bool {
+    paused || rule_clear.unwrap_or(scheduled)
+}
+
+/// Switches profiles for the rules. Their profile stays active while they
+/// pick it, and the one active before comes back afterwards, unless another
+/// was chosen in the meantime. A profile pinned by hand outranks the rules:
+/// they switch nothing until it is released, and then switch to whatever
+/// they pick at that point.
+#[derive(Debug, Default)]
+pub struct Switcher {
+    /// The profile the rules switched to, while they pick it.
+    applied: Option<String>,
+    /// The profile that was active before the rules switched.
+    restore: Option<String>,
+}
+
+impl Switcher {
+    /// The profile the rules have switched to, if any.
+    pub fn applied(&self) -> Option<&str> {
+        self.applied.as_deref()
+    }
+
+    /// The profile to switch to now that the rules pick `choice`, with
+    /// `active` the active profile, if any needs switching to.
+    pub fn follow(
+        &mut self,
+        choice: Option<String>,
+        active: Option<String>,
+        pinned: bool,
+    ) -> Option<String> {
+        if pinned {
+            *self = Self::default();
+            return None;
+        }
+        if choice == self.applied {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.applied, choice.clone());
+        match choice {
+            Some(name) => {
+                if self.restore.is_none() {
+                    self.restore = active;
+                }
+                Some(name)
+            }
+            None => self.restore.take().filter(|_| active == previous),
+        }
+    }
+}
+
+/// The names of the fields `slide` sets.
+fn fields(slide: &Slide) -> Vec<&'static str> {
+    [
+        ("details", slide.details.is_some()),
+        ("state", slide.state.is_some()),
+        ("large_image", slide.large_image.is_some()),
+        ("large_text", slide.large_text.is_some()),
+        ("small_image", slide.small_image.is_some()),
+        ("small_text", slide.small_text.is_some()),
+        ("buttons", slide.buttons.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// Checks the rules in the background and keeps the latest outcome.
+pub struct Watcher {
+    current: Arc<Mutex<Outcome>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl Watcher {
+    /// Checks the rules every `rules.interval`, with processes listed under
+    /// `proc_root`. `on_change` is called from the checking thread after
+    /// the first check and whenever the outcome changes.
+    pub fn start(rules: Rules, proc_root: PathBuf, on_change: impl Fn() + Send + 'static) -> Self {
+        let current = Arc::new(Mutex::new(Outcome::default()));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let shared = Arc::clone(&current);
+        // The daemon's environment is fixed once it has started.
+        let env: HashMap<String, String> = std::env::vars().collect();
+        thread::spawn(move || {
+            let mut last = None;
+            loop {
+                let outcome = rules.outcome(&Facts::gather(&proc_root, env.clone()));
+                if last.as_ref() != Some(&outcome) {
+                    debug!("Rules: {:?}", outcome);
+                    *shared.lock().unwrap() = outcome.clone();
+                    last = Some(outcome);
+                    on_change();
+                }
+                match stop_rx.recv_timeout(rules.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+        Self {
+            current,
+            _stop: stop_tx,
+        }
+    }
+
+    pub fn current(&self) -> Outcome {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    const RULES: &str = r#"
+timezone = "Europe/Berlin"
+
+[[rule]]
+name = "Office"
+days = "weekdays"
+from = "09:00"
+to = "17:30"
+hostname = "workstation"
+profile = "work"
+set = { state = "At the office" }
+
+[[rule]]
+name = "Coding"
+processes = ["nvim"]
+priority = 10
+set = { details = "Editing {language}", small_image = "{language}" }
+
+[[rule]]
+name = "Quiet"
+env = "DSTATUS_QUIET=1"
+priority = 20
+clear = true
+"#;
+
+    fn rules() -> Rules {
+        Rules::parse(RULES, Path::new("rules.toml")).unwrap()
+    }
+
+    /// Friday 2026-10-16 at `hour`:00 in Berlin, on the workstation, with
+    /// nothing running.
+    fn facts(hour: u32) -> Facts {
+        Facts {
+            now: chrono_tz::Europe::Berlin
+                .with_ymd_and_hms(2026, 10, 16, hour, 0, 0)
+                .unwrap()
+                .with_timezone(&Utc),
+            processes: Vec::new(),
+            hostname: "Workstation".to_string(),
+            env: HashMap::new(),
+        }
+    }
+
+    fn nvim() -> Process {
+        Process {
+            pid: 42,
+            names: vec!["nvim".to_string()],
+            args: vec!["src/main.rs".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_every_condition_must_hold() {
+        let rules = rules();
+        let evaluations = rules.evaluate(&facts(10));
+        assert!(evaluations[0].matched);
+        assert_eq!(evaluations[0].checks.len(), 2);
+        assert!(!evaluations[1].matched);
+        assert_eq!(evaluations[1].checks[0].detail, "none is running");
+
+        let mut elsewhere = facts(10);
+        elsewhere.hostname = "laptop".to_string();
+        assert_eq!(rules.outcome(&elsewhere), Outcome::default());
+        assert_eq!(rules.outcome(&facts(18)), Outcome::default());
+    }
+
+    #[test]
+    fn test_parts_are_decided_by_rank() {
+        let rules = rules();
+        let mut facts = facts(10);
+        facts.processes.push(nvim());
+        let (outcome, reasons) = rules.decide(&rules.evaluate(&facts));
+        assert_eq!(outcome.profile.as_deref(), Some("work"));
+        let set = outcome.set.unwrap();
+        // The coding rule outranks the office one, which still fills in
+        // the fields coding leaves alone.
+        assert_eq!(set.details.as_deref(), Some("Editing rust"));
+        assert_eq!(set.small_image.as_deref(), Some("rust"));
+        assert_eq!(set.state.as_deref(), Some("At the office"));
+        assert_eq!(outcome.clear, None);
+        assert!(reasons.contains(&("details", 1)));
+        assert!(reasons.contains(&("state", 0)));
+        assert!(reasons.contains(&("profile", 0)));
+
+        facts
+            .env
+            .insert("DSTATUS_QUIET".to_string(), "1".to_string());
+        assert_eq!(rules.outcome(&facts).clear, Some(true));
+        facts
+            .env
+            .insert("DSTATUS_QUIET".to_string(), "0".to_string());
+        assert_eq!(rules.outcome(&facts).clear, None);
+    }
+
+    #[test]
+    fn test_pinned_profile_outranks_rules() {
+        let profile = |name: &str| Some(name.to_string());
+        let mut switcher = Switcher::default();
+        assert_eq!(
+            switcher.follow(profile("work"), profile("default"), false),
+            profile("work")
+        );
+        assert_eq!(switcher.applied(), Some("work"));
+
+        // Pinned by hand: the rules leave the profile alone, whatever they
+        // pick meanwhile.
+        assert_eq!(
+            switcher.follow(profile("work"), profile("gaming"), true),
+            None
+        );
+        assert_eq!(switcher.follow(None, profile("gaming"), true), None);
+        assert_eq!(
+            switcher.follow(profile("work"), profile("gaming"), true),
+            None
+        );
+        assert_eq!(switcher.applied(), None);
+
+        // Released: the rules switch again, and give the pinned profile
+        // back once they stop picking theirs.
+        assert_eq!(
+            switcher.follow(profile("work"), profile("gaming"), false),
+            profile("work")
+        );
+        assert_eq!(
+            switcher.follow(None, profile("work"), false),
+            profile("gaming")
+        );
+
+        // A profile switched to some other way is not undone.
+        switcher.follow(profile("work"), profile("default"), false);
+        assert_eq!(switcher.follow(None, profile("gaming"), false), None);
+    }
+
+    #[test]
+    fn test_media_pause_outranks_rules() {
+        let rules = rules();
+        let mut quiet = facts(10);
+        quiet
+            .env
+            .insert("DSTATUS_QUIET".to_string(), "1".to_string());
+        let quiet = rules.outcome(&quiet).clear;
+        let shown = Some(false);
+
+        assert!(!hidden(None, false, false));
+        assert!(hidden(None, false, true));
+        assert!(hidden(quiet, false, false));
+        // A rule can show what the schedule clears, but not a paused
+        // player.
+        assert!(hidden(None, true, false));
+        assert!(!hidden(shown, true, false));
+        assert!(hidden(shown, false, true));
+    }
+
+    #[test]
+    fn test_file_and_command_conditions() {
+        let dir = tempdir().unwrap();
+        let flag = dir.path().join("focus");
+        let content = format!(
+            "[[rule]]\nfile = {:?}\ncommand = \"test -d /\"\nclear = true\n",
+            flag
+        );
+        let rules = Rules::parse(&content, Path::new("rules.toml")).unwrap();
+        assert_eq!(rules.outcome(&facts(10)).clear, None);
+        fs::write(&flag, "").unwrap();
+        assert_eq!(rules.outcome(&facts(10)).clear, Some(true));
+
+        let failing = Rules::parse(
+            "[[rule]]\ncommand = \"exit 3\"\nclear = true\n",
+            Path::new("rules.toml"),
+        )
+        .unwrap();
+        assert!(!failing.evaluate(&facts(10))[0].matched);
+    }
+
+    #[test]
+    fn test_conditions_stop_at_the_first_that_fails() {
+        let dir = tempdir().unwrap();
+        let ran = dir.path().join("ran");
+        let content = format!(
+            "timezone = \"Europe/Berlin\"\n[[rule]]\nfrom = \"09:00\"\nto = \"17:00\"\ncommand = \"touch {}\"\nclear = true\n",
+            ran.display()
+        );
+        let rules = Rules::parse(&content, Path::new("rules.toml")).unwrap();
+
+        let evaluation = &rules.evaluate(&facts(20))[0];
+        assert!(!evaluation.matched);
+        assert_eq!(evaluation.checks.len(), 1);
+        assert_eq!(evaluation.skipped.len(), 1);
+        assert!(!ran.exists());
+
+        let evaluation = &rules.evaluate(&facts(10))[0];
+        assert!(evaluation.matched);
+        assert!(evaluation.skipped.is_empty());
+        assert!(ran.exists());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let parse = |content| Rules::parse(content, Path::new("rules.toml"));
+        assert!(parse("[[rule]]\nprocesses = [\"nvim\"]\n").is_err());
+        assert!(parse("[[rule]]\nclear = true\nset = { details = \"x\" }\n").is_err());
+        assert!(parse("[[rule]]\nenv = \"=x\"\nclear = true\n").is_err());
+        assert!(parse("[[rule]]\nfrom = \"25:00\"\nclear = true\n").is_err());
+        assert!(parse("timezone = \"Mars/Olympus\"\n").is_err());
+        assert!(parse("[[rule]]\nwhen = \"never\"\nclear = true\n").is_err());
+    }
+}
@@ -0,0 +1,529 @@
+use crate::error::{Error, Result};
+use crate::format;
+use crate::profile;
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const FILE_STEM: &str = "schedule";
+
+/// How far ahead `next_change` looks. A week covers every weekly rule.
+const LOOKAHEAD_DAYS: i64 = 8;
+
+/// The schedule file, `schedule.toml` unless it was written in another
+/// format.
+pub fn path(config_dir: &Path) -> PathBuf {
+    format::find(config_dir, FILE_STEM)
+}
+
+/// The schedule in `config_dir`, or `None` when there is no schedule file.
+pub fn load(config_dir: &Path) -> Result<Option<Schedule>> {
+    let path = path(config_dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => Schedule::parse(&content, &path).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+}
+
+/// The schedule file as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    days: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    clear: bool,
+}
+
+/// Rules choosing the active profile by the time of day and week. The
+/// first rule that matches wins; a rule without days or times always
+/// matches, so it works as the fallback when written last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    zone: Zone,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: Option<String>,
+    window: Window,
+    pub action: Action,
+}
+
+/// Days of the week and hours of the day, as written in rule files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    days: [bool; 7],
+    days_text: Option<String>,
+    from: NaiveTime,
+    /// `None` is the end of the day.
+    to: Option<NaiveTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Profile(String),
+    /// Show no activity at all.
+    Clear,
+}
+
+/// Where wall-clock times are read: a named timezone, or the system's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl Schedule {
+    /// Parses a schedule file; `origin` picks the format and labels errors.
+    pub fn parse(content: &str, origin: &Path) -> Result<Self> {
+        let table = format::parse(
+            content,
+            format::Format::detect(&origin.to_string_lossy(), content),
+            origin,
+        )?;
+        let label = origin.display();
+        let file: File = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| Error::Config(format!("{}: {}", label, e.message())))?;
+        let zone = Zone::parse(file.timezone.as_deref())
+            .map_err(|message| Error::Config(format!("{}: {}", label, message)))?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                Rule::compile(rule).map_err(|message| {
+                    Error::Config(format!("{}: rule {}: {}", label, i + 1, message))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { zone, rules })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The timezone rules are read in, for display.
+    pub fn timezone(&self) -> String {
+        self.zone.name()
+    }
+
+    /// The index of the rule in effect at `at`.
+    pub fn matching(&self, at: DateTime<Utc>) -> Option<usize> {
+        let wall = self.zone.wall(at);
+        self.rules
+            .iter()
+            .position(|rule| rule.window.contains(wall))
+    }
+
+    /// The first moment after `after` at which a different rule takes
+    /// effect. Only rule boundaries are checked, so this is exact rather
+    /// than polled, and clock changes are accounted for.
+    pub fn next_change(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let current = self.matching(after);
+        let mut times = vec![NaiveTime::MIN];
+        for rule in &self.rules {
+            times.push(rule.window.from);
+            times.extend(rule.window.to);
+        }
+        let today = self.zone.wall(after).date();
+        let mut candidates: Vec<DateTime<Utc>> = (0..=LOOKAHEAD_DAYS)
+            .map(|days| today + Duration::days(days))
+            .flat_map(|date| times.iter().map(move |time| date.and_time(*time)))
+            .flat_map(|wall| self.zone.instants(wall))
+            .filter(|instant| *instant > after)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .find(|instant| self.matching(*instant) != current)
+    }
+
+    /// The moment a wall-clock time such as `2026-10-18T21:00` stands for
+    /// in the schedule's timezone. Times with an explicit offset are taken
+    /// as they are.
+    pub fn resolve(&self, text: &str) -> Result<DateTime<Utc>> {
+        if let Ok(at) = DateTime::parse_from_rfc3339(text) {
+            return Ok(at.with_timezone(&Utc));
+        }
+        let wall = [
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%d %H:%M",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text.trim(), format).ok())
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "Invalid time '{}': use a form like 2026-10-18T21:00",
+                text
+            ))
+        })?;
+        match self.zone.instants(wall).first() {
+            Some(at) if self.zone.wall(*at) == wall => Ok(*at),
+            _ => Err(Error::Config(format!(
+                "{} does not exist in {}; the clocks skip it",
+                text,
+                self.timezone()
+            ))),
+        }
+    }
+
+    /// `at` as a wall-clock time in the schedule's timezone.
+    pub fn display(&self, at: DateTime<Utc>) -> String {
+        self.zone.display(at)
+    }
+}
+
+impl Rule {
+    fn compile(rule: RuleFile) -> std::result::Result<Self, String> {
+        let action = match (rule.profile, rule.clear) {
+            (Some(_), true) => return Err("use either profile or clear, not both".to_string()),
+            (None, false) => return Err("needs a profile, or clear = true".to_string()),
+            (Some(name), false) => {
+                profile::validate_name(&name).map_err(|e| e.to_string())?;
+                Action::Profile(name)
+            }
+            (None, true) => Action::Clear,
+        };
+        Ok(Self {
+            name: rule.name,
+            window: Window::parse(rule.days, rule.from.as_deref(), rule.to.as_deref())?,
+            action,
+        })
+    }
+
+    /// The rule's days and hours, such as `weekdays 09:00–17:30`.
+    pub fn describe(&self) -> String {
+        self.window.describe()
+    }
+}
+
+impl Window {
+    /// Parses `days` as in `parse_days` and `from` and `to` as `HH:MM`.
+    /// Days default to every day, and times to the whole day.
+    pub fn parse(
+        days: Option<String>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> std::result::Result<Self, String> {
+        let from = match from {
+            Some(text) => parse_time(text).map_err(|e| format!("from: {}", e))?,
+            None => NaiveTime::MIN,
+        };
+        let to = match to {
+            None | Some("24:00") => None,
+            Some(text) => Some(parse_time(text).map_err(|e| format!("to: {}", e))?),
+        };
+        if to == Some(from) {
+            return Err("from and to must differ".to_string());
+        }
+        Ok(Self {
+            days: match &days {
+                Some(text) => parse_days(text)?,
+                None => [true; 7],
+            },
+            days_text: days,
+            from,
+            to,
+        })
+    }
+
+    /// Whether the window covers the wall-clock time `wall`. A window that
+    /// ends before it starts runs past midnight and belongs to the day it
+    /// starts on.
+    pub fn contains(&self, wall: NaiveDateTime) -> bool {
+        let day = wall.weekday().num_days_from_monday() as usize;
+        let time = wall.time();
+        match self.to {
+            Some(to) if to < self.from => {
+                let previous = (day + 6) % 7;
+                (self.days[day] && time >= self.from) || (self.days[previous] && time < to)
+            }
+            to => self.days[day] && time >= self.from && to.is_none_or(|to| time < to),
+        }
+    }
+
+    /// The days and hours, such as `weekdays 09:00–17:30`.
+    pub fn describe(&self) -> String {
+        let days = self.days_text.as_deref().unwrap_or("every day");
+        let whole_day = self.from == NaiveTime::MIN && self.to.is_none();
+        match (whole_day, self.days_text.is_some()) {
+            (true, false) => "always".to_string(),
+            (true, true) => days.to_string(),
+            (false, _) => format!(
+                "{} {}–{}",
+                days,
+                self.from.format("%H:%M"),
+                self.to
+                    .map_or("24:00".to_string(), |to| to.format("%H:%M").to_string())
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Profile(name) => write!(f, "profile '{}'", name),
+            Action::Clear => write!(f, "clear the presence"),
+        }
+    }
+}
+
+impl Zone {
+    /// The zone named by an IANA name such as `Europe/Berlin`, or the
+    /// system's when there is none.
+    pub fn parse(name: Option<&str>) -> std::result::Result<Self, String> {
+        match name {
+            None => Ok(Zone::Local),
+            Some(name) => name
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| format!("unknown timezone '{}'", name)),
+        }
+    }
+
+    /// The zone's name, for display.
+    pub fn name(&self) -> String {
+        match self {
+            Zone::Local => "local time".to_string(),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+
+    /// `at` as a wall-clock time in the zone.
+    pub fn display(&self, at: DateTime<Utc>) -> String {
+        const FORMAT: &str = "%a %Y-%m-%d %H:%M %Z";
+        match self {
+            Zone::Local => at.with_timezone(&chrono::Local).format(FORMAT).to_string(),
+            Zone::Named(tz) => at.with_timezone(tz).format(FORMAT).to_string(),
+        }
+    }
+
+    pub fn wall(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => at.with_timezone(&chrono::Local).naive_local(),
+            Zone::Named(tz) => at.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// Every moment the wall-clock time `wall` occurs: twice when the
+    /// clocks go back over it, and when they skip it, the moment they jump.
+    fn instants(&self, wall: NaiveDateTime) -> Vec<DateTime<Utc>> {
+        match self {
+            Zone::Local => instants(&chrono::Local, wall),
+            Zone::Named(tz) => instants(tz, wall),
+        }
+    }
+}
+
+fn instants<Z: TimeZone>(zone: &Z, wall: NaiveDateTime) -> Vec<DateTime<Utc>> {
+    match zone.from_local_datetime(&wall) {
+        LocalResult::Single(at) => vec![at.with_timezone(&Utc)],
+        LocalResult::Ambiguous(first, second) => {
+            vec![first.with_timezone(&Utc), second.with_timezone(&Utc)]
+        }
+        LocalResult::None => (1..=24 * 60)
+            .map(|minutes| wall + Duration::minutes(minutes))
+            .find_map(|later| zone.from_local_datetime(&later).earliest())
+            .map(|at| at.with_timezone(&Utc))
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn parse_time(text: &str) -> std::result::Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(text.trim(), "%H:%M")
+        .map_err(|_| format!("invalid time '{}': use HH:MM", text))
+}
+
+/// Parses days such as `weekdays`, `weekends`, `daily`, `fri`, `mon-thu`
+/// or `sat, sun`.
+fn parse_days(text: &str) -> std::result::Result<[bool; 7], String> {
+    let mut days = [false; 7];
+    for part in text.split(',').map(|part| part.trim().to_lowercase()) {
+        let range = match part.as_str() {
+            "daily" | "every day" => (0, 6),
+            "weekdays" => (0, 4),
+            "weekends" | "weekend" => (5, 6),
+            _ => match part.split_once('-') {
+                Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+                None => (parse_day(&part)?, parse_day(&part)?),
+            },
+        };
+        // Ranges may wrap around the week, as in `fri-mon`.
+        let mut day = range.0;
+        loop {
+            days[day] = true;
+            if day == range.1 {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+/// A day by its name, or the first three letters or more of it.
+fn parse_day(text: &str) -> std::result::Result<usize, String> {
+    const NAMES: [&str; 7] = [
+        "monday",
+        "tuesday",
+        "wednesday",
+        "thursday",
+        "friday",
+        "saturday",
+        "sunday",
+    ];
+    let text = text.trim().to_lowercase();
+    NAMES
+        .iter()
+        .position(|name| text.len() >= 3 && name.starts_with(&text))
+        .ok_or_else(|| format!("unknown day '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORK_WEEK: &str = r#"
+timezone = "Europe/Berlin"
+
+[[rule]]
+name = "work"
+days = "weekdays"
+from = "09:00"
+to = "17:30"
+profile = "work"
+
+[[rule]]
+days = "fri"
+from = "20:00"
+profile = "gaming"
+
+[[rule]]
+clear = true
+"#;
+
+    fn from_str(content: &str) -> Schedule {
+        Schedule::parse(content, Path::new("schedule.toml")).unwrap()
+    }
+
+    fn berlin(text: &str) -> DateTime<Utc> {
+        let wall = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        chrono_tz::Europe::Berlin
+            .from_local_datetime(&wall)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let schedule = from_str(WORK_WEEK);
+        // 2026-10-16 is a Friday.
+        assert_eq!(schedule.matching(berlin("2026-10-16 09:00")), Some(0));
+        assert_eq!(schedule.matching(berlin("2026-10-16 17:30")), Some(2));
+        assert_eq!(schedule.matching(berlin("2026-10-16 21:00")), Some(1));
+        assert_eq!(schedule.matching(berlin("2026-10-17 10:00")), Some(2));
+        assert_eq!(schedule.rules()[2].describe(), "always");
+        assert_eq!(schedule.rules()[1].describe(), "fri 20:00–24:00");
+    }
+
+    #[test]
+    fn test_next_change() {
+        let schedule = from_str(WORK_WEEK);
+        // Sunday evening waits for Monday morning.
+        assert_eq!(
+            schedule.next_change(berlin("2026-10-18 21:00")),
+            Some(berlin("2026-10-19 09:00"))
+        );
+        // Friday's gaming lasts until midnight.
+        assert_eq!(
+            schedule.next_change(berlin("2026-10-16 21:00")),
+            Some(berlin("2026-10-17 00:00"))
+        );
+        let always = from_str("[[rule]]\nprofile = \"idle\"\n");
+        assert_eq!(always.next_change(berlin("2026-10-16 21:00")), None);
+    }
+
+    #[test]
+    fn test_windows_past_midnight() {
+        let schedule = from_str(
+            "timezone = \"Europe/Berlin\"\n[[rule]]\ndays = \"sat\"\nfrom = \"22:00\"\nto = \"02:00\"\nprofile = \"late\"\n",
+        );
+        assert_eq!(schedule.matching(berlin("2026-10-17 23:00")), Some(0));
+        assert_eq!(schedule.matching(berlin("2026-10-18 01:59")), Some(0));
+        assert_eq!(schedule.matching(berlin("2026-10-18 02:00")), None);
+        assert_eq!(schedule.matching(berlin("2026-10-18 23:00")), None);
+    }
+
+    #[test]
+    fn test_daylight_saving_boundaries() {
+        let schedule = from_str(
+            "timezone = \"Europe/Berlin\"\n[[rule]]\nfrom = \"02:30\"\nto = \"03:30\"\nprofile = \"night\"\n",
+        );
+        // On 2026-03-29 the clocks jump from 02:00 to 03:00 CEST, so the
+        // rule starts at the jump.
+        let start = schedule.next_change(berlin("2026-03-29 01:00")).unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-03-29T01:00:00+00:00");
+        assert_eq!(
+            schedule.next_change(start).unwrap().to_rfc3339(),
+            "2026-03-29T01:30:00+00:00"
+        );
+        // On 2026-10-25 02:30 happens twice; the rule starts at the first.
+        let start = schedule.next_change(berlin("2026-10-25 01:00")).unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-10-25T00:30:00+00:00");
+        assert!(schedule.resolve("2026-03-29T02:30").is_err());
+        assert_eq!(
+            schedule.resolve("2026-10-18T21:00").unwrap(),
+            berlin("2026-10-18 21:00")
+        );
+    }
+
+    #[test]
+    fn test_parse_days() {
+        let days = |text| parse_days(text).unwrap();
+        assert_eq!(
+            days("weekdays"),
+            [true, true, true, true, true, false, false]
+        );
+        assert_eq!(
+            days("fri-mon"),
+            [true, false, false, false, true, true, true]
+        );
+        assert_eq!(
+            days("Tues, thursday"),
+            [false, true, false, true, false, false, false]
+        );
+        assert!(parse_days("someday").is_err());
+        assert!(parse_days("friyay").is_err());
+    }
+}
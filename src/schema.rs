@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::history;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kept next to the configuration so editors can be pointed at it.
+pub const FILE_NAME: &str = "configuration.schema.json";
+
+/// The JSON Schema of `Config`, derived from the struct itself.
+pub fn generate() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).expect("schema serializes") + "\n"
+}
+
+pub fn path(config_dir: &Path) -> PathBuf {
+    config_dir.join(FILE_NAME)
+}
+
+/// Writes the schema into `config_dir` unless it is already up to date,
+/// so that it follows the installed version of dstatus.
+pub fn install(config_dir: &Path) -> Result<PathBuf> {
+    let path = path(config_dir);
+    let schema = generate();
+    match fs::read_to_string(&path) {
+        Ok(existing) if existing == schema => {}
+        Ok(_) => history::atomic_write(&path, &schema)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            history::atomic_write(&path, &schema)?
+        }
+        Err(e) => return Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn properties() -> serde_json::Map<String, Value> {
+        let schema: Value = serde_json::from_str(&generate()).unwrap();
+        schema["properties"].as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_schema_matches_config() {
+        let properties = properties();
+        let mut names: Vec<&str> = properties.keys().map(String::as_str).collect();
+        let mut fields = Config::field_names().to_vec();
+        // Sourced fields are written in place of the text, not under a key.
+        fields.retain(|field| *field != crate::source::SOURCES_KEY);
+        names.sort();
+        fields.sort();
+        assert_eq!(names, fields);
+
+        assert_eq!(properties["large_image"]["maxLength"], 256);
+        assert_eq!(properties["details"]["$ref"], "#/$defs/Text");
+        assert_eq!(properties["buttons"]["maxItems"], 2);
+        assert!(properties["client_id"]["description"].is_string());
+    }
+
+    /// The GUI mirrors `Config` by hand; fail here when the two drift.
+    #[test]
+    fn test_gui_types_match_schema() {
+        let types = include_str!("../dstatus-ui/src/types.ts");
+        let start = types.find("export interface Config {").unwrap();
+        let end = start + types[start..].find("\n}").unwrap();
+        let mut fields: Vec<&str> = types[start..end]
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.trim().split(['?', ':']).next())
+            .filter(|name| !name.is_empty())
+            .collect();
+        fields.sort();
+
+        let properties = properties();
+        let mut names: Vec<&str> = properties.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(fields, names);
+    }
+}
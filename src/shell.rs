@@ -0,0 +1,575 @@
+use crate::error::{Error, Result};
+use crate::format;
+use crate::source;
+use clap::ValueEnum;
+use nix::errno::Errno;
+use nix::sys::signal;
+use nix::unistd::Pid;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+const FILE_STEM: &str = "shell";
+
+const DEFAULT_THROTTLE: Duration = Duration::from_secs(5);
+/// How often the shell that reported last is checked to still be open.
+const LIVENESS: Duration = Duration::from_secs(10);
+
+/// Programs that run the command after them, which is the one shown.
+const WRAPPERS: &[&str] = &[
+    "sudo", "doas", "env", "exec", "time", "nohup", "nice", "command",
+];
+
+const BASH: &str = r#"# dstatus shell integration for bash. Add to ~/.bashrc:
+#   eval "$(dstatus shell-init bash)"
+__dstatus_report() {
+    (command dstatus shell-report --pid "$$" --cwd "$PWD" ${1:+--command "$1"} >/dev/null 2>&1 &)
+}
+__dstatus_prompt() {
+    __dstatus_report
+}
+__dstatus_ready() {
+    __dstatus_at_prompt=1
+}
+__dstatus_preexec() {
+    [ -n "$__dstatus_at_prompt" ] || return
+    [ -n "$COMP_LINE" ] && return
+    __dstatus_at_prompt=
+    # Enter on an empty line only runs the prompt command.
+    [ "$BASH_COMMAND" = __dstatus_prompt ] && return
+    __dstatus_report "$BASH_COMMAND"
+}
+# Takes `trap -- 'command' DEBUG` as arguments and adds to that trap, such
+# as bash-preexec's, rather than replacing it. bash hides the trap from
+# functions and sourced files, so it is read at the first prompt.
+__dstatus_install() {
+    __dstatus_trap=$3
+    trap 'eval "$__dstatus_trap"; __dstatus_preexec' DEBUG
+}
+PROMPT_COMMAND="__dstatus_prompt${PROMPT_COMMAND:+
+$PROMPT_COMMAND}
+[ -n \"\${__dstatus_trap+set}\" ] || eval \"__dstatus_install \$(trap -p DEBUG)\"
+__dstatus_ready"
+"#;
+
+const ZSH: &str = r#"# dstatus shell integration for zsh. Add to ~/.zshrc:
+#   eval "$(dstatus shell-init zsh)"
+__dstatus_report() {
+    (command dstatus shell-report --pid "$$" --cwd "$PWD" ${1:+--command "$1"} >/dev/null 2>&1 &)
+}
+__dstatus_preexec() {
+    __dstatus_report "$1"
+}
+__dstatus_precmd() {
+    __dstatus_report
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec __dstatus_preexec
+add-zsh-hook precmd __dstatus_precmd
+"#;
+
+const FISH: &str = r#"# dstatus shell integration for fish. Add to ~/.config/fish/config.fish:
+#   dstatus shell-init fish | source
+function __dstatus_report
+    command dstatus shell-report --pid $fish_pid --cwd "$PWD" $argv >/dev/null 2>&1 &
+    disown 2>/dev/null
+end
+function __dstatus_preexec --on-event fish_preexec
+    __dstatus_report --command "$argv[1]"
+end
+function __dstatus_prompt --on-event fish_prompt
+    __dstatus_report
+end
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The hook `shell` runs to report to the daemon at each prompt and
+/// before each command.
+pub fn init_script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+/// What a shell hook tells the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Report {
+    /// The shell's process, so that what it reported ends with it.
+    pub pid: u32,
+    pub cwd: PathBuf,
+    /// The command line running in the foreground; none at the prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// What `{project}`, `{branch}` and `{command}` show.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// The name of the git repository's top directory.
+    pub project: Option<String>,
+    pub branch: Option<String>,
+    /// The program running, without its arguments.
+    pub command: Option<String>,
+}
+
+/// The shell settings file, `shell.toml` unless it was written in another
+/// format.
+pub fn path(config_dir: &Path) -> PathBuf {
+    format::find(config_dir, FILE_STEM)
+}
+
+/// The shell settings in `config_dir`, or `None` when there is no settings
+/// file.
+pub fn load(config_dir: &Path) -> Result<Option<Privacy>> {
+    let path = path(config_dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => Privacy::parse(&content, &path).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(format!("Failed to read {:?}", path), e)),
+    }
+}
+
+/// The settings file as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    throttle: Option<String>,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// Which directories the shells may report from, and how often.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Privacy {
+    /// How long reports are held back after one was applied; the latest
+    /// is applied then.
+    pub throttle: Duration,
+    /// When any are listed, only these directories are reported.
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+/// A path pattern matching a directory and everything under it. `*` and
+/// `?` stand for any characters but `/`, and `**` for any path.
+#[derive(Debug, Clone)]
+struct Pattern {
+    text: String,
+    regex: Regex,
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Default for Privacy {
+    fn default() -> Self {
+        Self {
+            throttle: DEFAULT_THROTTLE,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl Privacy {
+    /// Parses a settings file; `origin` picks the format and labels errors.
+    pub fn parse(content: &str, origin: &Path) -> Result<Self> {
+        let table = format::parse(
+            content,
+            format::Format::detect(&origin.to_string_lossy(), content),
+            origin,
+        )?;
+        let label = origin.display();
+        let file: File = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| Error::Config(format!("{}: {}", label, e.message())))?;
+        let throttle = file
+            .throttle
+            .as_deref()
+            .map_or(Ok(DEFAULT_THROTTLE), source::parse_duration)
+            .map_err(|e| Error::Config(format!("{}: throttle: {}", label, e)))?;
+        let patterns = |texts: Vec<String>, key: &str| {
+            texts
+                .into_iter()
+                .map(|text| {
+                    Pattern::compile(text).map_err(|message| {
+                        Error::Config(format!("{}: {}: {}", label, key, message))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            throttle,
+            allow: patterns(file.allow, "allow")?,
+            deny: patterns(file.deny, "deny")?,
+        })
+    }
+
+    /// Whether reports from `dir` may be shown: it is under an allowed
+    /// path, when any are listed, and under no denied one. Symlinks and
+    /// `..` are resolved first, so they can't lead around a denied path.
+    pub fn allows(&self, dir: &Path) -> bool {
+        let dir = real_path(dir);
+        let dir = dir.to_string_lossy();
+        let matches = |patterns: &[Pattern]| patterns.iter().any(|p| p.regex.is_match(&dir));
+        (self.allow.is_empty() || matches(&self.allow)) && !matches(&self.deny)
+    }
+
+    /// What `report` shows: nothing at all from a directory that is not
+    /// allowed.
+    pub fn status(&self, report: &Report) -> Status {
+        if !self.allows(&report.cwd) {
+            return Status::default();
+        }
+        let repo = find_repo(&report.cwd);
+        Status {
+            project: repo
+                .as_ref()
+                .and_then(|(top, _)| Some(top.file_name()?.to_string_lossy().into_owned())),
+            branch: repo.as_ref().and_then(|(_, git_dir)| branch(git_dir)),
+            command: report.command.as_deref().and_then(program),
+        }
+    }
+}
+
+impl Pattern {
+    fn compile(text: String) -> std::result::Result<Self, String> {
+        let path = source::expand_home(&text);
+        let path = path.to_string_lossy();
+        if !path.starts_with('/') {
+            return Err(format!(
+                "'{}' must be an absolute path or start with ~/",
+                text
+            ));
+        }
+        // Resolve the directories before the first wildcard the same way
+        // `allows` resolves the directory it checks.
+        let literal = path
+            .find(['*', '?'])
+            .map_or(path.len(), |i| path[..i].rfind('/').unwrap_or(0));
+        let (literal, glob) = path.split_at(literal);
+        let path = match literal {
+            "" => glob.to_string(),
+            literal => format!("{}{}", real_path(Path::new(literal)).display(), glob),
+        };
+        let path = path.trim_end_matches('/');
+        let mut regex = String::from("^");
+        let mut rest = path;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("**") {
+                regex.push_str(".*");
+                rest = after;
+                continue;
+            }
+            match c {
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+        regex.push_str("(/.*)?$");
+        let regex = Regex::new(&regex).map_err(|e| e.to_string())?;
+        Ok(Self { text, regex })
+    }
+}
+
+/// `path` with symlinks and `..` resolved as far as it exists; the rest,
+/// which may not exist (yet), is cleaned up without looking at the disk.
+fn real_path(path: &Path) -> PathBuf {
+    for existing in path.ancestors() {
+        let Ok(mut real) = fs::canonicalize(existing) else {
+            continue;
+        };
+        for component in path.strip_prefix(existing).unwrap_or(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    real.pop();
+                }
+                component => real.push(component),
+            }
+        }
+        return real;
+    }
+    path.to_path_buf()
+}
+
+/// The repository `dir` is in: its top directory and its git directory.
+fn find_repo(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for top in dir.ancestors() {
+        let dot_git = top.join(".git");
+        if dot_git.is_dir() {
+            return Some((top.to_path_buf(), dot_git));
+        }
+        // Worktrees and submodules point to their git directory.
+        if dot_git.is_file() {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            let git_dir = content.trim().strip_prefix("gitdir:")?.trim();
+            return Some((top.to_path_buf(), top.join(git_dir)));
+        }
+    }
+    None
+}
+
+/// The branch checked out, or the abbreviated commit when none is.
+fn branch(git_dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(match head.strip_prefix("ref: ") {
+        Some(name) => name.strip_prefix("refs/heads/").unwrap_or(name).to_string(),
+        None => head.chars().take(7).collect(),
+    })
+}
+
+/// The program a command line runs, past variable assignments and
+/// wrappers such as `sudo`.
+fn program(command: &str) -> Option<String> {
+    let word = command
+        .split_whitespace()
+        .find(|word| !word.contains('=') && !word.starts_with('-') && !WRAPPERS.contains(word))?;
+    Some(word.rsplit('/').next().unwrap_or(word).to_string())
+}
+
+/// Whether process `pid` is still running.
+fn alive(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    matches!(
+        signal::kill(Pid::from_raw(pid), None),
+        Ok(()) | Err(Errno::EPERM)
+    )
+}
+
+enum Message {
+    Report(Report),
+    Privacy(Privacy),
+}
+
+/// Keeps what the shell that reported last shows, applying reports at
+/// most once per `throttle`.
+pub struct Tracker {
+    current: Arc<Mutex<Status>>,
+    tx: mpsc::Sender<Message>,
+}
+
+impl Tracker {
+    /// `on_change` is called from the tracker's thread when the status
+    /// changes.
+    pub fn start(privacy: Privacy, on_change: impl Fn() + Send + 'static) -> Self {
+        let current = Arc::new(Mutex::new(Status::default()));
+        let (tx, rx) = mpsc::channel();
+        let shared = Arc::clone(&current);
+        thread::spawn(move || {
+            let mut privacy = privacy;
+            let mut last: Option<Report> = None;
+            let mut pending = false;
+            let mut applied_at: Option<Instant> = None;
+            loop {
+                let timeout = match (pending, applied_at) {
+                    (true, Some(at)) => privacy.throttle.saturating_sub(at.elapsed()),
+                    _ => LIVENESS,
+                };
+                match rx.recv_timeout(timeout) {
+                    Ok(Message::Report(report)) => {
+                        last = Some(report);
+                        pending = true;
+                    }
+                    Ok(Message::Privacy(changed)) => {
+                        privacy = changed;
+                        pending = true;
+                        applied_at = None;
+                    }
+                    Err(RecvTimeoutError::Timeout) if !pending => {
+                        // A shell that closed stops showing.
+                        if last.as_ref().is_some_and(|report| !alive(report.pid)) {
+                            last = None;
+                            pending = true;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if !pending || applied_at.is_some_and(|at| at.elapsed() < privacy.throttle) {
+                    continue;
+                }
+                pending = false;
+                applied_at = Some(Instant::now());
+                let status = last
+                    .as_ref()
+                    .map(|report| privacy.status(report))
+                    .unwrap_or_default();
+                let mut current = shared.lock().unwrap();
+                if *current != status {
+                    debug!("Shell: {:?}", status);
+                    *current = status;
+                    drop(current);
+                    on_change();
+                }
+            }
+        });
+        Self { current, tx }
+    }
+
+    pub fn report(&self, report: Report) {
+        let _ = self.tx.send(Message::Report(report));
+    }
+
+    /// Replaces the settings, applying them to the last report at once.
+    pub fn set_privacy(&self, privacy: Privacy) {
+        let _ = self.tx.send(Message::Privacy(privacy));
+    }
+
+    pub fn current(&self) -> Status {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_status_from_a_repository() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path().join("dstatus-rs");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("src/bin")).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/shell-hooks\n").unwrap();
+        let report = Report {
+            pid: std::process::id(),
+            cwd: repo.join("src/bin"),
+            command: Some("RUST_LOG=debug sudo -E /usr/bin/cargo test --all".to_string()),
+        };
+        let status = Privacy::default().status(&report);
+        assert_eq!(status.project.as_deref(), Some("dstatus-rs"));
+        assert_eq!(status.branch.as_deref(), Some("shell-hooks"));
+        assert_eq!(status.command.as_deref(), Some("cargo"));
+
+        fs::write(repo.join(".git/HEAD"), "8839839c2a6e0f1d\n").unwrap();
+        let status = Privacy::default().status(&report);
+        assert_eq!(status.branch.as_deref(), Some("8839839"));
+
+        let outside = Report {
+            cwd: dir.path().to_path_buf(),
+            command: None,
+            ..report
+        };
+        assert_eq!(Privacy::default().status(&outside), Status::default());
+    }
+
+    #[test]
+    fn test_privacy_lists() {
+        let privacy = Privacy::parse(
+            "throttle = \"2s\"\n\
+             allow = [\"/home/sam/src\", \"/srv/**/public\"]\n\
+             deny = [\"/home/sam/src/client-*\"]\n",
+            Path::new("shell.toml"),
+        )
+        .unwrap();
+        assert_eq!(privacy.throttle, Duration::from_secs(2));
+        assert!(privacy.allows(Path::new("/home/sam/src")));
+        assert!(privacy.allows(Path::new("/home/sam/src/dstatus-rs/src")));
+        assert!(!privacy.allows(Path::new("/home/sam/srcs")));
+        assert!(!privacy.allows(Path::new("/home/sam/src/client-acme/api")));
+        assert!(privacy.allows(Path::new("/srv/a/b/public/site")));
+        assert!(!privacy.allows(Path::new("/tmp")));
+
+        let denied = Report {
+            pid: 1,
+            cwd: PathBuf::from("/home/sam/src/client-acme"),
+            command: Some("vim".to_string()),
+        };
+        assert_eq!(privacy.status(&denied), Status::default());
+
+        let error = Privacy::parse("deny = [\"work\"]\n", Path::new("shell.toml")).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("deny: 'work' must be an absolute path"));
+    }
+
+    #[test]
+    fn test_privacy_resolves_symlinks_and_dot_dot() {
+        let dir = tempdir().unwrap();
+        let secret = dir.path().join("client-acme");
+        fs::create_dir_all(secret.join("api")).unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        std::os::unix::fs::symlink(&secret, dir.path().join("src/acme")).unwrap();
+
+        let privacy = Privacy {
+            deny: vec![Pattern::compile(secret.to_string_lossy().into_owned()).unwrap()],
+            ..Privacy::default()
+        };
+        assert!(!privacy.allows(&dir.path().join("src/acme/api")));
+        assert!(!privacy.allows(&dir.path().join("src/../client-acme/new")));
+        assert!(privacy.allows(&dir.path().join("src")));
+
+        // A pattern naming the link denies what it points to.
+        let privacy = Privacy {
+            deny: vec![Pattern::compile(format!("{}/src/acme", dir.path().display())).unwrap()],
+            ..Privacy::default()
+        };
+        assert!(!privacy.allows(&secret.join("api")));
+    }
+
+    #[test]
+    fn test_reports_are_throttled() {
+        let (tx, rx) = mpsc::channel();
+        let tracker = Tracker::start(
+            Privacy {
+                throttle: Duration::from_millis(300),
+                ..Privacy::default()
+            },
+            move || tx.send(()).unwrap(),
+        );
+        let report = |command: &str| Report {
+            pid: std::process::id(),
+            cwd: PathBuf::from("/"),
+            command: Some(command.to_string()),
+        };
+        tracker.report(report("make"));
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(tracker.current().command.as_deref(), Some("make"));
+
+        // Within the throttle, only the latest report is applied.
+        tracker.report(report("cargo"));
+        tracker.report(report("nvim"));
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(tracker.current().command.as_deref(), Some("nvim"));
+        assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn test_bash_hook_parses() {
+        let checked = Command::new("bash")
+            .args(["-n", "-c", init_script(Shell::Bash)])
+            .status()
+            .unwrap();
+        assert!(checked.success());
+    }
+}
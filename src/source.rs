@@ -0,0 +1,596 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::template;
+use crate::watch::{self, FileWatcher};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use toml::{Table, Value};
+use tracing::{debug, warn};
+
+/// Fields whose text may come from a command instead of the file.
+pub const TEXT_FIELDS: [&str; 4] = ["details", "state", "large_text", "small_text"];
+
+/// The key sourced fields are kept under once read, so that the rest of
+/// dstatus keeps seeing plain strings.
+pub const SOURCES_KEY: &str = "sources";
+
+const DEFAULT_EVERY: Duration = Duration::from_secs(60);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_EVERY: Duration = Duration::from_secs(1);
+
+/// How much of a command's output is read; the rest is discarded.
+const MAX_OUTPUT: u64 = 4096;
+
+/// A field filled from the output of a shell command or from a file,
+/// written in place of the text:
+/// `state = { exec = "git branch --show-current", every = "30s" }` or
+/// `state = { file = "~/build/status.json", pointer = "/stage" }`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSource {
+    /// Shell command whose first line of output becomes the text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+    /// File whose first line becomes the text. Re-read whenever it changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// JSON pointer such as `/build/status` selecting the text in a JSON
+    /// `file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+    /// How often to run the command, such as `30s` or `5m`. Defaults to `60s`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<String>,
+    /// How long the command may run, such as `500ms`. Defaults to `5s`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Text shown until the command first succeeds, or while the file
+    /// cannot be read.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub fallback: String,
+}
+
+impl FieldSource {
+    pub fn every(&self) -> Result<Duration> {
+        self.every
+            .as_deref()
+            .map_or(Ok(DEFAULT_EVERY), parse_duration)
+    }
+
+    pub fn timeout(&self) -> Result<Duration> {
+        self.timeout
+            .as_deref()
+            .map_or(Ok(DEFAULT_TIMEOUT), parse_duration)
+    }
+
+    /// Problems with the source, for validation.
+    pub fn check(&self) -> std::result::Result<(), String> {
+        match (&self.exec, &self.file) {
+            (Some(_), Some(_)) => return Err("use either exec or file, not both".to_string()),
+            (None, None) => return Err("needs exec or file".to_string()),
+            (None, Some(file)) => {
+                if file.trim().is_empty() {
+                    return Err("file must not be empty".to_string());
+                }
+                if self.every.is_some() || self.timeout.is_some() {
+                    return Err("every and timeout only apply to exec".to_string());
+                }
+                if let Some(pointer) = &self.pointer {
+                    if !pointer.is_empty() && !pointer.starts_with('/') {
+                        return Err("pointer must start with '/'".to_string());
+                    }
+                }
+                return Ok(());
+            }
+            (Some(exec), None) if exec.trim().is_empty() => {
+                return Err("exec must not be empty".to_string())
+            }
+            (Some(_), None) => {}
+        }
+        if self.pointer.is_some() {
+            return Err("pointer only applies to file".to_string());
+        }
+        let every = self.every().map_err(|e| format!("every: {}", e))?;
+        if every < MIN_EVERY {
+            return Err(format!("every must be at least {:?}", MIN_EVERY));
+        }
+        self.timeout().map_err(|e| format!("timeout: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Parses durations such as `500ms`, `30s`, `5m` or `1h`. A bare number is
+/// in seconds.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let invalid = || {
+        Error::Config(format!(
+            "Invalid duration '{}': use a number with ms, s, m or h",
+            text
+        ))
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let seconds = |factor: u64| {
+        number
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| Error::Config(format!("Duration '{}' is too long", text)))
+    };
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => seconds(1),
+        "m" => seconds(60),
+        "h" => seconds(3600),
+        _ => Err(invalid()),
+    }
+}
+
+/// Moves text fields written as tables into `sources`, leaving their
+/// fallback text behind.
+pub fn lift(table: &mut Table) {
+    for field in TEXT_FIELDS {
+        let Some(Value::Table(source)) = table.get(field) else {
+            continue;
+        };
+        let source = source.clone();
+        let fallback = source
+            .get("fallback")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        table.insert(field.to_string(), Value::String(fallback));
+        let sources = table
+            .entry(SOURCES_KEY)
+            .or_insert_with(|| Value::Table(Table::new()));
+        if let Value::Table(sources) = sources {
+            sources.insert(field.to_string(), Value::Table(source));
+        }
+    }
+}
+
+/// The reverse of `lift`: puts every source back in place of its field.
+pub fn lower(table: &mut Table) {
+    let Some(Value::Table(sources)) = table.remove(SOURCES_KEY) else {
+        return;
+    };
+    for (field, source) in sources {
+        table.insert(field, source);
+    }
+}
+
+/// Replaces the fields of `table`, as written in a file, whose text comes
+/// from a shell command with their fallback text, and returns those
+/// fields. Bases downloaded from URLs go through this, so that a shared
+/// file cannot run commands on every machine that uses it.
+pub fn strip_exec(table: &mut Table) -> Vec<String> {
+    let runs = |source: &Value| source.get("exec").is_some();
+    let mut stripped = Vec::new();
+    for field in TEXT_FIELDS {
+        let Some(source) = table
+            .get(field)
+            .filter(|value| value.is_table() && runs(value))
+        else {
+            continue;
+        };
+        let fallback = source
+            .get("fallback")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        table.insert(field.to_string(), Value::String(fallback));
+        stripped.push(field.to_string());
+    }
+    if let Some(Value::Table(sources)) = table.get_mut(SOURCES_KEY) {
+        sources.retain(|field, source| {
+            if runs(source) {
+                stripped.push(field.to_string());
+            }
+            !runs(source)
+        });
+    }
+    stripped
+}
+
+/// The latest output of every running command, shared with the runner's
+/// threads.
+#[derive(Debug, Clone, Default)]
+pub struct Outputs(Arc<Mutex<HashMap<String, String>>>);
+
+impl Outputs {
+    /// Fills the sourced fields of `config` with the latest output, or the
+    /// fallback text when a command has not succeeded yet.
+    pub fn apply(&self, config: &mut Config) {
+        let outputs = self.0.lock().unwrap();
+        for (field, source) in &config.sources {
+            let text = outputs.get(field).unwrap_or(&source.fallback);
+            let text = template::fit(text);
+            match field.as_str() {
+                "details" => config.details = text,
+                "state" => config.state = text,
+                "large_text" => config.large_text = text,
+                "small_text" => config.small_text = text,
+                _ => {}
+            }
+        }
+    }
+
+    /// Records `text` for `field`, returning whether it changed.
+    fn set(&self, field: &str, text: String) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(field.to_string(), text.clone())
+            != Some(text)
+    }
+
+    /// Goes back to the fallback text for `field`, returning whether that
+    /// changed anything.
+    fn clear(&self, field: &str) -> bool {
+        self.0.lock().unwrap().remove(field).is_some()
+    }
+}
+
+/// Runs the commands of a configuration, each on its own thread and
+/// schedule. The threads stop when the runner is dropped.
+pub struct Runner {
+    outputs: Outputs,
+    _stop: Vec<mpsc::Sender<()>>,
+    _watcher: Option<FileWatcher>,
+}
+
+impl Runner {
+    /// Starts the commands in `sources`. `on_change` is called from the
+    /// runner's threads whenever an output changes.
+    pub fn start(
+        sources: &BTreeMap<String, FieldSource>,
+        on_change: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        let outputs = Outputs::default();
+        let on_change: Arc<dyn Fn() + Send + Sync> = Arc::new(on_change);
+        let mut stop = Vec::new();
+
+        let files: Vec<(String, PathBuf, FieldSource)> = sources
+            .iter()
+            .filter_map(|(field, source)| {
+                let file = expand_home(source.file.as_deref()?);
+                Some((field.clone(), file, source.clone()))
+            })
+            .collect();
+        let watcher = if files.is_empty() {
+            None
+        } else {
+            watch_files(files, outputs.clone(), Arc::clone(&on_change))
+        };
+
+        for (field, source) in sources {
+            let Some(exec) = source.exec.clone() else {
+                continue;
+            };
+            let (stop_tx, stop_rx) = mpsc::channel::<()>();
+            stop.push(stop_tx);
+            let (field, source) = (field.clone(), source.clone());
+            let (outputs, on_change) = (outputs.clone(), Arc::clone(&on_change));
+            let every = source.every().unwrap_or(DEFAULT_EVERY);
+            let timeout = source.timeout().unwrap_or(DEFAULT_TIMEOUT);
+            thread::spawn(move || loop {
+                match run(&exec, timeout) {
+                    Ok(text) => {
+                        debug!("{}: {:?}", field, text);
+                        if outputs.set(&field, text) {
+                            on_change();
+                        }
+                    }
+                    // Keep the last good output rather than blanking the field.
+                    Err(e) => warn!("{}: '{}' failed: {}", field, exec, e),
+                }
+                match stop_rx.recv_timeout(every) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            });
+        }
+        Self {
+            outputs,
+            _stop: stop,
+            _watcher: watcher,
+        }
+    }
+
+    pub fn outputs(&self) -> Outputs {
+        self.outputs.clone()
+    }
+}
+
+/// Reads every file source now and again whenever its file changes. The
+/// watcher is left out, with a warning, if the files cannot be watched.
+fn watch_files(
+    files: Vec<(String, PathBuf, FieldSource)>,
+    outputs: Outputs,
+    on_change: Arc<dyn Fn() + Send + Sync>,
+) -> Option<FileWatcher> {
+    let refresh = move |files: &[(String, PathBuf, FieldSource)], changed: Option<&[PathBuf]>| {
+        let mut any_changed = false;
+        for (field, file, source) in files {
+            if changed.is_some_and(|changed| !changed.contains(&watch::normalize(file))) {
+                continue;
+            }
+            any_changed |= match read(file, source.pointer.as_deref()) {
+                Ok(text) => outputs.set(field, text),
+                Err(e) => {
+                    debug!("{}: {}", field, e);
+                    outputs.clear(field)
+                }
+            };
+        }
+        // The first read happens before anything was sent.
+        if any_changed && changed.is_some() {
+            on_change();
+        }
+    };
+
+    refresh(&files, None);
+    let paths: Vec<PathBuf> = files.iter().map(|(_, file, _)| file.clone()).collect();
+    let watched = files.clone();
+    match FileWatcher::new(move |changed| refresh(&watched, Some(&changed))) {
+        Ok(mut watcher) => {
+            watcher.watch(&paths);
+            Some(watcher)
+        }
+        Err(e) => {
+            warn!("Not watching field files for changes: {}", e);
+            None
+        }
+    }
+}
+
+/// The text a file source shows: the first non-empty line of `file`, or
+/// the value `pointer` selects when the file is JSON.
+pub fn read(file: &Path, pointer: Option<&str>) -> std::result::Result<String, String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("{:?}: {}", file, e))?;
+    let Some(pointer) = pointer else {
+        return Ok(first_line(&content));
+    };
+    let json: serde_json::Value =
+        serde_json::from_str(&content).map_err(|e| format!("{:?}: {}", file, e))?;
+    match json.pointer(pointer) {
+        Some(serde_json::Value::String(text)) => Ok(first_line(text)),
+        Some(serde_json::Value::Null) | None => {
+            Err(format!("{:?}: nothing at '{}'", file, pointer))
+        }
+        Some(value) => Ok(first_line(&value.to_string())),
+    }
+}
+
+/// `path` with a leading `~/` standing for the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn first_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Runs `command` with `sh -c` and returns the first non-empty line of its
+/// output. The command and anything it started are killed at `timeout`.
+pub fn run(command: &str, timeout: Duration) -> std::result::Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let stdout = read_capped(child.stdout.take());
+    let stderr = read_capped(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let group = nix::unistd::Pid::from_raw(child.id() as i32);
+    let kill_group = || nix::sys::signal::killpg(group, nix::sys::signal::Signal::SIGKILL);
+    let timed_out = || format!("timed out after {:?}", timeout);
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = kill_group();
+                let _ = child.wait();
+                return Err(timed_out());
+            }
+            None => thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    // Background processes the command started would keep the pipes open;
+    // they go with the shell. One that left the group is waited for only
+    // until the deadline.
+    let _ = kill_group();
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let read = |output: mpsc::Receiver<String>| match output.recv_timeout(remaining()) {
+        Ok(text) => Ok(text),
+        Err(RecvTimeoutError::Timeout) => Err(timed_out()),
+        Err(RecvTimeoutError::Disconnected) => Ok(String::new()),
+    };
+
+    if !status.success() {
+        let stderr = read(stderr).unwrap_or_default();
+        return Err(format!("{}: {}", status, first_line(&stderr)));
+    }
+    Ok(first_line(&read(stdout)?))
+}
+
+/// Reads up to `MAX_OUTPUT` bytes on a separate thread and drains the
+/// rest, so a chatty command cannot block on a full pipe.
+fn read_capped(pipe: Option<impl Read + Send + 'static>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    if let Some(mut pipe) = pipe {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = pipe.by_ref().take(MAX_OUTPUT).read_to_end(&mut buffer);
+            let _ = io::copy(&mut pipe, &mut io::sink());
+            let _ = tx.send(String::from_utf8_lossy(&buffer).into_owned());
+        });
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lift_and_lower() {
+        let original: Table =
+            "details = \"Coding\"\nstate = { exec = \"git branch --show-current\", fallback = \"main\" }\n"
+                .parse()
+                .unwrap();
+        let mut table = original.clone();
+        lift(&mut table);
+        assert_eq!(table["state"].as_str(), Some("main"));
+        assert_eq!(
+            table["sources"]["state"]["exec"].as_str(),
+            Some("git branch --show-current")
+        );
+        lower(&mut table);
+        assert_eq!(table, original);
+    }
+
+    #[test]
+    fn test_strip_exec_keeps_fallbacks_and_files() {
+        let mut table: Table = "details = { exec = \"whoami\", fallback = \"Coding\" }\n\
+             state = { file = \"~/status\" }\n\
+             [sources.small_text]\nexec = \"hostname\"\n"
+            .parse()
+            .unwrap();
+        assert_eq!(strip_exec(&mut table), vec!["details", "small_text"]);
+        assert_eq!(table["details"].as_str(), Some("Coding"));
+        assert!(table["state"].get("file").is_some());
+        assert!(table["sources"].as_table().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("2").unwrap(), Duration::from_secs(2));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5 days").is_err());
+        let err = parse_duration("9999999999999999h").unwrap_err();
+        assert!(err.to_string().contains("too long"), "{}", err);
+    }
+
+    #[test]
+    fn test_run() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(run("printf '\\n  main \\nmore'", timeout).unwrap(), "main");
+        let err = run("echo oops >&2; exit 3", timeout).unwrap_err();
+        assert!(err.contains("oops"), "{}", err);
+
+        let started = Instant::now();
+        let err = run("sleep 5; echo late", Duration::from_millis(200)).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(4));
+
+        // Background processes holding the output open end with the shell,
+        // or are given up on at the deadline if they left its group.
+        let started = Instant::now();
+        assert_eq!(run("echo ready; sleep 5 &", timeout).unwrap(), "ready");
+        let err = run("setsid sleep 5 &", Duration::from_millis(300)).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_failures_keep_the_last_output() {
+        let outputs = Outputs::default();
+        let mut config = Config::default();
+        config.sources.insert(
+            "state".to_string(),
+            FieldSource {
+                exec: Some("false".to_string()),
+                fallback: "starting".to_string(),
+                ..FieldSource::default()
+            },
+        );
+        outputs.apply(&mut config);
+        assert_eq!(config.state, "starting");
+
+        assert!(outputs.set("state", "deployed".to_string()));
+        assert!(!outputs.set("state", "deployed".to_string()));
+        outputs.apply(&mut config);
+        assert_eq!(config.state, "deployed");
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = dir.path().join("status");
+        fs::write(&status, "\n  passing  \nmore\n").unwrap();
+        assert_eq!(read(&status, None).unwrap(), "passing");
+
+        let json = dir.path().join("status.json");
+        fs::write(
+            &json,
+            r#"{"build": {"stage": "deploy", "step": 3, "eta": null}}"#,
+        )
+        .unwrap();
+        assert_eq!(read(&json, Some("/build/stage")).unwrap(), "deploy");
+        assert_eq!(read(&json, Some("/build/step")).unwrap(), "3");
+        assert!(read(&json, Some("/build/eta")).is_err());
+        assert!(read(&dir.path().join("missing"), None).is_err());
+    }
+
+    #[test]
+    fn test_file_changes_update_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = dir.path().join("status");
+        fs::write(&status, "building\n").unwrap();
+        let mut config = Config::default();
+        config.sources.insert(
+            "state".to_string(),
+            FieldSource {
+                file: Some(status.to_string_lossy().into_owned()),
+                fallback: "idle".to_string(),
+                ..FieldSource::default()
+            },
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let runner = Runner::start(&config.sources, move || {
+            let _ = tx.send(());
+        });
+        runner.outputs().apply(&mut config);
+        assert_eq!(config.state, "building");
+
+        crate::history::atomic_write(&status, "deployed\n").unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        runner.outputs().apply(&mut config);
+        assert_eq!(config.state, "deployed");
+
+        fs::remove_file(&status).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        runner.outputs().apply(&mut config);
+        assert_eq!(config.state, "idle");
+    }
+}
@@ -6,7 +6,8 @@ use std::fs;
 use std::io::{Read, Write};
 use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use tracing::{debug, error, info, warn};
 use crate::config::Button;
@@ -78,8 +79,11 @@ pub enum ActivityEvent {
     ActivityInvite,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct Activity {
+    /// What the user is doing, such as listening; playing when left out.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub state: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -131,28 +135,26 @@ impl StreamManager {
     }
 
     pub fn connect(&mut self) -> Result<()> {
-        self.connect_with_tmp_dir(std::env::temp_dir())
+        self.state = ConnectionState::Pending;
+
+        let socket_paths: Vec<PathBuf> = candidate_dirs()
+            .iter()
+            .flat_map(|dir| find_sockets(dir))
+            .collect();
+
+        self.connect_any(socket_paths)
     }
 
+    #[cfg(test)]
     fn connect_with_tmp_dir(&mut self, tmp_dir: PathBuf) -> Result<()> {
-        let re = Regex::new(r"discord-ipc-\d+").unwrap();
-
         self.state = ConnectionState::Pending;
-        info!("Looking for Discord IPC socket in {:?}", tmp_dir);
-
-        let socket_paths: Vec<PathBuf> = fs::read_dir(tmp_dir)?
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| {
-                entry
-                    .file_name()
-                    .to_str()
-                    .map(|s| re.is_match(s))
-                    .unwrap_or(false)
-            })
-            .map(|entry| entry.path())
-            .collect();
+        let socket_paths = find_sockets(&tmp_dir);
+        self.connect_any(socket_paths)
+    }
 
+    fn connect_any(&mut self, socket_paths: Vec<PathBuf>) -> Result<()> {
         if socket_paths.is_empty() {
+            self.state = ConnectionState::Disconnected;
             error!("No Discord IPC sockets found. Is Discord running?");
             return Err(anyhow!(
                 "No Discord IPC socket found. Make sure Discord is running."
@@ -162,25 +164,41 @@ impl StreamManager {
         info!("Found {} potential Discord IPC sockets", socket_paths.len());
 
         for socket_path in socket_paths {
-            info!("Attempting to connect to socket: {:?}", socket_path);
-            match UnixStream::connect(&socket_path) {
-                Ok(stream) => {
-                    self.socket = Some(stream);
-                    self.state = ConnectionState::Connected;
-                    info!("Successfully connected to Discord IPC");
-                    return Ok(());
-                }
-                Err(e) => {
-                    warn!("Failed to connect to socket {:?}: {}", socket_path, e);
-                    continue;
-                }
+            if self.connect_path(&socket_path).is_ok() {
+                return Ok(());
             }
         }
 
+        self.state = ConnectionState::Disconnected;
         error!("Failed to connect to any Discord IPC sockets");
         Err(anyhow!("Could not connect to any Discord IPC sockets. Make sure Discord is running and try again."))
     }
 
+    pub fn connect_path(&mut self, socket_path: &Path) -> Result<()> {
+        info!("Attempting to connect to socket: {:?}", socket_path);
+        match UnixStream::connect(socket_path) {
+            Ok(stream) => {
+                self.socket = Some(stream);
+                self.state = ConnectionState::Connected;
+                info!("Successfully connected to Discord IPC");
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to connect to socket {:?}: {}", socket_path, e);
+                Err(e.into())
+            }
+        }
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let socket = self
+            .socket
+            .as_ref()
+            .ok_or_else(|| anyhow!("Not connected"))?;
+        socket.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn disconnect(&mut self) -> Result<()> {
         if self.socket.is_none() {
@@ -235,6 +253,62 @@ impl StreamManager {
     }
 }
 
+/// Directories Discord may place its IPC sockets in, in lookup order.
+///
+/// Besides the usual runtime and temp directories this covers the
+/// sandboxed Flatpak and Snap builds, which nest their sockets one level
+/// deeper.
+pub fn candidate_dirs() -> Vec<PathBuf> {
+    let mut bases: Vec<PathBuf> = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    bases.push(std::env::temp_dir());
+    bases.push(PathBuf::from("/tmp"));
+
+    let mut dirs = Vec::new();
+    for base in bases {
+        for sub in [
+            "",
+            "app/com.discordapp.Discord",
+            "snap.discord",
+            ".flatpak/dev.vencord.Vesktop/xdg-run",
+        ] {
+            let dir = if sub.is_empty() { base.clone() } else { base.join(sub) };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// Lists the `discord-ipc-N` sockets in `dir`, sorted by name. A missing or
+/// unreadable directory yields no sockets.
+pub fn find_sockets(dir: &Path) -> Vec<PathBuf> {
+    let re = Regex::new(r"^discord-ipc-\d+$").unwrap();
+
+    debug!("Looking for Discord IPC socket in {:?}", dir);
+    let mut socket_paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .map(|s| re.is_match(s))
+                    .unwrap_or(false)
+            })
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    socket_paths.sort();
+    socket_paths
+}
+
 fn unpack(bytes: Vec<u8>) -> Result<(u32, u32)> {
     if bytes.len() < 8 {
         return Err(anyhow!("Invalid header length"));
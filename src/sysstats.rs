@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where `/proc` and `/sys` are found.
+pub const ROOT: &str = "/";
+
+/// Samples taken closer together than this are served from the last one,
+/// however many fields or renders ask.
+const MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+static SHARED: Mutex<Option<Sampler>> = Mutex::new(None);
+
+/// What the system statistics placeholders show. Anything that could not
+/// be read is `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Load averages over 1, 5 and 15 minutes.
+    pub load: Option<[f64; 3]>,
+    /// How busy the CPUs were since the previous sample, in percent.
+    pub cpu: Option<f64>,
+    pub memory: Option<Memory>,
+    pub battery: Option<Battery>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Memory {
+    pub used: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Battery {
+    /// Charge in percent, averaged over all batteries.
+    pub percent: u8,
+    /// Such as `Charging` or `Discharging`.
+    pub status: String,
+}
+
+/// CPU time counters from `/proc/stat`, in clock ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// Reads the statistics under a root directory, no more often than
+/// `MIN_INTERVAL`.
+#[derive(Debug)]
+pub struct Sampler {
+    root: PathBuf,
+    last: Option<(Instant, Stats)>,
+    /// The counters of the last sample, which CPU usage is measured from.
+    cpu_times: Option<CpuTimes>,
+}
+
+impl Sampler {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            last: None,
+            cpu_times: None,
+        }
+    }
+
+    /// The statistics as of `now`, or of the last sample if it was taken
+    /// less than `MIN_INTERVAL` before.
+    pub fn sample(&mut self, now: Instant) -> Stats {
+        if let Some((taken, stats)) = &self.last {
+            if now.duration_since(*taken) < MIN_INTERVAL {
+                return stats.clone();
+            }
+        }
+        let cpu_times = read_cpu_times(&self.root);
+        let cpu = cpu_times.map(|times| cpu_usage(self.cpu_times, times));
+        self.cpu_times = cpu_times;
+        let stats = Stats {
+            load: read_load(&self.root),
+            cpu,
+            memory: read_memory(&self.root),
+            battery: read_battery(&self.root),
+        };
+        self.last = Some((now, stats.clone()));
+        stats
+    }
+}
+
+/// The statistics of this system, sampled once for the whole process.
+pub fn shared() -> Stats {
+    SHARED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| Sampler::new(ROOT))
+        .sample(Instant::now())
+}
+
+impl Stats {
+    /// The value of a placeholder such as `{cpu}` or `{mem:used}`, or
+    /// nothing when it could not be read.
+    pub fn format(&self, name: &str, arg: Option<&str>) -> String {
+        let text = match (name, arg) {
+            ("load", _) => self.load.map(|load| {
+                let i = match arg {
+                    Some("5") => 1,
+                    Some("15") => 2,
+                    _ => 0,
+                };
+                format!("{:.2}", load[i])
+            }),
+            ("cpu", _) => self.cpu.map(|cpu| format!("{:.0}%", cpu)),
+            ("mem", Some("used")) => self
+                .memory
+                .map(|memory| format!("{} GB", gigabytes(memory.used))),
+            ("mem", Some("total")) => self
+                .memory
+                .map(|memory| format!("{} GB", gigabytes(memory.total))),
+            ("mem", Some("percent")) => self
+                .memory
+                .filter(|memory| memory.total > 0)
+                .map(|memory| memory.used as f64 * 100.0 / memory.total as f64)
+                .map(|percent| format!("{:.0}%", percent)),
+            ("mem", _) => self.memory.map(|memory| {
+                let (used, total) = (gigabytes(memory.used), gigabytes(memory.total));
+                format!("{}/{} GB", used, total)
+            }),
+            ("battery", Some("status")) => {
+                self.battery.as_ref().map(|battery| battery.status.clone())
+            }
+            ("battery", _) => self
+                .battery
+                .as_ref()
+                .map(|battery| format!("{}%", battery.percent)),
+            _ => None,
+        };
+        text.unwrap_or_default()
+    }
+}
+
+/// Whether `{name:arg}` is a statistics placeholder.
+pub fn is_placeholder(name: &str, arg: Option<&str>) -> bool {
+    matches!(
+        (name, arg),
+        ("load", None | Some("1" | "5" | "15"))
+            | ("cpu", None)
+            | ("mem", None | Some("used" | "total" | "percent"))
+            | ("battery", None | Some("status"))
+    )
+}
+
+/// Bytes as gigabytes with at most one decimal, such as `12.1` or `32`.
+fn gigabytes(bytes: u64) -> String {
+    let text = format!("{:.1}", bytes as f64 / GIB);
+    text.strip_suffix(".0").unwrap_or(&text).to_string()
+}
+
+fn read_load(root: &Path) -> Option<[f64; 3]> {
+    let content = fs::read_to_string(root.join("proc/loadavg")).ok()?;
+    let mut fields = content.split_whitespace().map(|field| field.parse().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+fn read_cpu_times(root: &Path) -> Option<CpuTimes> {
+    let content = fs::read_to_string(root.join("proc/stat")).ok()?;
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    // user nice system idle iowait irq softirq steal; guest time is
+    // already counted in user.
+    let ticks: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    if ticks.len() < 4 {
+        return None;
+    }
+    let total: u64 = ticks.iter().sum();
+    let idle = ticks[3] + ticks.get(4).copied().unwrap_or(0);
+    Some(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+/// The share of busy time between two readings of the counters, or since
+/// boot when there is no earlier one.
+fn cpu_usage(previous: Option<CpuTimes>, current: CpuTimes) -> f64 {
+    let (busy, total) = match previous {
+        Some(previous) if current.total > previous.total => (
+            current.busy.saturating_sub(previous.busy),
+            current.total - previous.total,
+        ),
+        _ => (current.busy, current.total),
+    };
+    if total == 0 {
+        return 0.0;
+    }
+    busy as f64 * 100.0 / total as f64
+}
+
+fn read_memory(root: &Path) -> Option<Memory> {
+    let content = fs::read_to_string(root.join("proc/meminfo")).ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = content.lines().find(|line| line.starts_with(name))?;
+        let kilobytes: u64 = line[name.len()..]
+            .trim_start_matches(':')
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()?;
+        Some(kilobytes * 1024)
+    };
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    Some(Memory {
+        used: total.saturating_sub(available),
+        total,
+    })
+}
+
+/// How long the system has been up, from `/proc/uptime`.
+pub fn read_uptime(root: &Path) -> Option<Duration> {
+    let content = fs::read_to_string(root.join("proc/uptime")).ok()?;
+    let seconds: f64 = content.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// The batteries under `/sys/class/power_supply`. Mains adapters and the
+/// batteries of peripherals are left out.
+fn read_battery(root: &Path) -> Option<Battery> {
+    let entries = fs::read_dir(root.join("sys/class/power_supply")).ok()?;
+    let mut supplies: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    supplies.sort();
+    let read = |dir: &Path, name: &str| {
+        fs::read_to_string(dir.join(name))
+            .ok()
+            .map(|text| text.trim().to_string())
+    };
+    let batteries: Vec<(u32, String)> = supplies
+        .iter()
+        .filter(|dir| read(dir, "type").as_deref() == Some("Battery"))
+        .filter(|dir| read(dir, "scope").as_deref() != Some("Device"))
+        .filter_map(|dir| {
+            let percent = read(dir, "capacity")?.parse().ok()?;
+            Some((percent, read(dir, "status").unwrap_or_default()))
+        })
+        .collect();
+    let (_, status) = batteries.first()?;
+    let percent =
+        batteries.iter().map(|(percent, _)| percent).sum::<u32>() / batteries.len() as u32;
+    Some(Battery {
+        percent: percent.min(100) as u8,
+        status: status.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn stat(busy: u64, idle: u64) -> String {
+        format!(
+            "cpu  {} 0 0 {} 0 0 0 0 0 0\ncpu0 {} 0 0 {} 0 0 0 0 0 0\n",
+            busy, idle, busy, idle
+        )
+    }
+
+    #[test]
+    fn test_read_fixture_root() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        write(root, "proc/loadavg", "0.52 0.48 0.40 2/512 12345\n");
+        write(root, "proc/stat", &stat(300, 700));
+        write(
+            root,
+            "proc/meminfo",
+            "MemTotal:       33554432 kB\nMemFree:         1048576 kB\nMemAvailable:   20866662 kB\n",
+        );
+        write(root, "proc/uptime", "11550.5 40000.0\n");
+        write(root, "sys/class/power_supply/AC/type", "Mains\n");
+        write(root, "sys/class/power_supply/BAT0/type", "Battery\n");
+        write(root, "sys/class/power_supply/BAT0/capacity", "80\n");
+        write(root, "sys/class/power_supply/BAT0/status", "Discharging\n");
+        write(root, "sys/class/power_supply/BAT1/type", "Battery\n");
+        write(root, "sys/class/power_supply/BAT1/capacity", "90\n");
+        write(root, "sys/class/power_supply/hid-mouse/type", "Battery\n");
+        write(root, "sys/class/power_supply/hid-mouse/scope", "Device\n");
+        write(root, "sys/class/power_supply/hid-mouse/capacity", "5\n");
+
+        let mut sampler = Sampler::new(root);
+        let start = Instant::now();
+        let stats = sampler.sample(start);
+        assert_eq!(stats.format("load", None), "0.52");
+        assert_eq!(stats.format("load", Some("15")), "0.40");
+        // Since boot on the first sample.
+        assert_eq!(stats.format("cpu", None), "30%");
+        assert_eq!(stats.format("mem", None), "12.1/32 GB");
+        assert_eq!(stats.format("mem", Some("used")), "12.1 GB");
+        assert_eq!(stats.format("mem", Some("percent")), "38%");
+        assert_eq!(read_uptime(root), Some(Duration::from_secs_f64(11550.5)));
+        assert_eq!(stats.format("battery", None), "85%");
+        assert_eq!(stats.format("battery", Some("status")), "Discharging");
+
+        // Within the interval, the sample is reused.
+        write(root, "proc/stat", &stat(400, 800));
+        assert_eq!(sampler.sample(start + Duration::from_secs(1)), stats);
+        // Then usage is measured since the previous sample.
+        let later = sampler.sample(start + MIN_INTERVAL);
+        assert_eq!(later.format("cpu", None), "50%");
+    }
+
+    #[test]
+    fn test_missing_files_read_as_nothing() {
+        let root = tempdir().unwrap();
+        let stats = Sampler::new(root.path()).sample(Instant::now());
+        assert_eq!(stats, Stats::default());
+        assert_eq!(stats.format("battery", None), "");
+        assert_eq!(stats.format("mem", None), "");
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert!(is_placeholder("cpu", None));
+        assert!(is_placeholder("load", Some("5")));
+        assert!(is_placeholder("mem", Some("total")));
+        assert!(!is_placeholder("load", Some("2")));
+        assert!(!is_placeholder("cpu", Some("0")));
+        assert_eq!(gigabytes(32 * 1024 * 1024 * 1024), "32");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::config::Config;
+use crate::media::Track;
+use crate::shell;
+use crate::sysstats::{self, Stats};
+use crate::validation;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Values placeholders are filled from, captured once per send so that
+/// every field sees the same time.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub profile: String,
+    pub hostname: String,
+    pub user: String,
+    pub now: DateTime<Local>,
+    pub uptime: Duration,
+    /// Sampled when a statistics placeholder first needs it.
+    pub stats: OnceCell<Stats>,
+    /// The track `{title}`, `{artist}` and `{album}` come from.
+    pub track: Option<Track>,
+    /// What `{project}`, `{branch}` and `{command}` show.
+    pub shell: shell::Status,
+    /// The variables `{env:NAME}` reads.
+    pub env: HashMap<String, String>,
+}
+
+impl Context {
+    /// The current values. `started` stands in for the system boot time
+    /// where the system uptime cannot be read.
+    pub fn current(profile: &str, started: Instant) -> Self {
+        Self {
+            profile: profile.to_string(),
+            hostname: nix::unistd::gethostname()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+                .unwrap_or_default(),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("LOGNAME"))
+                .unwrap_or_default(),
+            now: Local::now(),
+            uptime: sysstats::read_uptime(Path::new(sysstats::ROOT))
+                .unwrap_or_else(|| started.elapsed()),
+            stats: OnceCell::new(),
+            track: None,
+            shell: shell::Status::default(),
+            env: std::env::vars().collect(),
+        }
+    }
+}
+
+/// Whether `text` uses any placeholder, in which case its length is only
+/// known once rendered.
+pub fn has_placeholders(text: &str) -> bool {
+    parse(text)
+        .iter()
+        .any(|piece| matches!(piece, Piece::Placeholder(..)))
+}
+
+/// Replaces the placeholders in `text`. Unknown ones are left as they are;
+/// `{{` and `}}` stand for literal braces.
+pub fn render(text: &str, context: &Context) -> String {
+    parse(text)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text,
+            Piece::Placeholder(name, arg) => resolve(name, arg, context).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// A copy of `config` with the text fields rendered and brought within
+/// Discord's length limits.
+pub fn render_config(config: &Config, context: &Context) -> Config {
+    let mut rendered = config.clone();
+    for field in [
+        &mut rendered.details,
+        &mut rendered.state,
+        &mut rendered.large_text,
+        &mut rendered.small_text,
+    ] {
+        if has_placeholders(field) {
+            *field = fit(&render(field, context));
+        }
+    }
+    rendered
+}
+
+/// Truncates `text` to `TEXT_MAX` characters, ending it with an ellipsis,
+/// and pads text that is too short for Discord to accept. Empty text stays
+/// empty, since empty fields are left out of the activity.
+pub fn fit(text: &str) -> String {
+    let text = text.trim();
+    let len = text.chars().count();
+    if len > validation::TEXT_MAX {
+        let kept: String = text.chars().take(validation::TEXT_MAX - 1).collect();
+        format!("{}…", kept.trim_end())
+    } else if len > 0 && len < validation::TEXT_MIN {
+        // A zero-width space keeps the text looking the same.
+        format!("{}{}", text, "\u{200b}".repeat(validation::TEXT_MIN - len))
+    } else {
+        text.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Text(String),
+    Placeholder(&'a str, Option<&'a str>),
+}
+
+fn parse(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            literal.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+        if c == '{' {
+            if let Some(end) = rest.find('}') {
+                let inner = &rest[1..end];
+                let (name, arg) = match inner.split_once(':') {
+                    Some((name, arg)) => (name, Some(arg)),
+                    None => (inner, None),
+                };
+                if is_known(name, arg) {
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Placeholder(name, arg));
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        literal.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Text(literal));
+    }
+    pieces
+}
+
+fn is_known(name: &str, arg: Option<&str>) -> bool {
+    match (name, arg) {
+        ("hostname" | "user" | "uptime" | "profile", None) => true,
+        ("title" | "artist" | "album", None) => true,
+        ("project" | "branch" | "command", None) => true,
+        ("env", Some(var)) => !var.is_empty(),
+        ("time" | "date", None) => true,
+        ("time" | "date", Some(format)) => is_valid_format(format),
+        _ => sysstats::is_placeholder(name, arg),
+    }
+}
+
+/// chrono panics on invalid format strings, so they are checked up front.
+fn is_valid_format(format: &str) -> bool {
+    !format.is_empty() && !StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+fn resolve(name: &str, arg: Option<&str>, context: &Context) -> Option<String> {
+    Some(match name {
+        "hostname" => context.hostname.clone(),
+        "user" => context.user.clone(),
+        "profile" => context.profile.clone(),
+        "uptime" => format_duration(context.uptime),
+        "title" => context.track.as_ref()?.title.clone(),
+        "artist" => context.track.as_ref()?.artist.clone(),
+        "album" => context.track.as_ref()?.album.clone(),
+        "project" => context.shell.project.clone()?,
+        "branch" => context.shell.branch.clone()?,
+        "command" => context.shell.command.clone()?,
+        "env" => context.env.get(arg?).cloned().unwrap_or_default(),
+        "time" => context
+            .now
+            .format(arg.unwrap_or(DEFAULT_TIME_FORMAT))
+            .to_string(),
+        "date" => context
+            .now
+            .format(arg.unwrap_or(DEFAULT_DATE_FORMAT))
+            .to_string(),
+        _ if sysstats::is_placeholder(name, arg) => context
+            .stats
+            .get_or_init(sysstats::shared)
+            .format(name, arg),
+        _ => return None,
+    })
+}
+
+/// A coarse duration such as `2d 3h`, `3h 12m` or `12m`.
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context() -> Context {
+        Context {
+            profile: "work".to_string(),
+            hostname: "devbox".to_string(),
+            user: "sam".to_string(),
+            now: Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 0).unwrap(),
+            uptime: Duration::from_secs(3 * 3600 + 12 * 60 + 30),
+            stats: OnceCell::from(Stats {
+                cpu: Some(34.2),
+                memory: Some(sysstats::Memory {
+                    used: 13_000_000_000,
+                    total: 34_359_738_368,
+                }),
+                ..Stats::default()
+            }),
+            track: Some(Track {
+                title: "Roygbiv".to_string(),
+                artist: "Boards of Canada".to_string(),
+                album: String::new(),
+                started: 1_700_000_000,
+                ends: None,
+                player: "mpd".to_string(),
+            }),
+            shell: shell::Status {
+                project: Some("dstatus-rs".to_string()),
+                branch: Some("main".to_string()),
+                command: None,
+            },
+            env: HashMap::from([("DSTATUS_TEMPLATE_TEST".to_string(), "rust".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let context = context();
+        assert_eq!(
+            render("{user}@{hostname} ({profile}), up {uptime}", &context),
+            "sam@devbox (work), up 3h 12m"
+        );
+        assert_eq!(render("{date} {time}", &context), "2024-03-09 14:05");
+        assert_eq!(render("{time:%I:%M %p}", &context), "02:05 PM");
+        assert_eq!(
+            render("Writing {env:DSTATUS_TEMPLATE_TEST}", &context),
+            "Writing rust"
+        );
+        assert_eq!(render("{env:DSTATUS_TEMPLATE_UNSET}", &context), "");
+        assert_eq!(
+            render("CPU {cpu} · RAM {mem}{battery}", &context),
+            "CPU 34% · RAM 12.1/32 GB"
+        );
+        assert_eq!(
+            render("{title} · {artist}{album}", &context),
+            "Roygbiv · Boards of Canada"
+        );
+        assert_eq!(
+            render("{project} ({branch}) {command}", &context),
+            "dstatus-rs (main) "
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholders_are_kept() {
+        let context = context();
+        assert_eq!(render("{nope} {time:%Q} {", &context), "{nope} {time:%Q} {");
+        assert_eq!(render("{{user}} {{{user}}}", &context), "{user} {sam}");
+        assert!(!has_placeholders("{nope} {{user}}"));
+        assert!(has_placeholders("on {hostname}"));
+    }
+
+    #[test]
+    fn test_fit() {
+        let long = "x".repeat(validation::TEXT_MAX + 10);
+        let fitted = fit(&long);
+        assert_eq!(fitted.chars().count(), validation::TEXT_MAX);
+        assert!(fitted.ends_with('…'));
+        assert_eq!(fit("a").chars().count(), validation::TEXT_MIN);
+        assert_eq!(fit("  "), "");
+        assert_eq!(fit("ok"), "ok");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(59)), "0m");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 86400 + 3 * 3600)),
+            "2d 3h"
+        );
+    }
+}
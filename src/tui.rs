@@ -1,6 +1,8 @@
 use crate::config::{Button, Config};
+use crate::rotation::Order;
+use crate::validation::{self, FieldIssue};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -8,6 +10,17 @@ use ratatui::{prelude::*, widgets::*};
 use std::io;
 use unicode_width::UnicodeWidthStr;
 
+/// The configuration keys of the text fields, in the order they are shown.
+const FIELDS: [&str; 7] = [
+    "client_id",
+    "details",
+    "state",
+    "large_image",
+    "large_text",
+    "small_image",
+    "small_text",
+];
+
 struct App {
     config: Config,
     current_field: usize,
@@ -26,7 +39,9 @@ impl App {
     }
 }
 
-pub fn run_tui(config: Config) -> io::Result<Config> {
+/// Lets the user edit `config`. Returns `None` when they quit without
+/// saving.
+pub fn run_tui(config: Config) -> io::Result<Option<Config>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -34,6 +49,7 @@ pub fn run_tui(config: Config) -> io::Result<Config> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut app = App::new(config.clone());
+    let mut saved = true;
 
     loop {
         terminal.draw(|f| ui(f, &mut app))?;
@@ -42,23 +58,29 @@ pub fn run_tui(config: Config) -> io::Result<Config> {
             let num_buttons = app.config.buttons.as_ref().map_or(0, |b| b.len());
             let total_navigable_items = 8 + num_buttons;
 
+            // Raw mode delivers Ctrl-C as a key rather than a signal.
+            if key.modifiers.contains(KeyModifiers::CONTROL)
+                && matches!(key.code, KeyCode::Char('c') | KeyCode::Char('q'))
+            {
+                saved = false;
+                break;
+            }
+
             match key.code {
                 KeyCode::Esc => {
                     if app.editing_button.is_some() {
                         app.editing_button = None;
                     } else {
-                        if let Some(buttons) = &app.config.buttons {
-                            for button in buttons {
-                                if button.label.is_empty() || button.url.is_empty() {
-                                    app.error_message =
-                                        Some("Button labels and URLs cannot be empty.".to_string());
-                                    continue;
-                                }
-                            }
-                        }
-                        if app.error_message.is_none() {
+                        // Only errors the form can fix keep the user here.
+                        let errors: Vec<FieldIssue> = validation::validate(&app.config)
+                            .errors
+                            .into_iter()
+                            .filter(|issue| is_editable(&app.config, issue))
+                            .collect();
+                        if errors.is_empty() {
                             break;
                         }
+                        app.error_message = Some(validation::format_issues(&errors));
                     }
                 }
                 KeyCode::Tab => {
@@ -136,7 +158,31 @@ pub fn run_tui(config: Config) -> io::Result<Config> {
     )?;
     terminal.show_cursor()?;
 
-    Ok(app.config)
+    Ok(saved.then_some(app.config))
+}
+
+/// Whether the form can fix `issue`.
+fn is_editable(config: &Config, issue: &FieldIssue) -> bool {
+    let field = issue.field.as_str();
+    field == "buttons"
+        || field.starts_with("buttons[")
+        || (FIELDS.contains(&field) && !config.sources.contains_key(field))
+}
+
+/// Fields filled from `sources` are written back as their source on save,
+/// so typing into them would be lost.
+fn is_sourced(app: &App) -> bool {
+    app.editing_button.is_none()
+        && FIELDS
+            .get(app.current_field)
+            .is_some_and(|field| app.config.sources.contains_key(*field))
+}
+
+fn read_only_message(app: &App) -> String {
+    format!(
+        "{} comes from sources; edit it in the configuration file",
+        FIELDS[app.current_field]
+    )
 }
 
 fn get_selected_button_index(app: &App) -> Option<usize> {
@@ -173,7 +219,9 @@ fn delete_button_at_index(app: &mut App, index: usize) {
 }
 
 fn handle_char_input(app: &mut App, c: char) {
-    if let Some((button_index, field_index)) = app.editing_button {
+    if is_sourced(app) {
+        app.error_message = Some(read_only_message(app));
+    } else if let Some((button_index, field_index)) = app.editing_button {
         let button = &mut app.config.buttons.as_mut().unwrap()[button_index];
         if field_index == 0 {
             button.label.push(c);
@@ -195,7 +243,9 @@ fn handle_char_input(app: &mut App, c: char) {
 }
 
 fn handle_backspace(app: &mut App) {
-    if let Some((button_index, field_index)) = app.editing_button {
+    if is_sourced(app) {
+        app.error_message = Some(read_only_message(app));
+    } else if let Some((button_index, field_index)) = app.editing_button {
         let button = &mut app.config.buttons.as_mut().unwrap()[button_index];
         if field_index == 0 {
             button.label.pop();
@@ -217,6 +267,7 @@ fn handle_backspace(app: &mut App) {
 }
 
 fn ui(f: &mut Frame, app: &mut App) {
+    let slides = app.config.rotation.as_ref().map_or(0, |r| r.slides.len());
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
@@ -231,6 +282,7 @@ fn ui(f: &mut Frame, app: &mut App) {
                 Constraint::Length(3), // Small Text
                 Constraint::Length(3), // Add Button
                 Constraint::Min(3),    // Buttons
+                Constraint::Length(if slides > 0 { slides as u16 + 2 } else { 0 }), // Rotation
                 Constraint::Length(1), // Instructions
             ]
             .as_ref(),
@@ -239,7 +291,7 @@ fn ui(f: &mut Frame, app: &mut App) {
 
     if let Some(error) = &app.error_message {
         let error_p = Paragraph::new(error.as_str()).style(Style::default().fg(Color::Red));
-        f.render_widget(error_p, chunks[9]);
+        f.render_widget(error_p, chunks[10]);
         return;
     }
 
@@ -254,13 +306,21 @@ fn ui(f: &mut Frame, app: &mut App) {
     ];
 
     for (i, (title, value)) in fields.iter().enumerate() {
+        let sourced = app.config.sources.contains_key(FIELDS[i]);
+        let title = if sourced {
+            format!("{} (from sources, read-only)", title)
+        } else {
+            title.to_string()
+        };
         let input = Paragraph::new(value.as_str())
             .style(if app.current_field == i && app.editing_button.is_none() {
                 Style::default().fg(Color::Yellow)
+            } else if sourced {
+                Style::default().fg(Color::DarkGray)
             } else {
                 Style::default()
             })
-            .block(Block::default().borders(Borders::ALL).title(*title));
+            .block(Block::default().borders(Borders::ALL).title(title));
         f.render_widget(input, chunks[i]);
 
         if app.current_field == i && app.editing_button.is_none() {
@@ -271,6 +331,27 @@ fn ui(f: &mut Frame, app: &mut App) {
         }
     }
 
+    if let Some(rotation) = app.config.rotation.as_ref().filter(|_| slides > 0) {
+        let title = format!(
+            "Rotation (every {}, {})",
+            rotation.interval.as_deref().unwrap_or("60s"),
+            match rotation.order {
+                Order::Sequential => "in order",
+                Order::Random => "at random",
+            }
+        );
+        let lines: Vec<Line> = rotation
+            .slides
+            .iter()
+            .enumerate()
+            .map(|(i, slide)| Line::from(format!("{}. {}", i + 1, slide.summary(&app.config))))
+            .collect();
+        let rotation_p = Paragraph::new(lines)
+            .style(Style::default().fg(Color::DarkGray))
+            .block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(rotation_p, chunks[9]);
+    }
+
     let add_button = Paragraph::new("Add Button")
         .style(if app.current_field == 7 {
             Style::default().fg(Color::Yellow)
@@ -353,7 +434,7 @@ fn ui(f: &mut Frame, app: &mut App) {
     }
 
     let instructions =
-        Paragraph::new("Tab: Navigate | Enter: Edit/Add | Del: Delete | Backspace: Delete Char/Button | Esc: Save & Exit")
+        Paragraph::new("Tab: Navigate | Enter: Edit/Add | Del: Delete | Backspace: Delete Char/Button | Esc: Save & Exit | Ctrl-C: Quit Without Saving")
             .style(Style::default().fg(Color::DarkGray));
-    f.render_widget(instructions, chunks[9]);
+    f.render_widget(instructions, chunks[10]);
 }
@@ -0,0 +1,354 @@
+use crate::config::{Button, Config};
+use crate::media::Media;
+use crate::rotation::{self, Rotation, Slide};
+use crate::source;
+use crate::template;
+use serde::Serialize;
+
+/// Limits Discord enforces on a Rich Presence activity. Activities that
+/// exceed them are dropped or rejected without any feedback.
+pub const TEXT_MIN: usize = 2;
+pub const TEXT_MAX: usize = 128;
+pub const IMAGE_KEY_MAX: usize = 256;
+pub const BUTTON_LABEL_MAX: usize = 32;
+pub const BUTTON_URL_MAX: usize = 512;
+pub const MAX_BUTTONS: usize = 2;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldIssue {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Report {
+    pub errors: Vec<FieldIssue>,
+    pub warnings: Vec<FieldIssue>,
+}
+
+impl Report {
+    fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldIssue {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.warnings.push(FieldIssue {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub fn format_issues(issues: &[FieldIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("{}: {}", issue.field, issue.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Checks a configuration against Discord's activity limits.
+pub fn validate(config: &Config) -> Report {
+    let mut report = Report::default();
+
+    validate_client_id(&config.client_id, &mut report);
+
+    for (field, value) in [
+        ("details", &config.details),
+        ("state", &config.state),
+        ("large_text", &config.large_text),
+        ("small_text", &config.small_text),
+    ] {
+        // Command output is fitted to the limits when it arrives.
+        if !config.sources.contains_key(field) {
+            validate_text(field, value, &mut report);
+        }
+    }
+    for (field, source) in &config.sources {
+        if !source::TEXT_FIELDS.contains(&field.as_str()) {
+            report.error(
+                format!("sources.{}", field),
+                "only text fields can come from a command",
+            );
+        } else if let Err(message) = source.check() {
+            report.error(field.as_str(), message);
+        }
+    }
+
+    for (field, value) in [
+        ("large_image", &config.large_image),
+        ("small_image", &config.small_image),
+    ] {
+        if value.chars().count() > IMAGE_KEY_MAX {
+            report.error(
+                field,
+                format!("must be at most {} characters", IMAGE_KEY_MAX),
+            );
+        }
+    }
+    if config.large_image.is_empty() && !config.large_text.is_empty() {
+        report.warning("large_text", "is only shown when large_image is set");
+    }
+    if config.small_image.is_empty() && !config.small_text.is_empty() {
+        report.warning("small_text", "is only shown when small_image is set");
+    }
+
+    validate_party(config, &mut report);
+
+    if let (Some(start), Some(end)) = (config.start_timestamp, config.end_timestamp) {
+        if end < start {
+            report.error("end_timestamp", "must not be earlier than start_timestamp");
+        }
+    }
+
+    if let Some(buttons) = &config.buttons {
+        validate_buttons("buttons", buttons, &mut report);
+    }
+
+    if let Some(rotation) = &config.rotation {
+        validate_rotation(rotation, &mut report);
+    }
+
+    if let Some(media) = &config.media {
+        validate_media(media, &mut report);
+    }
+
+    report
+}
+
+fn validate_client_id(client_id: &str, report: &mut Report) {
+    if client_id.is_empty() {
+        report.error("client_id", "is required");
+    } else if !client_id.chars().all(|c| c.is_ascii_digit()) {
+        report.error("client_id", "must be a numeric Discord application ID");
+    } else if !(17..=20).contains(&client_id.len()) || client_id.parse::<u64>().is_err() {
+        report.error("client_id", "is not a valid Discord snowflake");
+    }
+}
+
+fn validate_text(field: &str, value: &str, report: &mut Report) {
+    // Empty text fields are omitted from the activity rather than sent;
+    // text with placeholders is fitted to the limits once rendered.
+    if value.is_empty() || template::has_placeholders(value) {
+        return;
+    }
+    let len = value.chars().count();
+    if !(TEXT_MIN..=TEXT_MAX).contains(&len) {
+        report.error(
+            field,
+            format!(
+                "must be between {} and {} characters (got {})",
+                TEXT_MIN, TEXT_MAX, len
+            ),
+        );
+    }
+}
+
+fn validate_buttons(field: &str, buttons: &[Button], report: &mut Report) {
+    if buttons.len() > MAX_BUTTONS {
+        report.warning(
+            field,
+            format!(
+                "Discord shows at most {} buttons; the rest are ignored",
+                MAX_BUTTONS
+            ),
+        );
+    }
+    for (i, button) in buttons.iter().enumerate() {
+        let label_len = button.label.chars().count();
+        if label_len == 0 || label_len > BUTTON_LABEL_MAX {
+            report.error(
+                format!("{}[{}].label", field, i),
+                format!("must be between 1 and {} characters", BUTTON_LABEL_MAX),
+            );
+        }
+        let url_len = button.url.chars().count();
+        if url_len == 0 || url_len > BUTTON_URL_MAX {
+            report.error(
+                format!("{}[{}].url", field, i),
+                format!("must be between 1 and {} characters", BUTTON_URL_MAX),
+            );
+        } else if !button.url.starts_with("https://") && !button.url.starts_with("http://") {
+            report.error(
+                format!("{}[{}].url", field, i),
+                "must start with http:// or https://",
+            );
+        }
+    }
+}
+
+fn validate_rotation(rotation: &Rotation, report: &mut Report) {
+    match rotation.interval() {
+        Ok(interval) if interval < rotation::MIN_INTERVAL => report.error(
+            "rotation.interval",
+            format!(
+                "must be at least {:?}; Discord ignores faster updates",
+                rotation::MIN_INTERVAL
+            ),
+        ),
+        Ok(_) => {}
+        Err(e) => report.error("rotation.interval", e.to_string()),
+    }
+    if rotation.slides.is_empty() {
+        report.warning("rotation.slides", "is empty, so nothing rotates");
+    }
+    for (i, slide) in rotation.slides.iter().enumerate() {
+        validate_slide(&format!("rotation.slides[{}]", i), slide, report);
+    }
+}
+
+fn validate_slide(prefix: &str, slide: &Slide, report: &mut Report) {
+    for (field, value) in slide.texts() {
+        if let Some(value) = value {
+            validate_text(&format!("{}.{}", prefix, field), value, report);
+        }
+    }
+    for (field, value) in [
+        ("large_image", &slide.large_image),
+        ("small_image", &slide.small_image),
+    ] {
+        if value
+            .as_ref()
+            .is_some_and(|v| v.chars().count() > IMAGE_KEY_MAX)
+        {
+            report.error(
+                format!("{}.{}", prefix, field),
+                format!("must be at most {} characters", IMAGE_KEY_MAX),
+            );
+        }
+    }
+    if let Some(buttons) = &slide.buttons {
+        validate_buttons(&format!("{}.buttons", prefix), buttons, report);
+    }
+}
+
+fn validate_media(media: &Media, report: &mut Report) {
+    match media.mpd.as_deref() {
+        Some("") => report.error("media.mpd", "must not be empty"),
+        None if media.mpris == Some(false) => {
+            report.warning("media", "follows no player; set mpd or leave mpris on")
+        }
+        _ => {}
+    }
+    if let Some(set) = &media.set {
+        validate_slide("media.set", set, report);
+    }
+}
+
+fn validate_party(config: &Config, report: &mut Report) {
+    if config.party_size < 0 {
+        report.error("party_size", "must not be negative");
+    }
+    if config.max_party_size < 0 {
+        report.error("max_party_size", "must not be negative");
+    }
+    if config.max_party_size > 0 && config.party_size > config.max_party_size {
+        report.error("party_size", "must not exceed max_party_size");
+    }
+    if config.party_size > 0 && config.max_party_size == 0 {
+        report.warning("max_party_size", "is 0, so the party size is not shown");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotation::Slide;
+
+    fn valid_config() -> Config {
+        Config {
+            client_id: "1234567890123456789".to_string(),
+            details: "Coding".to_string(),
+            state: "In a meeting".to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_config_has_no_issues() {
+        let report = validate(&valid_config());
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_text_length_limits() {
+        let mut config = valid_config();
+        config.details = "x".to_string();
+        config.state = "y".repeat(TEXT_MAX + 1);
+        let fields: Vec<String> = validate(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(fields, vec!["details", "state"]);
+    }
+
+    #[test]
+    fn test_client_id_must_be_snowflake() {
+        let mut config = valid_config();
+        config.client_id = "my-app".to_string();
+        assert!(!validate(&config).is_valid());
+        config.client_id = "123".to_string();
+        assert!(!validate(&config).is_valid());
+    }
+
+    #[test]
+    fn test_button_limits() {
+        let mut config = valid_config();
+        let button = Button {
+            label: "l".repeat(BUTTON_LABEL_MAX + 1),
+            url: "ftp://example.com".to_string(),
+        };
+        config.buttons = Some(vec![button.clone(), button.clone(), button]);
+        let report = validate(&config);
+        assert_eq!(report.warnings[0].field, "buttons");
+        assert!(report.errors.iter().any(|e| e.field == "buttons[0].label"));
+        assert!(report.errors.iter().any(|e| e.field == "buttons[0].url"));
+    }
+
+    #[test]
+    fn test_party_size_cannot_exceed_max() {
+        let mut config = valid_config();
+        config.party_size = 5;
+        config.max_party_size = 4;
+        assert_eq!(validate(&config).errors[0].field, "party_size");
+    }
+
+    #[test]
+    fn test_rotation_limits() {
+        let mut config = valid_config();
+        config.rotation = Some(Rotation {
+            interval: Some("5s".to_string()),
+            slides: vec![Slide {
+                details: Some("x".to_string()),
+                buttons: Some(vec![Button {
+                    label: "Docs".to_string(),
+                    url: "docs.rs".to_string(),
+                }]),
+                ..Slide::default()
+            }],
+            ..Rotation::default()
+        });
+        let fields: Vec<String> = validate(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "rotation.interval",
+                "rotation.slides[0].details",
+                "rotation.slides[0].buttons[0].url"
+            ]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::error::{Error, Result};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+/// How long the files must stay quiet before a change is reported. Editors
+/// often save in several steps (write a temp file, rename, chmod).
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// What changes when a file is written, replaced or removed: its
+/// modification time, size and inode, or that it is gone.
+type Stamp = Option<(SystemTime, u64, u64)>;
+
+/// Reports changes to a set of files. The parent directories are watched
+/// rather than the files themselves, so saves that replace a file by
+/// renaming another over it are seen too.
+pub struct FileWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+    /// The watched files, with how they looked when last reported.
+    files: Arc<Mutex<HashMap<PathBuf, Stamp>>>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Calls `on_change` with the changed files, from a background thread.
+    pub fn new(on_change: impl Fn(Vec<PathBuf>) + Send + 'static) -> Result<Self> {
+        let files: Arc<Mutex<HashMap<PathBuf, Stamp>>> = Arc::default();
+        let watched = Arc::clone(&files);
+        let debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let mut changed: Vec<PathBuf> = events
+                    .into_iter()
+                    .map(|event| normalize(&event.path))
+                    .collect();
+                changed.sort();
+                changed.dedup();
+                // Opening a file raises events too, and the daemon reads
+                // the files it is told about; only real changes count.
+                let mut watched = watched.lock().unwrap();
+                changed.retain(|path| match watched.get_mut(path) {
+                    Some(seen) => {
+                        let now = stamp(path);
+                        std::mem::replace(seen, now) != now
+                    }
+                    None => false,
+                });
+                drop(watched);
+                if !changed.is_empty() {
+                    on_change(changed);
+                }
+            }
+            Err(e) => warn!("File watcher error: {}", e),
+        })
+        .map_err(|e| Error::Other(format!("Failed to start file watcher: {}", e)))?;
+
+        Ok(Self {
+            debouncer,
+            files,
+            dirs: HashSet::new(),
+        })
+    }
+
+    /// Replaces the set of watched files. A directory that can't be watched
+    /// (one that doesn't exist, say) is skipped with a warning; the other
+    /// files are still watched.
+    pub fn watch(&mut self, files: &[PathBuf]) {
+        // Files watched already keep their stamp, so that a change made
+        // since it was taken is still reported.
+        let seen = self.files.lock().unwrap().clone();
+        let files: HashMap<PathBuf, Stamp> = files
+            .iter()
+            .map(|file| {
+                let file = normalize(file);
+                let stamp = seen.get(&file).copied().unwrap_or_else(|| stamp(&file));
+                (file, stamp)
+            })
+            .collect();
+        let dirs: HashSet<PathBuf> = files
+            .keys()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect();
+
+        let watcher = self.debouncer.watcher();
+        for dir in self.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        let mut watched = HashSet::new();
+        for dir in dirs {
+            if !self.dirs.contains(&dir) {
+                debug!("Watching {:?}", dir);
+                if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                    warn!("Not watching {:?} for changes: {}", dir, e);
+                    continue;
+                }
+            }
+            watched.insert(dir);
+        }
+
+        self.dirs = watched;
+        *self.files.lock().unwrap() = files;
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len(), metadata.ino()))
+}
+
+/// Makes paths from the caller and from the OS comparable. Only the
+/// directory is canonicalized, since the file may be briefly missing in
+/// the middle of a save.
+pub fn normalize(path: &Path) -> PathBuf {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match (fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reports_only_watched_files() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("configuration.toml");
+        fs::write(&config, "state = \"one\"\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = FileWatcher::new(move |files| tx.send(files).unwrap()).unwrap();
+        watcher.watch(std::slice::from_ref(&config));
+
+        fs::write(dir.path().join("unrelated.txt"), "x").unwrap();
+        crate::history::atomic_write(&config, "state = \"two\"\n").unwrap();
+
+        let changed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changed, vec![normalize(&config)]);
+    }
+
+    #[test]
+    fn test_reading_is_not_a_change() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("configuration.toml");
+        fs::write(&config, "state = \"one\"\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = FileWatcher::new(move |files| tx.send(files).unwrap()).unwrap();
+        watcher.watch(std::slice::from_ref(&config));
+
+        fs::read_to_string(&config).unwrap();
+        assert!(rx.recv_timeout(DEBOUNCE * 3).is_err());
+        fs::write(&config, "state = \"two, longer\"\n").unwrap();
+        let changed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changed, vec![normalize(&config)]);
+    }
+
+    #[test]
+    fn test_skips_directories_that_cannot_be_watched() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("configuration.toml");
+        let base = dir.path().join("missing").join("base.toml");
+        fs::write(&config, "state = \"one\"\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = FileWatcher::new(move |files| tx.send(files).unwrap()).unwrap();
+        watcher.watch(&[base, config.clone()]);
+        assert_eq!(
+            watcher.dirs,
+            HashSet::from([normalize(&config).parent().unwrap().to_path_buf()])
+        );
+
+        crate::history::atomic_write(&config, "state = \"two\"\n").unwrap();
+        let changed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changed, vec![normalize(&config)]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::Config;
+use crate::control::{self, Hold};
+use crate::error::{Error, Result};
+use crate::paths::Paths;
+use crate::rich_presence::RichPresence;
+use crate::rotation::Slide;
+use crate::template;
+use chrono::Utc;
+use signal_hook::consts::SIGINT;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Options {
+    /// Fields shown while the command runs.
+    pub activity: Slide,
+    /// Whether to show how the command exited, for `linger`.
+    pub show_status: bool,
+    pub linger: Duration,
+    pub command: Vec<String>,
+}
+
+/// Where the activity is shown.
+enum Shown {
+    /// Held by the running daemon until the connection closes.
+    Daemon(control::Client),
+    /// On a Discord connection of this process's own.
+    Own(Box<RichPresence>),
+    /// Discord could not be reached; the command runs all the same.
+    Nowhere,
+}
+
+impl Shown {
+    fn connect(paths: &Paths) -> Self {
+        match control::Client::connect(&paths.control_socket()) {
+            Ok(Some(client)) => return Self::Daemon(client),
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+        let started = paths
+            .config_file()
+            .and_then(|path| {
+                let path = path
+                    .to_str()
+                    .ok_or_else(|| Error::Config(format!("Path {:?} is not valid UTF-8", path)))?;
+                Config::from_file(path)
+            })
+            .and_then(|config| {
+                let mut presence = RichPresence::new(config);
+                presence.start().map_err(Error::ipc)?;
+                Ok(presence)
+            });
+        match started {
+            Ok(presence) => Self::Own(Box::new(presence)),
+            Err(e) => {
+                eprintln!("Warning: not showing the command in Discord: {}", e);
+                Self::Nowhere
+            }
+        }
+    }
+
+    fn show(&mut self, hold: &Hold) {
+        let result = match self {
+            Self::Daemon(client) => client.hold(hold),
+            Self::Own(presence) => {
+                presence.set_held(Some(hold.clone()));
+                presence.set_activity().map_err(Error::ipc)
+            }
+            Self::Nowhere => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: failed to update the presence: {}", e);
+        }
+    }
+
+    /// Gives the presence back: the daemon goes back to what it showed
+    /// before, and a presence of our own is cleared.
+    fn release(self) {
+        if let Self::Own(mut presence) = self {
+            presence.set_held(None);
+            presence.set_cleared(true);
+            let _ = presence.set_activity();
+        }
+    }
+}
+
+/// Runs `options.command`, showing `options.activity` with an elapsed timer
+/// while it runs. Goes through the daemon when one is running. Returns the
+/// code to exit with, which is the command's own.
+pub fn run(paths: &Paths, options: Options) -> Result<i32> {
+    let (program, args) = options
+        .command
+        .split_first()
+        .ok_or_else(|| Error::Other("No command to run".to_string()))?;
+    let mut shown = Shown::connect(paths);
+
+    // Ctrl-C reaches the command, which decides what to do with it; this
+    // process stays to report how it exited.
+    signal_hook::flag::register(SIGINT, Arc::new(AtomicBool::new(false)))
+        .map_err(|e| Error::io("Failed to install signal handler", e))?;
+    let started = Instant::now();
+    let mut child = Command::new(program)
+        .args(args)
+        .spawn()
+        .map_err(|e| Error::io(format!("Failed to run '{}'", program), e))?;
+    shown.show(&Hold {
+        activity: options.activity.clone(),
+        start_timestamp: Some(Utc::now().timestamp()),
+        pid: Some(child.id()),
+    });
+    let status = child
+        .wait()
+        .map_err(|e| Error::io(format!("Failed to wait for '{}'", program), e))?;
+
+    if options.show_status {
+        shown.show(&Hold {
+            activity: Slide {
+                state: Some(template::fit(&describe_exit(status, started.elapsed()))),
+                ..options.activity
+            },
+            start_timestamp: None,
+            pid: None,
+        });
+        thread::sleep(options.linger);
+    }
+    shown.release();
+    Ok(exit_code(status))
+}
+
+/// The first line of the activity when none was given: the command line.
+pub fn default_details(command: &[String]) -> String {
+    template::fit(&command.join(" "))
+}
+
+fn describe_exit(status: ExitStatus, elapsed: Duration) -> String {
+    let took = format_elapsed(elapsed);
+    match (status.code(), status.signal()) {
+        (Some(0), _) => format!("Finished after {}", took),
+        (Some(code), _) => format!("Failed with status {} after {}", code, took),
+        (None, Some(signal)) => format!("Killed by signal {} after {}", signal, took),
+        (None, None) => format!("Stopped after {}", took),
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// The shell's convention: the command's own code, or 128 plus the signal
+/// that killed it.
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_is_described() {
+        let minute = Duration::from_secs(83);
+        assert_eq!(
+            describe_exit(ExitStatus::from_raw(0), minute),
+            "Finished after 1m 23s"
+        );
+        let failed = ExitStatus::from_raw(2 << 8);
+        assert_eq!(
+            describe_exit(failed, Duration::from_secs(5)),
+            "Failed with status 2 after 5s"
+        );
+        assert_eq!(exit_code(failed), 2);
+        let killed = ExitStatus::from_raw(9);
+        assert_eq!(
+            describe_exit(killed, Duration::from_secs(7300)),
+            "Killed by signal 9 after 2h 1m"
+        );
+        assert_eq!(exit_code(killed), 137);
+    }
+}